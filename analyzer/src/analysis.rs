@@ -0,0 +1,393 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use common::*;
+
+use crate::{
+    attack::{self, TechniqueUsage},
+    elf::ElfInfo,
+    integrity::Integrity,
+    ioc::{Ioc, IocCollector},
+    logsource::{self, LogFormat},
+    report::{self, Report},
+    rule::{self, Correlation, DetectionInfo, RuleEngine, UserRule},
+    scope::{Detonation, Scope},
+    score::{ThreatScore, Thresholds},
+    stats::ParseStats,
+    syscall::StraceParser,
+    syslog::SyslogEntry,
+    yara::{YaraMatch, YaraScanner},
+};
+
+#[derive(Debug)]
+pub struct Options {
+    pub log_format: LogFormat, // of the sysmon log, the other collectors have their own
+    pub include_all: bool,
+    pub follow: bool,
+    pub strict: bool,
+    pub keep_entries: bool, // only needed for the HTML report
+    pub failure_samples: usize,
+    pub user_rules: Vec<UserRule>,
+    pub yara_rules: Option<String>,
+    pub thresholds: Thresholds,
+}
+
+// reported while the result directory is analyzed, e.g. to print detections as they occur
+pub enum Progress<'a> {
+    Integrity(&'a Integrity),
+    Scope(Option<&'a Detonation>),
+    Log(&'a str, LogFormat),
+    SyscallLog(&'a str),
+    Detection(&'a DetectionInfo),
+}
+
+// the analysis of one sandbox result directory
+pub struct Analysis {
+    pub target_root_dir: String,
+    pub run_name: String,
+    pub integrity: Integrity,
+    pub marker: Option<Detonation>, // also read with include_all, for the run id and the times
+    pub entries: Vec<SyslogEntry>,
+    pub parse_stats: ParseStats,
+    pub yara_matches: Vec<YaraMatch>,
+    pub detection_info: Vec<DetectionInfo>,
+    pub correlations: Vec<Correlation>,
+    pub threat_score: ThreatScore,
+    pub techniques: Vec<TechniqueUsage>,
+    pub iocs: Vec<(Ioc, DateTime<FixedOffset>)>,
+}
+
+impl Analysis {
+    pub fn run(
+        target_root_dir: &str,
+        options: &Options,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<Self> {
+        let detonation_path = format!("{}/{}", target_root_dir, DETONATION_FILE_NAME);
+        let log_paths = logsource::find_logs(target_root_dir, options.log_format);
+        let target_elf_path = target_elf_path(target_root_dir);
+
+        // check directory
+        if log_paths.is_empty() || !Path::new(&target_elf_path).exists() {
+            bail!("Detect invalid directory");
+        }
+
+        // the manifest is written after the detonation, a followed run can not be verified yet
+        let integrity = if options.follow {
+            Integrity::Unverified
+        } else {
+            Integrity::check(target_root_dir)
+        };
+        progress(Progress::Integrity(&integrity));
+
+        // sandbox results without a detonation marker can only be analyzed as a whole
+        let detonation = if options.include_all || !Path::new(&detonation_path).exists() {
+            None
+        } else {
+            Some(
+                Detonation::from_file(&detonation_path)
+                    .context("Failed to read detonation marker")?,
+            )
+        };
+        if !options.include_all {
+            progress(Progress::Scope(detonation.as_ref()));
+        }
+
+        // entries are only kept for the HTML report, detection runs on the stream
+        let mut entries = vec![];
+        let mut parse_stats = ParseStats::new(options.failure_samples);
+        let mut rule_engine = RuleEngine::new(&options.user_rules);
+        let mut ioc_collector = IocCollector::new();
+
+        for (log_path, log_format) in &log_paths {
+            let mut log_source = if options.follow {
+                // the log is complete once the sandbox has recorded the end of the detonation
+                let detonation_path = detonation_path.clone();
+                let is_done = Box::new(move || {
+                    Detonation::from_file(&detonation_path).is_ok_and(|d| d.ended_at.is_some())
+                });
+
+                logsource::open_follow(log_path, *log_format, is_done)
+            } else {
+                logsource::open_file(log_path, *log_format)
+            }
+            .context("Failed to open log file")?;
+            progress(Progress::Log(log_path, log_source.format()));
+
+            // process guids are specific to each collector
+            let mut scope = detonation.clone().map(Scope::new);
+
+            while let Some(record) = log_source
+                .next_record()
+                .context("Failed to read log file")?
+            {
+                parse_stats.add_record(record.payload.is_some());
+
+                let payload = match &record.payload {
+                    Some(payload) => payload,
+                    None => continue,
+                };
+
+                match SyslogEntry::parse(&record.raw, payload) {
+                    Ok(Some(entry)) => {
+                        parse_stats.add_event(&entry.sysmon_event.event_id);
+
+                        // the event is still analyzed with its raw EventData
+                        if let Some(err) = &entry.sysmon_event.data_error {
+                            if options.strict {
+                                bail!(
+                                    "Failed to parse event record #{}: {}\n{}",
+                                    parse_stats.total_records,
+                                    err,
+                                    record.raw
+                                );
+                            }
+
+                            parse_stats.add_failure(&record.raw, err);
+                        }
+
+                        if let Some(scope) = &mut scope {
+                            if !scope.contains(&entry) {
+                                parse_stats.add_out_of_scope();
+                                continue;
+                            }
+                        }
+
+                        for info in rule_engine.process(&entry) {
+                            progress(Progress::Detection(info));
+                        }
+                        ioc_collector.add_event(&entry);
+
+                        if options.keep_entries {
+                            entries.push(entry);
+                        }
+                    }
+                    Ok(None) => (),
+                    Err(err) => {
+                        if options.strict {
+                            bail!(
+                                "Failed to parse event record #{}: {:#}\n{}",
+                                parse_stats.total_records,
+                                err,
+                                record.raw
+                            );
+                        }
+
+                        parse_stats.add_failure(&record.raw, &err);
+                    }
+                }
+            }
+        }
+
+        // written by strace, only covers the target so it is not scoped
+        let syscall_log_path = format!("{}/{}", target_root_dir, SYSCALL_LOG_FILE_NAME);
+
+        if Path::new(&syscall_log_path).exists() {
+            progress(Progress::SyscallLog(&syscall_log_path));
+
+            let mut reader =
+                logsource::open_reader(&syscall_log_path).context("Failed to open syscall log")?;
+            let mut parser = StraceParser::new();
+
+            while let Some(line) =
+                logsource::read_line(reader.as_mut()).context("Failed to read syscall log")?
+            {
+                parse_stats.add_record(true);
+
+                match parser.parse_line(&line) {
+                    Ok(Some(syscall)) => {
+                        parse_stats.add_syscall();
+                        ioc_collector.add_syscall(&syscall);
+
+                        for info in rule_engine.process_syscall(&syscall) {
+                            progress(Progress::Detection(info));
+                        }
+                    }
+                    Ok(None) => (),
+                    Err(err) => {
+                        if options.strict {
+                            bail!(
+                                "Failed to parse syscall record #{}: {:#}\n{}",
+                                parse_stats.total_records,
+                                err,
+                                line
+                            );
+                        }
+
+                        parse_stats.add_failure(&line, &err);
+                    }
+                }
+            }
+        }
+
+        // dropped files are checked by their path in the container, at the time they were collected
+        let dropped_dir = Path::new(target_root_dir).join(DROPPED_DIR_NAME);
+        let mut dropped_files = vec![];
+        report::collect_files(&dropped_dir, &mut dropped_files);
+
+        for path in &dropped_files {
+            let container_path = format!(
+                "/{}",
+                path.strip_prefix(&dropped_dir).unwrap().to_string_lossy()
+            );
+            let modified = fs::metadata(path)
+                .and_then(|m| m.modified())
+                .context("Failed to read dropped file")?;
+            ioc_collector
+                .add_file(path, &container_path, true)
+                .context("Failed to hash dropped file")?;
+
+            for info in rule_engine.process_dropped_file(
+                &container_path,
+                DateTime::<Utc>::from(modified).fixed_offset(),
+            ) {
+                progress(Progress::Detection(info));
+            }
+        }
+
+        // logs of different collectors are read one after another
+        entries.sort_by_key(|e| e.sysmon_event.time_created);
+
+        // the files are only complete once the sandbox has finished
+        let yara_matches = match &options.yara_rules {
+            Some(path) if !options.follow => YaraScanner::load(path)
+                .context("Failed to load YARA rules")?
+                .scan_result_dir(target_root_dir),
+            _ => vec![],
+        };
+
+        ioc_collector
+            .add_file(Path::new(&target_elf_path), TARGET_FILE_NAME, false)
+            .context("Failed to hash target")?;
+
+        let mut detection_info = rule_engine.finish();
+        detection_info.extend(yara_matches.iter().map(|m| m.detection()));
+        let correlations = rule::correlate(&detection_info);
+        let threat_score =
+            ThreatScore::compute(&detection_info, &correlations, &options.thresholds);
+        let techniques = attack::collect_techniques(&detection_info, &correlations);
+
+        let run_name = Path::new(target_root_dir)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or(target_root_dir.to_string());
+
+        return Ok(Self {
+            target_root_dir: target_root_dir.to_string(),
+            run_name,
+            integrity,
+            marker: Detonation::from_file(&detonation_path).ok(),
+            entries,
+            parse_stats,
+            yara_matches,
+            detection_info,
+            correlations,
+            threat_score,
+            techniques,
+            iocs: ioc_collector.finish(),
+        });
+    }
+
+    // report_path is where the HTML report is written, links to the result files are relative to it
+    pub fn report<'a>(&'a self, report_path: &'a str) -> Report<'a> {
+        return Report {
+            target_root_dir: &self.target_root_dir,
+            report_path,
+            elf_info: ElfInfo::from_file(&target_elf_path(&self.target_root_dir)).ok(),
+            entries: &self.entries,
+            detection_info: &self.detection_info,
+            threat_score: &self.threat_score,
+            techniques: &self.techniques,
+            parse_stats: &self.parse_stats,
+            integrity: &self.integrity,
+            yara_matches: &self.yara_matches,
+            iocs: &self.iocs,
+        };
+    }
+}
+
+fn target_elf_path(target_root_dir: &str) -> String {
+    return format!(
+        "{}/{}/{}",
+        target_root_dir, TARGETS_DIR_NAME, TARGET_FILE_NAME
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    // as written by rsyslog in the guest
+    const SYSLOG: &str = r#"Aug 10 12:00:01 sandbox sysmon: <Event><System><Provider Name="Linux-Sysmon" Guid="{ff032593-a8d3-4f13-b0d6-01fc615a0f97}"/><EventID>1</EventID><Version>5</Version><Level>4</Level><Task>1</Task><Opcode>0</Opcode><Keywords>0x8000000000000000</Keywords><TimeCreated SystemTime="2023-08-10T12:00:01.123456000Z"/><EventRecordID>1</EventRecordID><Correlation/><Execution ProcessID="100" ThreadID="100"/><Channel>Linux-Sysmon/Operational</Channel><Computer>sandbox</Computer><Security UserId="0"/></System><EventData><Data Name="RuleName">-</Data><Data Name="UtcTime">2023-08-10 12:00:01.123</Data><Data Name="ProcessGuid">{5bd6ab47-0001-64d4-0000-000000000000}</Data><Data Name="ProcessId">50</Data><Data Name="Image">/root/target.bin</Data><Data Name="FileVersion">-</Data><Data Name="Description">-</Data><Data Name="Product">-</Data><Data Name="Company">-</Data><Data Name="OriginalFileName">-</Data><Data Name="CommandLine">./target.bin</Data><Data Name="CurrentDirectory">/root</Data><Data Name="User">root</Data><Data Name="LogonGuid">{5bd6ab47-0000-64d4-0000-000000000000}</Data><Data Name="LogonId">0</Data><Data Name="TerminalSessionId">3</Data><Data Name="IntegrityLevel">no level</Data><Data Name="Hashes">SHA256=abcd</Data><Data Name="ParentProcessGuid">{5bd6ab47-0000-64d4-0000-000000000000}</Data><Data Name="ParentProcessId">1</Data><Data Name="ParentImage">/usr/bin/bash</Data><Data Name="ParentCommandLine">bash</Data><Data Name="ParentUser">root</Data></EventData></Event>
+"#;
+
+    fn options() -> Options {
+        return Options {
+            log_format: LogFormat::Auto,
+            include_all: true,
+            follow: false,
+            strict: true,
+            keep_entries: false,
+            failure_samples: 0,
+            user_rules: vec![],
+            yara_rules: None,
+            thresholds: Thresholds {
+                suspicious: 30,
+                malicious: 70,
+            },
+        };
+    }
+
+    #[test]
+    fn analyze_dropped_files() {
+        let dir = env::temp_dir().join(format!("elf-sandbox-analysis-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join(TARGETS_DIR_NAME)).unwrap();
+        fs::create_dir_all(dir.join(DROPPED_DIR_NAME).join("etc/cron.d")).unwrap();
+        fs::write(dir.join(SYSLOG_FILE_NAME), SYSLOG).unwrap();
+        fs::write(dir.join(TARGETS_DIR_NAME).join(TARGET_FILE_NAME), "\x7fELF").unwrap();
+        fs::write(
+            dir.join(DROPPED_DIR_NAME).join("etc/cron.d/update"),
+            "* * * * * root /tmp/.x/kworker\n",
+        )
+        .unwrap();
+
+        let analysis = Analysis::run(dir.to_str().unwrap(), &options(), &mut |_| ()).unwrap();
+
+        let cron: Vec<_> = analysis
+            .detection_info
+            .iter()
+            .filter(|d| d.code == rule::Code::Cron("/etc/cron.d/update".to_string()))
+            .collect();
+        assert_eq!(cron.len(), 1);
+        assert_eq!(
+            cron[0].reason_for_detection,
+            "Dropped cron job /etc/cron.d/update"
+        );
+
+        let files: Vec<_> = analysis
+            .iocs
+            .iter()
+            .filter_map(|(ioc, _)| match ioc {
+                Ioc::File {
+                    path,
+                    dropped,
+                    sha256,
+                    ..
+                } => Some((path.as_str(), *dropped, sha256.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert!(files.contains(&(
+            "/etc/cron.d/update",
+            true,
+            "b72f9eb5dc631f0304244ecbc6c26c17075a7454100afbaa8efc7f39fa5fb8d8"
+        )));
+        assert!(files.iter().any(|f| f.0 == TARGET_FILE_NAME && !f.1));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +0,0 @@
-use clap::Parser;
-
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-pub struct Arguments {
-    #[arg(long)]
-    pub target_root_dir: String,
-}
@@ -0,0 +1,328 @@
+use std::{collections::BTreeMap, sync::LazyLock};
+
+use regex::Regex;
+use serde_json::{json, Value};
+
+use crate::rule::{Correlation, DetectionInfo};
+
+// (technique id, name, tactic) of the techniques referenced by the built-in rules
+const TECHNIQUES: &[(&str, &str, &str)] = &[
+    (
+        "T1036.004",
+        "Masquerading: Masquerade Task or Service",
+        "defense-evasion",
+    ),
+    (
+        "T1037.004",
+        "Boot or Logon Initialization Scripts: RC Scripts",
+        "persistence",
+    ),
+    (
+        "T1046",
+        "Network Service Discovery",
+        "discovery",
+    ),
+    (
+        "T1053.003",
+        "Scheduled Task/Job: Cron",
+        "persistence",
+    ),
+    (
+        "T1070.002",
+        "Indicator Removal: Clear Linux or Mac System Logs",
+        "defense-evasion",
+    ),
+    (
+        "T1070.003",
+        "Indicator Removal: Clear Command History",
+        "defense-evasion",
+    ),
+    (
+        "T1070.004",
+        "Indicator Removal: File Deletion",
+        "defense-evasion",
+    ),
+    (
+        "T1082",
+        "System Information Discovery",
+        "discovery",
+    ),
+    (
+        "T1098.004",
+        "Account Manipulation: SSH Authorized Keys",
+        "persistence",
+    ),
+    (
+        "T1105",
+        "Ingress Tool Transfer",
+        "command-and-control",
+    ),
+    (
+        "T1110.001",
+        "Brute Force: Password Guessing",
+        "credential-access",
+    ),
+    (
+        "T1136.001",
+        "Create Account: Local Account",
+        "persistence",
+    ),
+    (
+        "T1222.002",
+        "File and Directory Permissions Modification: Linux and Mac File and Directory Permissions Modification",
+        "defense-evasion",
+    ),
+    (
+        "T1489",
+        "Service Stop",
+        "impact",
+    ),
+    (
+        "T1496",
+        "Resource Hijacking",
+        "impact",
+    ),
+    (
+        "T1497.001",
+        "Virtualization/Sandbox Evasion: System Checks",
+        "defense-evasion",
+    ),
+    (
+        "T1497.003",
+        "Virtualization/Sandbox Evasion: Time Based Evasion",
+        "defense-evasion",
+    ),
+    (
+        "T1543.002",
+        "Create or Modify System Process: Systemd Service",
+        "persistence",
+    ),
+    (
+        "T1546.004",
+        "Event Triggered Execution: Unix Shell Configuration Modification",
+        "persistence",
+    ),
+    (
+        "T1547.006",
+        "Boot or Logon Autostart Execution: Kernel Modules and Extensions",
+        "persistence",
+    ),
+    (
+        "T1562.004",
+        "Impair Defenses: Disable or Modify System Firewall",
+        "defense-evasion",
+    ),
+    (
+        "T1574.006",
+        "Hijack Execution Flow: Dynamic Linker Hijacking",
+        "persistence",
+    ),
+    (
+        "T1620",
+        "Reflective Code Loading",
+        "defense-evasion",
+    ),
+    (
+        "T1622",
+        "Debugger Evasion",
+        "defense-evasion",
+    ),
+];
+
+#[derive(Debug, Clone)]
+pub struct TechniqueUsage {
+    pub technique_id: String,
+    pub name: Option<String>,
+    pub tactic: Option<String>,
+    pub sources: Vec<String>, // rule names and correlation reasons
+    pub count: usize,
+}
+
+static TECHNIQUE_ID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^T\d{4}(\.\d{3})?$").unwrap());
+
+pub fn is_valid_technique_id(id: &str) -> bool {
+    return TECHNIQUE_ID.is_match(id);
+}
+
+pub fn lookup(technique_id: &str) -> Option<(&'static str, &'static str)> {
+    return TECHNIQUES
+        .iter()
+        .find(|(id, _, _)| *id == technique_id)
+        .map(|(_, name, tactic)| (*name, *tactic));
+}
+
+pub fn collect_techniques(
+    detection_info: &[DetectionInfo],
+    correlations: &[Correlation],
+) -> Vec<TechniqueUsage> {
+    let mut usages: BTreeMap<String, TechniqueUsage> = BTreeMap::new();
+
+    let sources = detection_info
+        .iter()
+        .map(|i| (i.code.name().to_string(), &i.techniques))
+        .chain(
+            correlations
+                .iter()
+                .map(|c| (c.reason.clone(), &c.techniques)),
+        );
+
+    for (source, techniques) in sources {
+        for technique_id in techniques {
+            let usage = usages
+                .entry(technique_id.clone())
+                .or_insert_with(|| TechniqueUsage {
+                    technique_id: technique_id.clone(),
+                    name: lookup(technique_id).map(|(name, _)| name.to_string()),
+                    tactic: lookup(technique_id).map(|(_, tactic)| tactic.to_string()),
+                    sources: vec![],
+                    count: 0,
+                });
+
+            usage.count += 1;
+            if !usage.sources.contains(&source) {
+                usage.sources.push(source.clone());
+            }
+        }
+    }
+
+    return usages.into_values().collect();
+}
+
+pub fn navigator_layer(name: &str, techniques: &[TechniqueUsage]) -> Value {
+    let max_count = techniques.iter().map(|t| t.count).max().unwrap_or(1);
+
+    let techniques: Vec<Value> = techniques
+        .iter()
+        .map(|t| {
+            json!({
+                "techniqueID": t.technique_id,
+                "score": t.count,
+                "comment": t.sources.join("; "),
+                "enabled": true,
+                "showSubtechniques": true,
+            })
+        })
+        .collect();
+
+    return json!({
+        "name": name,
+        "versions": {
+            "attack": "14",
+            "navigator": "4.9.1",
+            "layer": "4.5",
+        },
+        "domain": "enterprise-attack",
+        "description": "Techniques observed by elf-sandbox",
+        "filters": {
+            "platforms": ["Linux"],
+        },
+        "gradient": {
+            "colors": ["#ffe766", "#ff6666"],
+            "minValue": 0,
+            "maxValue": max_count,
+        },
+        "techniques": techniques,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::rule::{Code, Severity};
+
+    fn detection(code: Code, techniques: &[&str]) -> DetectionInfo {
+        return DetectionInfo {
+            event_id: None,
+            time_created: DateTime::parse_from_rfc3339("2023-08-10T12:00:01Z").unwrap(),
+            reason_for_detection: String::new(),
+            code,
+            evidence: String::new(),
+            severity: Severity::Medium,
+            weight: 10,
+            techniques: techniques.iter().map(|t| t.to_string()).collect(),
+        };
+    }
+
+    #[test]
+    fn known_techniques_are_valid_and_sorted() {
+        for (id, name, tactic) in TECHNIQUES {
+            assert!(is_valid_technique_id(id), "{}", id);
+            assert!(!name.is_empty() && !tactic.is_empty());
+        }
+
+        assert!(TECHNIQUES.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn technique_ids() {
+        assert!(is_valid_technique_id("T1496"));
+        assert!(is_valid_technique_id("T1070.004"));
+        assert!(!is_valid_technique_id("T149"));
+        assert!(!is_valid_technique_id("T1070.4"));
+        assert!(!is_valid_technique_id("t1496"));
+        assert!(!is_valid_technique_id("T1496 "));
+    }
+
+    #[test]
+    fn look_up_techniques() {
+        assert_eq!(
+            lookup("T1053.003"),
+            Some(("Scheduled Task/Job: Cron", "persistence"))
+        );
+        assert_eq!(lookup("T9999"), None);
+    }
+
+    #[test]
+    fn collect_from_detections_and_correlations() {
+        let info = [
+            detection(Code::Rm(vec!["/root/a".to_string()]), &["T1070.004"]),
+            detection(Code::Rm(vec!["/root/b".to_string()]), &["T1070.004"]),
+            detection(
+                Code::SelfDelete("/root/target.bin".to_string()),
+                &["T1070.004"],
+            ),
+            detection(Code::Custom("my_rule".to_string()), &["T1059.004"]),
+        ];
+        let correlations = [Correlation {
+            reason: "Detected to delete file under /root".to_string(),
+            severity: Severity::Medium,
+            bonus: 15,
+            techniques: vec!["T1070.004".to_string()],
+        }];
+        let techniques = collect_techniques(&info, &correlations);
+
+        assert_eq!(techniques.len(), 2);
+
+        // not in the built-in table
+        assert_eq!(techniques[0].technique_id, "T1059.004");
+        assert_eq!(techniques[0].name, None);
+        assert_eq!(techniques[0].sources, vec!["my_rule"]);
+
+        assert_eq!(techniques[1].technique_id, "T1070.004");
+        assert_eq!(techniques[1].count, 4);
+        assert_eq!(techniques[1].tactic.as_deref(), Some("defense-evasion"));
+        assert_eq!(
+            techniques[1].sources,
+            vec!["rm", "self_delete", "Detected to delete file under /root"]
+        );
+    }
+
+    #[test]
+    fn navigator_layer_scores() {
+        let info = [
+            detection(Code::Wget, &["T1105"]),
+            detection(Code::Stratum, &["T1496"]),
+            detection(Code::Miner("xmrig".to_string()), &["T1496"]),
+        ];
+        let layer = navigator_layer("run1", &collect_techniques(&info, &[]));
+
+        assert_eq!(layer["name"], "run1");
+        assert_eq!(layer["domain"], "enterprise-attack");
+        assert_eq!(layer["gradient"]["maxValue"], 2);
+        assert_eq!(layer["techniques"][0]["techniqueID"], "T1105");
+        assert_eq!(layer["techniques"][1]["score"], 2);
+        assert_eq!(layer["techniques"][1]["comment"], "stratum; miner");
+    }
+}
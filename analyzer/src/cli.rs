@@ -0,0 +1,405 @@
+use std::{fs, path::Path, thread, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use common::*;
+
+use crate::{
+    attack,
+    database::{self, Database, RunRecord, SearchQuery},
+    diff::{self, RunBehaviour},
+    integrity::Integrity,
+    ioc, score,
+    score::Thresholds,
+    Analysis, LogFormat, Options, Progress, UserRule,
+};
+
+#[derive(Parser, Debug)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    after_help = "Exit status: 0 = benign, 10 = suspicious, 20 = malicious",
+    subcommand_negates_reqs = true
+)]
+pub struct Arguments {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub analyze: AnalyzeArguments,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Compare the behaviour of two runs, e.g. with different setup scripts or of two variants
+    #[command(after_help = "Exit status: 0 = same behaviour, 1 = different behaviour")]
+    Diff(DiffArguments),
+    /// Search the index of analyzed runs, conditions are combined with AND
+    Search(Box<SearchArguments>),
+}
+
+#[derive(Args, Debug)]
+pub struct AnalyzeArguments {
+    #[arg(long, required = true)]
+    pub target_root_dir: Option<String>,
+    /// Format of the collected log
+    #[arg(long, value_enum, default_value_t = LogFormat::Auto)]
+    pub log_format: LogFormat,
+    /// Print record and event counts and parse failures
+    #[arg(long)]
+    pub stats: bool,
+    /// Number of parse failures shown with --stats
+    #[arg(long, default_value_t = 10)]
+    pub failure_samples: usize,
+    /// Fail on any sysmon record that can not be parsed
+    #[arg(long)]
+    pub strict: bool,
+    /// Analyze the log while the sandbox is still running and print detections as they occur
+    #[arg(long)]
+    pub follow: bool,
+    /// Analyze all events instead of only the target's process subtree during the detonation
+    #[arg(long)]
+    pub include_all: bool,
+    /// Write a self-contained HTML report to this path
+    #[arg(long)]
+    pub html_report: Option<String>,
+    /// Write the verdict, detections, techniques and IOCs as JSON to this path
+    #[arg(long)]
+    pub json_report: Option<String>,
+    /// Load additional detection rules from a JSON file
+    #[arg(long)]
+    pub rules: Option<String>,
+    /// Scan the target, dropped files and memory dumps with YARA rules from this file or directory
+    #[arg(long)]
+    pub yara_rules: Option<String>,
+    /// Export observed ATT&CK techniques as a Navigator layer JSON to this path
+    #[arg(long)]
+    pub attack_layer: Option<String>,
+    /// Export the extracted IOCs as a STIX 2.1 bundle to this path
+    #[arg(long)]
+    pub stix: Option<String>,
+    /// Export the extracted IOCs as a MISP event JSON to this path
+    #[arg(long)]
+    pub misp: Option<String>,
+    /// Index the run in this SQLite database instead of the one next to the result directory
+    #[arg(long)]
+    pub database: Option<String>,
+    /// Do not index the run
+    #[arg(long, conflicts_with = "database")]
+    pub no_index: bool,
+    /// Minimum threat score (0-100) for the "suspicious" verdict
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(0..=100))]
+    pub suspicious_threshold: u32,
+    /// Minimum threat score (0-100) for the "malicious" verdict
+    #[arg(long, default_value_t = 70, value_parser = clap::value_parser!(u32).range(0..=100))]
+    pub malicious_threshold: u32,
+}
+
+#[derive(Args, Debug)]
+pub struct DiffArguments {
+    /// Result directory of the first run
+    pub run_a: String,
+    /// Result directory of the second run
+    pub run_b: String,
+    /// Compare all events instead of only the target's process subtree during the detonation
+    #[arg(long)]
+    pub include_all: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct SearchArguments {
+    /// Index database, by default the one in the sandbox's results directory
+    #[arg(long)]
+    pub database: Option<String>,
+    /// Runs of the sample, or that dropped a file, with this MD5, SHA-1 or SHA-256
+    #[arg(long)]
+    pub hash: Option<String>,
+    /// Runs of samples whose file name contains this
+    #[arg(long)]
+    pub filename: Option<String>,
+    /// Runs that resolved or connected to this domain or one of its subdomains
+    #[arg(long)]
+    pub domain: Option<String>,
+    /// Runs that connected to this IP address
+    #[arg(long)]
+    pub ip: Option<String>,
+    /// Runs with a URL containing this in a command line
+    #[arg(long)]
+    pub url: Option<String>,
+    /// Runs that created or dropped a file whose path contains this
+    #[arg(long)]
+    pub path: Option<String>,
+    /// Runs with a detection of this rule, e.g. wget, or whose reason contains this
+    #[arg(long)]
+    pub detection: Option<String>,
+    /// Runs with this ATT&CK technique or one of its sub-techniques
+    #[arg(long)]
+    pub technique: Option<String>,
+    /// Runs with this verdict
+    #[arg(long, value_parser = ["benign", "suspicious", "malicious"])]
+    pub verdict: Option<String>,
+    /// Runs started since this time, e.g. 7d, 24h or 2023-08-10
+    #[arg(long, value_parser = database::parse_since)]
+    pub since: Option<DateTime<Utc>>,
+    /// Maximum number of runs shown
+    #[arg(long, default_value_t = 50)]
+    pub limit: usize,
+}
+
+// runs the command and returns the exit status of the analyzer
+pub fn run(args: &Arguments) -> Result<i32> {
+    return match &args.command {
+        Some(Command::Diff(diff_args)) => diff(diff_args),
+        Some(Command::Search(search_args)) => search(search_args),
+        None => analyze(&args.analyze),
+    };
+}
+
+// exits with 1 if the runs behave differently
+pub fn diff(args: &DiffArguments) -> Result<i32> {
+    let behaviour_a =
+        RunBehaviour::load(&args.run_a, args.include_all).context("Failed to analyze run")?;
+    let behaviour_b =
+        RunBehaviour::load(&args.run_b, args.include_all).context("Failed to analyze run")?;
+    let differences = diff::print_diff(&args.run_a, &behaviour_a, &args.run_b, &behaviour_b);
+
+    return Ok(if differences > 0 { 1 } else { 0 });
+}
+
+pub fn search(args: &SearchArguments) -> Result<i32> {
+    let database_path = args
+        .database
+        .clone()
+        .unwrap_or(format!("{}/{}", RESULTS_DIR_PATH, INDEX_DATABASE_FILE_NAME));
+    let database =
+        Database::open(Path::new(&database_path)).context("Failed to open index database")?;
+    let runs = database
+        .search(&SearchQuery {
+            hash: args.hash.clone(),
+            filename: args.filename.clone(),
+            domain: args.domain.clone(),
+            ip: args.ip.clone(),
+            url: args.url.clone(),
+            path: args.path.clone(),
+            detection: args.detection.clone(),
+            technique: args.technique.clone(),
+            verdict: args.verdict.clone(),
+            since: args.since,
+            limit: args.limit,
+        })
+        .context("Failed to search index database")?;
+
+    for run in &runs {
+        println!(
+            "{}  {}  {:<10} {:>3}  {}  {}  {}",
+            run.started_at.as_deref().unwrap_or("-"),
+            run.run_id,
+            run.verdict,
+            run.score,
+            run.target_name.as_deref().unwrap_or("-"),
+            run.sha256.as_deref().unwrap_or("-"),
+            run.path
+        );
+    }
+    println!("{} runs", runs.len());
+
+    return Ok(0);
+}
+
+// prints the analysis of a run, writes the requested exports and exits with its verdict
+pub fn analyze(args: &AnalyzeArguments) -> Result<i32> {
+    let thresholds = Thresholds {
+        suspicious: args.suspicious_threshold,
+        malicious: args.malicious_threshold,
+    };
+
+    if thresholds.suspicious > thresholds.malicious {
+        bail!("Suspicious threshold must not be greater than malicious threshold");
+    }
+
+    // required unless a subcommand is given
+    let target_root_dir = args.target_root_dir.clone().unwrap();
+    let detonation_path = &format!("{}/{}", target_root_dir, DETONATION_FILE_NAME);
+
+    // the sandbox creates the log and the marker when the target is launched
+    if args.follow {
+        println!("Waiting for the detonation to start...");
+
+        while !Path::new(detonation_path).exists() {
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    let options = Options {
+        log_format: args.log_format,
+        include_all: args.include_all,
+        follow: args.follow,
+        strict: args.strict,
+        keep_entries: args.html_report.is_some(),
+        failure_samples: args.failure_samples,
+        user_rules: match &args.rules {
+            Some(path) => UserRule::load(path).context("Failed to load user rules")?,
+            None => vec![],
+        },
+        yara_rules: args.yara_rules.clone(),
+        thresholds,
+    };
+
+    let analysis = Analysis::run(&target_root_dir, &options, &mut |progress| match progress {
+        Progress::Integrity(integrity) => {
+            println!("Integrity: {}", integrity);
+
+            if let Integrity::Failed(problems) = integrity {
+                for problem in problems {
+                    println!("  - {}", problem);
+                }
+            }
+        }
+        Progress::Scope(Some(detonation)) => println!(
+            "Scope: target process subtree from {} to {}",
+            detonation.started_at,
+            detonation
+                .ended_at
+                .map(|t| t.to_string())
+                .unwrap_or("the end of the detonation".to_string())
+        ),
+        Progress::Scope(None) => println!("Scope: all events (no detonation marker found)"),
+        Progress::Log(log_path, log_format) => println!("Log: {} ({:?})", log_path, log_format),
+        Progress::SyscallLog(log_path) => println!("Log: {} (strace)", log_path),
+        Progress::Detection(info) => {
+            if args.follow {
+                println!(
+                    "[{}] {} ({:?})",
+                    info.time_created, info.reason_for_detection, info.severity
+                );
+            }
+        }
+    })?;
+
+    let parse_stats = &analysis.parse_stats;
+
+    if args.stats {
+        parse_stats.print();
+    } else if parse_stats.failures > 0 {
+        println!(
+            "Failed to parse {} of {} event records (run with --stats for details)",
+            parse_stats.failures, parse_stats.event_records
+        );
+    }
+
+    for m in &analysis.yara_matches {
+        println!(
+            "YARA: {} matched {} ({} strings)",
+            m.rule,
+            m.file,
+            m.strings.len()
+        );
+    }
+
+    let threat_score = &analysis.threat_score;
+
+    println!("{:?}", analysis.detection_info);
+    println!("Score: {}/{}", threat_score.score, score::MAX_SCORE);
+    println!("Verdict: {}", threat_score.verdict);
+
+    for reason in &threat_score.reasons {
+        println!("  - {}", reason);
+    }
+
+    println!("ATT&CK techniques:");
+    for technique in &analysis.techniques {
+        println!(
+            "  - {} {} ({})",
+            technique.technique_id,
+            technique.name.as_deref().unwrap_or("-"),
+            technique.sources.join(", ")
+        );
+    }
+
+    println!("IOCs:");
+    for (ioc, _) in &analysis.iocs {
+        println!("  - {}", ioc);
+    }
+
+    let run_name = &analysis.run_name;
+
+    if let Some(layer_path) = &args.attack_layer {
+        let layer = attack::navigator_layer(run_name, &analysis.techniques);
+
+        fs::write(layer_path, serde_json::to_string_pretty(&layer).unwrap())
+            .context("Failed to write ATT&CK Navigator layer")?;
+        println!("Generated ATT&CK Navigator layer: {}", layer_path);
+    }
+
+    if let Some(stix_path) = &args.stix {
+        let bundle = ioc::stix_bundle(run_name, &analysis.iocs, threat_score);
+
+        fs::write(stix_path, serde_json::to_string_pretty(&bundle).unwrap())
+            .context("Failed to write STIX bundle")?;
+        println!("Generated STIX bundle: {}", stix_path);
+    }
+
+    if let Some(misp_path) = &args.misp {
+        let event = ioc::misp_event(run_name, &analysis.iocs, threat_score, &analysis.techniques);
+
+        fs::write(misp_path, serde_json::to_string_pretty(&event).unwrap())
+            .context("Failed to write MISP event")?;
+        println!("Generated MISP event: {}", misp_path);
+    }
+
+    if !args.no_index {
+        let run_path =
+            fs::canonicalize(&target_root_dir).context("Failed to resolve result directory")?;
+        let database_path = match &args.database {
+            Some(path) => Path::new(path).to_path_buf(),
+            None => run_path
+                .parent()
+                .unwrap_or(&run_path)
+                .join(INDEX_DATABASE_FILE_NAME),
+        };
+        let marker = analysis.marker.as_ref();
+
+        // the analysis is still valid, it is only missing from the index
+        let indexed = Database::open(&database_path).and_then(|mut database| {
+            database.insert_run(&RunRecord {
+                run_id: marker.map(|m| m.run_id.clone()).unwrap_or(run_name.clone()),
+                path: run_path.to_string_lossy().to_string(),
+                target_name: marker.and_then(|m| m.target_name.clone()),
+                started_at: marker.map(|m| m.started_at),
+                ended_at: marker.and_then(|m| m.ended_at),
+                profile: marker.and_then(|m| m.profile.clone()),
+                threat_score,
+                detection_info: &analysis.detection_info,
+                iocs: &analysis.iocs,
+                techniques: &analysis.techniques,
+            })
+        });
+
+        match indexed {
+            Ok(()) => println!("Indexed run in {}", database_path.display()),
+            Err(err) => println!("Failed to index run: {:#}", err),
+        }
+    }
+
+    if args.html_report.is_some() || args.json_report.is_some() {
+        let report = analysis.report(args.html_report.as_deref().unwrap_or_default());
+
+        if let Some(report_path) = &args.html_report {
+            fs::write(report_path, report.to_html()).context("Failed to write HTML report")?;
+            println!("Generated HTML report: {}", report_path);
+        }
+
+        if let Some(report_path) = &args.json_report {
+            fs::write(
+                report_path,
+                serde_json::to_string_pretty(&report.to_json()).unwrap(),
+            )
+            .context("Failed to write JSON report")?;
+            println!("Generated JSON report: {}", report_path);
+        }
+    }
+
+    return Ok(threat_score.verdict.exit_code());
+}
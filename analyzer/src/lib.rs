@@ -0,0 +1,34 @@
+//! Parses Sysmon for Linux logs, runs the detection rules on them and reports on sandbox runs.
+//!
+//! - [`SyslogReader`] reads the events of any Sysmon for Linux log
+//! - [`RuleEngine`] runs the built-in and user rules on a stream of events
+//! - [`Analysis`] analyzes a sandbox result directory, [`Analysis::report`] is the HTML and JSON report
+//! - [`cli`] is the command line of the analyzer, also used by `elf-sandbox`
+
+pub mod analysis;
+pub mod attack;
+pub mod cli;
+pub mod collector;
+pub mod database;
+pub mod diff;
+pub mod elf;
+pub mod integrity;
+pub mod ioc;
+pub mod logsource;
+pub mod process;
+pub mod report;
+pub mod rule;
+pub mod scope;
+pub mod score;
+pub mod stats;
+pub mod syscall;
+pub mod syslog;
+pub mod sysmon;
+pub mod yara;
+
+pub use analysis::{Analysis, Options, Progress};
+pub use logsource::LogFormat;
+pub use report::Report;
+pub use rule::{DetectionInfo, RuleEngine, UserRule};
+pub use syslog::{SyslogEntry, SyslogReader};
+pub use sysmon::SysmonEvent;
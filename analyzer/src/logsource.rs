@@ -0,0 +1,769 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use common::*;
+use flate2::read::MultiGzDecoder;
+use regex::Regex;
+use serde_json::Value;
+
+const SYSMON_IDENTIFIER: &str = "sysmon";
+// "<unix time>\t<kind>\t..." lines, bpftrace also prints a few status lines
+const EBPF_REGEX: &str = r"^\d+\.\d+\t[a-z]+\t";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    /// Detect the format from the first record
+    Auto,
+    /// BSD syslog (`Mon dd hh:mm:ss host sysmon: ...`) or rsyslog high-precision timestamps
+    Syslog,
+    /// RFC 5424 syslog (`<pri>1 timestamp host sysmon procid msgid sd msg`)
+    Rfc5424,
+    /// `journalctl -o json`
+    JournalJson,
+    /// `journalctl -o export`
+    JournalExport,
+    /// Stream of Sysmon `<Event>` XML documents
+    SysmonXml,
+    /// auditd log (`/var/log/audit/audit.log`)
+    Auditd,
+    /// Output of the sandbox's bpftrace collector
+    Ebpf,
+}
+
+#[derive(Debug, Clone)]
+pub enum EventPayload {
+    SysmonXml(String),
+    Auditd(Vec<String>), // all records of one audit event
+    Ebpf(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub raw: String,
+    pub payload: Option<EventPayload>, // None if the record is not an event of a collector
+}
+
+pub trait LogSource {
+    fn format(&self) -> LogFormat;
+    fn next_record(&mut self) -> Result<Option<LogRecord>>;
+}
+
+// records and lines are buffered until they are complete, Sysmon events are a few KiB
+const MAX_RECORD_SIZE: usize = 16 * 1024 * 1024;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+// logs of the collectors that ran in a result directory, possibly compressed for archiving
+pub fn find_logs(target_root_dir: &str, syslog_format: LogFormat) -> Vec<(String, LogFormat)> {
+    return [
+        (SYSLOG_FILE_NAME, syslog_format),
+        (AUDIT_LOG_FILE_NAME, LogFormat::Auditd),
+        (EBPF_LOG_FILE_NAME, LogFormat::Ebpf),
+    ]
+    .iter()
+    .filter_map(|(name, format)| {
+        ["", ".gz", ".zst"]
+            .iter()
+            .map(|ext| format!("{}/{}{}", target_root_dir, name, ext))
+            .find(|path| Path::new(path).exists())
+            .map(|path| (path, *format))
+    })
+    .collect();
+}
+
+// opens a plain, gzip or zstd compressed log file
+pub fn open_file(path: &str, format: LogFormat) -> Result<Box<dyn LogSource>> {
+    return open(open_reader(path)?, format);
+}
+
+pub fn open_reader(path: &str) -> Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let head = reader.fill_buf()?;
+
+    return Ok(if head.starts_with(GZIP_MAGIC) {
+        Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+    } else if head.starts_with(ZSTD_MAGIC) {
+        Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?))
+    } else {
+        Box::new(reader)
+    });
+}
+
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// reads a log that is still being written, end of file is reported once is_done returns true
+pub fn open_follow(
+    path: &str,
+    format: LogFormat,
+    is_done: Box<dyn FnMut() -> bool>,
+) -> Result<Box<dyn LogSource>> {
+    let reader = FollowReader {
+        file: File::open(path)?,
+        is_done,
+    };
+
+    return open(Box::new(BufReader::new(reader)), format);
+}
+
+struct FollowReader {
+    file: File,
+    is_done: Box<dyn FnMut() -> bool>,
+}
+
+impl Read for FollowReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // checked before reading so that data written before completion is not lost
+            let done = (self.is_done)();
+            let size = self.file.read(buf)?;
+
+            if size > 0 || done {
+                return Ok(size);
+            }
+
+            thread::sleep(FOLLOW_POLL_INTERVAL);
+        }
+    }
+}
+
+pub fn open(mut reader: Box<dyn BufRead>, format: LogFormat) -> Result<Box<dyn LogSource>> {
+    let format = match format {
+        LogFormat::Auto => detect(reader.fill_buf()?),
+        format => format,
+    };
+
+    return Ok(match format {
+        LogFormat::Auto | LogFormat::Syslog => Box::new(LineSource::syslog(reader)),
+        LogFormat::Rfc5424 => Box::new(LineSource::rfc5424(reader)),
+        LogFormat::JournalJson => Box::new(LineSource::journal_json(reader)),
+        LogFormat::JournalExport => Box::new(JournalExportSource { reader }),
+        LogFormat::SysmonXml => Box::new(SysmonXmlSource::new(reader)),
+        LogFormat::Auditd => Box::new(AuditdSource::new(reader)),
+        LogFormat::Ebpf => Box::new(LineSource::ebpf(reader)),
+    });
+}
+
+fn detect(head: &[u8]) -> LogFormat {
+    let head = String::from_utf8_lossy(head);
+    let first_line = head.lines().map(|l| l.trim()).find(|l| !l.is_empty());
+
+    let first_line = match first_line {
+        Some(line) => line,
+        None => return LogFormat::Syslog,
+    };
+
+    if first_line.starts_with('{') {
+        return LogFormat::JournalJson;
+    }
+
+    if first_line.starts_with("__CURSOR=") || first_line.starts_with("__REALTIME_TIMESTAMP=") {
+        return LogFormat::JournalExport;
+    }
+
+    if first_line.starts_with("<?xml") || first_line.starts_with("<Event") {
+        return LogFormat::SysmonXml;
+    }
+
+    if first_line.starts_with("type=") {
+        return LogFormat::Auditd;
+    }
+
+    if first_line.starts_with("Attaching ") || Regex::new(EBPF_REGEX).unwrap().is_match(first_line)
+    {
+        return LogFormat::Ebpf;
+    }
+
+    if Regex::new(r"^(<\d{1,3}>)?1 ").unwrap().is_match(first_line) {
+        return LogFormat::Rfc5424;
+    }
+
+    return LogFormat::Syslog;
+}
+
+// longer lines are truncated, so that a log without newlines is not read into memory at once
+pub fn read_line(reader: &mut dyn BufRead) -> Result<Option<String>> {
+    let mut buf = vec![];
+
+    if read_bounded_line(reader, &mut buf)? == 0 {
+        return Ok(None);
+    }
+
+    while buf.last() == Some(&b'\n') || buf.last() == Some(&b'\r') {
+        buf.pop();
+    }
+
+    return Ok(Some(String::from_utf8_lossy(&buf).to_string()));
+}
+
+// reads up to and including the next newline, but at most MAX_RECORD_SIZE bytes of it
+fn read_bounded_line(reader: &mut dyn BufRead, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let read = (&mut *reader)
+        .take(MAX_RECORD_SIZE as u64)
+        .read_until(b'\n', buf)?;
+
+    if read == MAX_RECORD_SIZE && buf.last() != Some(&b'\n') {
+        reader.skip_until(b'\n')?;
+    }
+
+    return Ok(read);
+}
+
+// line oriented formats, one record per line
+struct LineSource {
+    reader: Box<dyn BufRead>,
+    format: LogFormat,
+    regex: Option<Regex>, // extracts the message of syslog formats
+}
+
+impl LineSource {
+    fn syslog(reader: Box<dyn BufRead>) -> Self {
+        // "Aug 10 12:00:00 host sysmon: ..." or "2023-08-10T12:00:00.123456+00:00 host sysmon[1]: ..."
+        let regex = Regex::new(
+            r"^(?:[A-Z][a-z]{2}\s+\d+\s\d\d:\d\d:\d\d|\d{4}-\d\d-\d\dT\S+)\s\S+\ssysmon\S*:\s(.+)",
+        )
+        .unwrap();
+
+        return Self {
+            reader,
+            format: LogFormat::Syslog,
+            regex: Some(regex),
+        };
+    }
+
+    fn rfc5424(reader: Box<dyn BufRead>) -> Self {
+        // "<pri>1 timestamp hostname app-name procid msgid structured-data msg"
+        let regex = Regex::new(
+            r"^(?:<\d{1,3}>)?1\s\S+\s\S+\ssysmon\S*\s\S+\s\S+\s(?:-|(?:\[(?:[^\]\\]|\\.)*\])+)\s?(?:\x{FEFF})?(.+)",
+        )
+        .unwrap();
+
+        return Self {
+            reader,
+            format: LogFormat::Rfc5424,
+            regex: Some(regex),
+        };
+    }
+
+    fn journal_json(reader: Box<dyn BufRead>) -> Self {
+        return Self {
+            reader,
+            format: LogFormat::JournalJson,
+            regex: None,
+        };
+    }
+
+    fn ebpf(reader: Box<dyn BufRead>) -> Self {
+        return Self {
+            reader,
+            format: LogFormat::Ebpf,
+            regex: Some(Regex::new(EBPF_REGEX).unwrap()),
+        };
+    }
+
+    fn extract(&self, line: &str) -> Option<EventPayload> {
+        if self.format == LogFormat::Ebpf {
+            return self
+                .regex
+                .as_ref()?
+                .is_match(line)
+                .then(|| EventPayload::Ebpf(line.to_string()));
+        }
+
+        return self.extract_sysmon_xml(line).map(EventPayload::SysmonXml);
+    }
+
+    fn extract_sysmon_xml(&self, line: &str) -> Option<String> {
+        if let Some(regex) = &self.regex {
+            return regex.captures(line).map(|c| c[1].to_string());
+        }
+
+        let entry: Value = serde_json::from_str(line).ok()?;
+
+        if !journal_value(&entry["SYSLOG_IDENTIFIER"])?.starts_with(SYSMON_IDENTIFIER) {
+            return None;
+        }
+
+        return journal_value(&entry["MESSAGE"]);
+    }
+}
+
+// journald writes non UTF-8 fields as arrays of bytes
+fn journal_value(value: &Value) -> Option<String> {
+    return match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Array(bytes) => {
+            let bytes: Vec<u8> = bytes
+                .iter()
+                .filter_map(|b| b.as_u64().map(|b| b as u8))
+                .collect();
+            Some(String::from_utf8_lossy(&bytes).to_string())
+        }
+        _ => None,
+    };
+}
+
+impl LogSource for LineSource {
+    fn format(&self) -> LogFormat {
+        return self.format;
+    }
+
+    fn next_record(&mut self) -> Result<Option<LogRecord>> {
+        return Ok(read_line(self.reader.as_mut())?.map(|line| LogRecord {
+            payload: self.extract(&line),
+            raw: line,
+        }));
+    }
+}
+
+// https://systemd.io/JOURNAL_EXPORT_FORMATS/
+struct JournalExportSource {
+    reader: Box<dyn BufRead>,
+}
+
+impl LogSource for JournalExportSource {
+    fn format(&self) -> LogFormat {
+        return LogFormat::JournalExport;
+    }
+
+    fn next_record(&mut self) -> Result<Option<LogRecord>> {
+        let mut fields = vec![];
+
+        loop {
+            let mut line = vec![];
+
+            if read_bounded_line(self.reader.as_mut(), &mut line)? == 0 {
+                break;
+            }
+
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+
+            // empty line terminates a record
+            if line.is_empty() {
+                if fields.is_empty() {
+                    continue;
+                }
+                break;
+            }
+
+            match line.iter().position(|b| *b == b'=') {
+                Some(i) => fields.push((
+                    String::from_utf8_lossy(&line[..i]).to_string(),
+                    String::from_utf8_lossy(&line[i + 1..]).to_string(),
+                )),
+                None => {
+                    // binary field: name, little endian u64 size, data, newline
+                    let mut size = [0; 8];
+                    self.reader
+                        .read_exact(&mut size)
+                        .context("Truncated binary field in journal export")?;
+
+                    let size = u64::from_le_bytes(size);
+
+                    if size > MAX_RECORD_SIZE as u64 {
+                        bail!("Binary field of {} bytes in journal export", size);
+                    }
+
+                    let mut data = vec![0; size as usize];
+                    self.reader
+                        .read_exact(&mut data)
+                        .context("Truncated binary field in journal export")?;
+
+                    let mut newline = [0; 1];
+                    self.reader.read_exact(&mut newline)?;
+
+                    fields.push((
+                        String::from_utf8_lossy(&line).to_string(),
+                        String::from_utf8_lossy(&data).to_string(),
+                    ));
+                }
+            }
+        }
+
+        if fields.is_empty() {
+            return Ok(None);
+        }
+
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let payload = match field("SYSLOG_IDENTIFIER") {
+            Some(identifier) if identifier.starts_with(SYSMON_IDENTIFIER) => {
+                field("MESSAGE").map(EventPayload::SysmonXml)
+            }
+            _ => None,
+        };
+
+        let raw = fields
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        return Ok(Some(LogRecord { raw, payload }));
+    }
+}
+
+// concatenated <Event> documents, possibly spanning multiple lines
+struct SysmonXmlSource {
+    reader: Box<dyn BufRead>,
+    buf: String,
+    start_regex: Regex,
+}
+
+impl SysmonXmlSource {
+    fn new(reader: Box<dyn BufRead>) -> Self {
+        return Self {
+            reader,
+            buf: String::new(),
+            start_regex: Regex::new(r"<Event[\s>]").unwrap(),
+        };
+    }
+}
+
+impl LogSource for SysmonXmlSource {
+    fn format(&self) -> LogFormat {
+        return LogFormat::SysmonXml;
+    }
+
+    fn next_record(&mut self) -> Result<Option<LogRecord>> {
+        const END_TAG: &str = "</Event>";
+
+        loop {
+            if let Some(start) = self.start_regex.find(&self.buf).map(|m| m.start()) {
+                if let Some(end) = self.buf[start..].find(END_TAG) {
+                    let end = start + end + END_TAG.len();
+                    let xml = self.buf[start..end].to_string();
+                    self.buf.drain(..end);
+
+                    return Ok(Some(LogRecord {
+                        raw: xml.clone(),
+                        payload: Some(EventPayload::SysmonXml(xml)),
+                    }));
+                }
+            }
+
+            // also without a start tag, e.g. if the log is not Sysmon XML at all
+            if self.buf.len() > MAX_RECORD_SIZE {
+                bail!(
+                    "No complete <Event> in {} bytes of Sysmon XML",
+                    self.buf.len()
+                );
+            }
+
+            match read_line(self.reader.as_mut())? {
+                Some(line) => {
+                    self.buf.push_str(&line);
+                    self.buf.push('\n');
+                }
+                None => {
+                    let rest = self.buf.trim();
+
+                    if rest.is_empty() || rest == "</Events>" {
+                        return Ok(None);
+                    }
+
+                    let rest = rest.to_string();
+                    self.buf.clear();
+
+                    // an unterminated event is handed over so that it is reported as unparsable
+                    return Ok(Some(LogRecord {
+                        payload: self
+                            .start_regex
+                            .is_match(&rest)
+                            .then(|| EventPayload::SysmonXml(rest.clone())),
+                        raw: rest,
+                    }));
+                }
+            }
+        }
+    }
+}
+
+// records of one event share the serial in "msg=audit(<time>:<serial>)"
+struct AuditdSource {
+    reader: Box<dyn BufRead>,
+    pending: Option<String>, // first line of the next event
+    id_regex: Regex,
+}
+
+impl AuditdSource {
+    fn new(reader: Box<dyn BufRead>) -> Self {
+        return Self {
+            reader,
+            pending: None,
+            id_regex: Regex::new(r"msg=audit\(([\d.]+:\d+)\)").unwrap(),
+        };
+    }
+
+    fn event_id(&self, line: &str) -> Option<String> {
+        return self.id_regex.captures(line).map(|c| c[1].to_string());
+    }
+}
+
+impl LogSource for AuditdSource {
+    fn format(&self) -> LogFormat {
+        return LogFormat::Auditd;
+    }
+
+    fn next_record(&mut self) -> Result<Option<LogRecord>> {
+        let mut lines: Vec<String> = self.pending.take().into_iter().collect();
+
+        loop {
+            let line = match read_line(self.reader.as_mut())? {
+                Some(line) => line,
+                None => break,
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // EOE terminates multi record events
+            if line.starts_with("type=EOE ") {
+                if lines.is_empty() {
+                    continue;
+                }
+                break;
+            }
+
+            match lines.first() {
+                Some(first) if self.event_id(first) != self.event_id(&line) => {
+                    self.pending = Some(line);
+                    break;
+                }
+                _ => lines.push(line),
+            }
+        }
+
+        if lines.is_empty() {
+            return Ok(None);
+        }
+
+        let payload = self
+            .event_id(&lines[0])
+            .map(|_| EventPayload::Auditd(lines.clone()));
+
+        return Ok(Some(LogRecord {
+            raw: lines.join("\n"),
+            payload,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        io::{Cursor, Write},
+        process,
+    };
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+    use crate::sysmon::SysmonEvent;
+
+    const EVENT: &str = r#"<Event><System><Provider Name="Linux-Sysmon" Guid="{ff032593-a8d3-4f13-b0d6-01fc615a0f97}"/><EventID>5</EventID><Version>3</Version><Level>4</Level><Task>5</Task><Opcode>0</Opcode><Keywords>0x8000000000000000</Keywords><TimeCreated SystemTime="2023-08-10T12:00:20.000000000Z"/><EventRecordID>9</EventRecordID><Correlation/><Execution ProcessID="100" ThreadID="100"/><Channel>Linux-Sysmon/Operational</Channel><Computer>sandbox</Computer><Security UserId="0"/></System><EventData><Data Name="RuleName">-</Data><Data Name="UtcTime">2023-08-10 12:00:20.000</Data><Data Name="ProcessGuid">{5bd6ab47-0002-64d4-0000-000000000000}</Data><Data Name="ProcessId">100</Data><Data Name="Image">/usr/bin/bash</Data><Data Name="User">root</Data></EventData></Event>"#;
+
+    fn records(data: &[u8], format: LogFormat) -> Result<Vec<LogRecord>> {
+        let mut source = open(Box::new(Cursor::new(data.to_vec())), format)?;
+        let mut records = vec![];
+
+        while let Some(record) = source.next_record()? {
+            records.push(record);
+        }
+
+        return Ok(records);
+    }
+
+    fn sysmon_xml(record: &LogRecord) -> Option<&str> {
+        return match &record.payload {
+            Some(EventPayload::SysmonXml(xml)) => Some(xml),
+            _ => None,
+        };
+    }
+
+    #[test]
+    fn detect_formats() {
+        let cases = [
+            ("Aug 10 12:00:01 sandbox sysmon: <Event>", LogFormat::Syslog),
+            (
+                "<14>1 2023-08-10T12:00:01.123456+00:00 sandbox sysmon 100 - - <Event>",
+                LogFormat::Rfc5424,
+            ),
+            (
+                r#"{"__CURSOR":"s=1","MESSAGE":"x"}"#,
+                LogFormat::JournalJson,
+            ),
+            ("__CURSOR=s=1\nMESSAGE=x", LogFormat::JournalExport),
+            ("<?xml version=\"1.0\"?>\n<Events>", LogFormat::SysmonXml),
+            (
+                "type=SYSCALL msg=audit(1691668810.123:42): arch=c000003e syscall=59",
+                LogFormat::Auditd,
+            ),
+            ("Attaching 6 probes...", LogFormat::Ebpf),
+            (
+                "1691668810.123456\texec\t100\t1\t/usr/bin/id",
+                LogFormat::Ebpf,
+            ),
+            ("\n\n", LogFormat::Syslog),
+        ];
+
+        for (head, format) in cases {
+            assert_eq!(detect(head.as_bytes()), format, "{}", head);
+        }
+    }
+
+    #[test]
+    fn syslog_keeps_only_sysmon_records() {
+        let log = format!(
+            "Aug 10 12:00:01 sandbox systemd[1]: Started Session 3 of user root.\n\
+             Aug 10 12:00:20 sandbox sysmon: {}\n\
+             2023-08-10T12:00:20.123456+00:00 sandbox sysmon[812]: {}\n",
+            EVENT, EVENT
+        );
+        let records = records(log.as_bytes(), LogFormat::Auto).unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert!(records[0].payload.is_none());
+        assert_eq!(sysmon_xml(&records[1]), Some(EVENT));
+        assert_eq!(sysmon_xml(&records[2]), Some(EVENT));
+    }
+
+    #[test]
+    fn rfc5424_skips_structured_data() {
+        let log = format!(
+            "<14>1 2023-08-10T12:00:20.123456+00:00 sandbox sysmon 812 - [meta sequenceId=\"1\"] {}\n",
+            EVENT
+        );
+        let records = records(log.as_bytes(), LogFormat::Auto).unwrap();
+
+        assert_eq!(sysmon_xml(&records[0]), Some(EVENT));
+    }
+
+    #[test]
+    fn journal_json_decodes_byte_arrays() {
+        let message: Vec<String> = EVENT.bytes().map(|b| b.to_string()).collect();
+        let log = format!(
+            "{{\"SYSLOG_IDENTIFIER\":\"sshd\",\"MESSAGE\":\"Accepted publickey\"}}\n\
+             {{\"SYSLOG_IDENTIFIER\":\"sysmon\",\"MESSAGE\":[{}]}}\n",
+            message.join(",")
+        );
+        let records = records(log.as_bytes(), LogFormat::Auto).unwrap();
+
+        assert!(records[0].payload.is_none());
+        assert_eq!(sysmon_xml(&records[1]), Some(EVENT));
+    }
+
+    #[test]
+    fn journal_export_reads_binary_fields() {
+        let mut log = b"__CURSOR=s=1\nSYSLOG_IDENTIFIER=sysmon\nMESSAGE\n".to_vec();
+        log.extend((EVENT.len() as u64).to_le_bytes());
+        log.extend(EVENT.as_bytes());
+        log.extend(b"\n\n__CURSOR=s=2\nSYSLOG_IDENTIFIER=cron\nMESSAGE=(root) CMD (true)\n\n");
+        let records = records(&log, LogFormat::Auto).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(sysmon_xml(&records[0]), Some(EVENT));
+        assert!(records[1].payload.is_none());
+    }
+
+    #[test]
+    fn journal_export_rejects_huge_binary_field() {
+        let mut log = b"__CURSOR=s=1\nMESSAGE\n".to_vec();
+        log.extend(u64::MAX.to_le_bytes());
+
+        assert!(records(&log, LogFormat::JournalExport).is_err());
+    }
+
+    #[test]
+    fn sysmon_xml_spans_lines() {
+        let log = format!(
+            "<?xml version=\"1.0\"?>\n<Events>\n{}\n{}\n</Events>\n",
+            EVENT.replace("<EventData>", "\n  <EventData>\n"),
+            EVENT
+        );
+        let records = records(log.as_bytes(), LogFormat::Auto).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert!(sysmon_xml(&records[0]).unwrap().contains("\n  <EventData>"));
+        assert_eq!(sysmon_xml(&records[1]), Some(EVENT));
+    }
+
+    #[test]
+    fn sysmon_xml_reports_unterminated_event() {
+        let log = format!("{}\n{}", EVENT, &EVENT[..100]);
+        let records = records(log.as_bytes(), LogFormat::SysmonXml).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert!(SysmonEvent::from_xml(sysmon_xml(&records[1]).unwrap()).is_err());
+    }
+
+    #[test]
+    fn sysmon_xml_buffer_is_bounded() {
+        let line = "x".repeat(1024 * 1024) + "\n";
+        let log = line.repeat(MAX_RECORD_SIZE / line.len() + 2);
+
+        assert!(records(log.as_bytes(), LogFormat::SysmonXml).is_err());
+    }
+
+    #[test]
+    fn long_lines_are_truncated() {
+        let log = format!("{}\nnext\n", "x".repeat(MAX_RECORD_SIZE + 10));
+        let mut reader: &[u8] = log.as_bytes();
+
+        assert_eq!(
+            read_line(&mut reader).unwrap().unwrap().len(),
+            MAX_RECORD_SIZE
+        );
+        assert_eq!(read_line(&mut reader).unwrap().unwrap(), "next");
+        assert_eq!(read_line(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn auditd_groups_records_of_one_event() {
+        let log = "\
+type=SYSCALL msg=audit(1691668810.123:42): arch=c000003e syscall=59 success=yes exit=0 a0=55d0 a1=55d1 a2=55d2 a3=0 items=2 ppid=100 pid=101 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts0 ses=3 comm=\"wget\" exe=\"/usr/bin/wget\" key=\"exec\"
+type=EXECVE msg=audit(1691668810.123:42): argc=2 a0=\"wget\" a1=\"http://example.com/x\"
+type=PROCTITLE msg=audit(1691668810.123:42): proctitle=776765740068747470
+type=EOE msg=audit(1691668810.123:42):
+type=SYSCALL msg=audit(1691668811.000:43): arch=c000003e syscall=87 success=yes exit=0 a0=7ffd items=2 ppid=100 pid=101 comm=\"rm\" exe=\"/usr/bin/rm\" key=\"delete\"
+";
+        let records = records(log.as_bytes(), LogFormat::Auto).unwrap();
+
+        assert_eq!(records.len(), 2);
+        match &records[0].payload {
+            Some(EventPayload::Auditd(lines)) => assert_eq!(lines.len(), 3),
+            payload => panic!("Unexpected payload {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn open_gzip_compressed_log() {
+        let path = env::temp_dir().join(format!("analyzer-log-{}.gz", process::id()));
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        writeln!(encoder, "Aug 10 12:00:20 sandbox sysmon: {}", EVENT).unwrap();
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        let mut source = open_file(path.to_str().unwrap(), LogFormat::Auto).unwrap();
+        let record = source.next_record().unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(source.format(), LogFormat::Syslog);
+        assert_eq!(sysmon_xml(&record), Some(EVENT));
+    }
+}
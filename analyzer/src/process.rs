@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, FixedOffset};
+use uuid::Uuid;
+
+use crate::{syslog::SyslogEntry, sysmon::SysmonEventData};
+
+#[derive(Debug, Clone)]
+pub struct ProcessNode {
+    pub process_guid: Uuid,
+    pub parent_process_guid: Option<Uuid>,
+    pub process_id: u32,
+    pub image: String,
+    pub command_line: String,
+    pub time_created: DateTime<FixedOffset>,
+    pub children: Vec<Uuid>, // process guids
+}
+
+#[derive(Debug, Default)]
+pub struct ProcessTree {
+    nodes: HashMap<Uuid, ProcessNode>,
+    roots: Vec<Uuid>,
+}
+
+impl ProcessTree {
+    pub fn build(entries: &[SyslogEntry]) -> Self {
+        let mut nodes = HashMap::new();
+        let mut order = vec![];
+
+        for e in entries {
+            let p = match &e.sysmon_event.data {
+                SysmonEventData::ProcessCreate(p) => p,
+                _ => continue,
+            };
+
+            order.push(p.process_guid);
+            nodes.insert(
+                p.process_guid,
+                ProcessNode {
+                    process_guid: p.process_guid,
+                    parent_process_guid: p.parent_process_guid,
+                    process_id: p.process_id,
+                    image: p.image.clone().unwrap_or_default(),
+                    command_line: p.command_line.clone().unwrap_or_default(),
+                    time_created: e.sysmon_event.time_created,
+                    children: vec![],
+                },
+            );
+        }
+
+        let mut roots = vec![];
+
+        for guid in order {
+            let parent_guid = nodes[&guid].parent_process_guid;
+
+            match parent_guid {
+                Some(parent_guid) if parent_guid != guid && nodes.contains_key(&parent_guid) => {
+                    nodes.get_mut(&parent_guid).unwrap().children.push(guid)
+                }
+                _ => roots.push(guid),
+            }
+        }
+
+        return Self { nodes, roots };
+    }
+
+    pub fn roots(&self) -> &[Uuid] {
+        return &self.roots;
+    }
+
+    pub fn get(&self, process_guid: &Uuid) -> Option<&ProcessNode> {
+        return self.nodes.get(process_guid);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.nodes.is_empty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rule::tests::sysmon_entry, sysmon::SysmonEventId};
+
+    const TARGET: &str = "{5bd6ab47-0002-64d4-0000-000000000000}";
+    const SHELL: &str = "{5bd6ab47-0003-64d4-0000-000000000000}";
+    const WGET: &str = "{5bd6ab47-0004-64d4-0000-000000000000}";
+
+    fn process_create(guid: &str, parent_guid: &str, image: &str) -> SyslogEntry {
+        return sysmon_entry(
+            SysmonEventId::PROCESS_CREATE,
+            &[
+                ("RuleName", "-"),
+                ("UtcTime", "2023-08-10 12:00:13.000"),
+                ("ProcessGuid", guid),
+                ("ProcessId", "101"),
+                ("Image", image),
+                ("CommandLine", image),
+                ("CurrentDirectory", "/root"),
+                ("User", "root"),
+                ("LogonGuid", "{5bd6ab47-0000-64d4-0000-000000000000}"),
+                ("LogonId", "0"),
+                ("Hashes", "-"),
+                ("ParentProcessGuid", parent_guid),
+                ("ParentProcessId", "100"),
+                ("ParentImage", "-"),
+                ("ParentCommandLine", "-"),
+            ],
+        );
+    }
+
+    #[test]
+    fn build_tree() {
+        let entries = [
+            // its parent was created before the log starts
+            process_create(
+                TARGET,
+                "{5bd6ab47-0001-64d4-0000-000000000000}",
+                "/root/target.bin",
+            ),
+            process_create(SHELL, TARGET, "/usr/bin/sh"),
+            process_create(WGET, SHELL, "/usr/bin/wget"),
+            // its own parent, as logged for the first process of a namespace
+            process_create(
+                "{5bd6ab47-0005-64d4-0000-000000000000}",
+                "{5bd6ab47-0005-64d4-0000-000000000000}",
+                "/sbin/init",
+            ),
+        ];
+        let tree = ProcessTree::build(&entries);
+        let guid = |g: &str| Uuid::parse_str(g).unwrap();
+
+        assert_eq!(
+            tree.roots(),
+            &[guid(TARGET), guid("{5bd6ab47-0005-64d4-0000-000000000000}")]
+        );
+        assert_eq!(tree.get(&guid(TARGET)).unwrap().children, vec![guid(SHELL)]);
+        assert_eq!(tree.get(&guid(SHELL)).unwrap().children, vec![guid(WGET)]);
+        assert_eq!(tree.get(&guid(WGET)).unwrap().image, "/usr/bin/wget");
+        assert!(!tree.is_empty());
+        assert!(ProcessTree::build(&[]).is_empty());
+    }
+}
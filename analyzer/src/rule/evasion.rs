@@ -0,0 +1,473 @@
+// anti-analysis: checks for debuggers, containers and virtual machines, delays and hiding
+
+use super::{
+    command_line_of, created_process, event_detection, image_name, image_of, syscall_detection,
+    Code, DetectionInfo, Severity,
+};
+use crate::{
+    syscall::Syscall,
+    syslog::SyslogEntry,
+    sysmon::{ProcessCreate, SysmonEventData},
+};
+
+// sleeps of at least this many seconds are assumed to outlast the sandbox
+const LONG_SLEEP_SECS: u64 = 60;
+
+// Sysmon reports some paths with the container's rootfs on the host
+const LXC_PATH: &str = "/var/lib/lxc/";
+
+// syscalls whose first string argument is a path that is read or checked for existence
+const PATH_SYSCALLS: &[&str] = &[
+    "open",
+    "openat",
+    "openat2",
+    "access",
+    "faccessat",
+    "faccessat2",
+    "stat",
+    "lstat",
+    "newfstatat",
+    "statx",
+    "readlink",
+    "readlinkat",
+];
+
+// /proc/self/status, /proc/<pid>/status and the same for tasks, which contain TracerPid
+fn is_status_path(path: &str) -> bool {
+    let process = match path
+        .strip_prefix("/proc/")
+        .and_then(|p| p.strip_suffix("/status"))
+    {
+        Some(process) => process,
+        None => return false,
+    };
+
+    return process.split('/').all(|p| {
+        p == "self" || p == "thread-self" || p == "task" || p.chars().all(|c| c.is_ascii_digit())
+    });
+}
+
+fn is_container_path(path: &str) -> bool {
+    return ["/proc/1/cgroup", "/.dockerenv", "/run/.containerenv"].contains(&path);
+}
+
+fn is_dmi_path(path: &str) -> bool {
+    return path.starts_with("/sys/class/dmi") || path.starts_with("/sys/devices/virtual/dmi");
+}
+
+fn probed_path(s: &Syscall) -> Option<String> {
+    if !PATH_SYSCALLS.contains(&s.name.as_str()) {
+        return None;
+    }
+
+    let arg = s.args.iter().find(|a| a.starts_with('"'))?;
+    return Some(arg.trim_end_matches("...").trim_matches('"').to_string());
+}
+
+// first argument of a command line, e.g. "cat /proc/1/cgroup", that is a probed path
+fn probing_argument(p: &ProcessCreate, is_probed: fn(&str) -> bool) -> Option<String> {
+    return command_line_of(p)
+        .split_whitespace()
+        .skip(1)
+        .map(|a| a.trim_matches(|c| c == '"' || c == '\''))
+        .find(|a| is_probed(a))
+        .map(|a| a.to_string());
+}
+
+pub fn tracer_pid(e: &SyslogEntry) -> Option<DetectionInfo> {
+    let p = created_process(e)?;
+
+    if probing_argument(p, is_status_path).is_none() && !command_line_of(p).contains("TracerPid") {
+        return None;
+    }
+
+    return Some(event_detection(
+        e,
+        "Checked TracerPid for a debugger",
+        Code::TracerPid,
+        command_line_of(p),
+        Severity::Medium,
+        15,
+        &["T1622"],
+    ));
+}
+
+pub fn tracer_pid_syscall(s: &Syscall) -> Option<DetectionInfo> {
+    if !is_status_path(&probed_path(s)?) {
+        return None;
+    }
+
+    return Some(syscall_detection(
+        s,
+        "Read its status for TracerPid to check for a debugger",
+        Code::TracerPid,
+        Severity::Medium,
+        15,
+        &["T1622"],
+    ));
+}
+
+pub fn ptrace(s: &Syscall) -> Option<DetectionInfo> {
+    if s.name == "ptrace" && s.field("arg0")? == "PTRACE_TRACEME" {
+        return Some(syscall_detection(
+            s,
+            "Checked for a debugger with ptrace(PTRACE_TRACEME)",
+            Code::Ptrace,
+            Severity::Medium,
+            15,
+            &["T1622"],
+        ));
+    }
+
+    return None;
+}
+
+pub fn container_check(e: &SyslogEntry) -> Option<DetectionInfo> {
+    let p = created_process(e)?;
+    let path = probing_argument(p, is_container_path)?;
+
+    return Some(event_detection(
+        e,
+        &format!("Checked for a container with {}", path),
+        Code::ContainerCheck(path),
+        command_line_of(p),
+        Severity::Medium,
+        15,
+        &["T1497.001"],
+    ));
+}
+
+pub fn container_check_syscall(s: &Syscall) -> Option<DetectionInfo> {
+    let path = probed_path(s)?;
+
+    if !is_container_path(&path) {
+        return None;
+    }
+
+    return Some(syscall_detection(
+        s,
+        &format!("Checked for a container with {}", path),
+        Code::ContainerCheck(path),
+        Severity::Medium,
+        15,
+        &["T1497.001"],
+    ));
+}
+
+pub fn dmi_check(e: &SyslogEntry) -> Option<DetectionInfo> {
+    let p = created_process(e)?;
+    probing_argument(p, is_dmi_path)?;
+
+    return Some(event_detection(
+        e,
+        "Checked the DMI tables for a virtual machine",
+        Code::DmiCheck,
+        command_line_of(p),
+        Severity::Medium,
+        15,
+        &["T1497.001", "T1082"],
+    ));
+}
+
+pub fn dmi_check_syscall(s: &Syscall) -> Option<DetectionInfo> {
+    if !is_dmi_path(&probed_path(s)?) {
+        return None;
+    }
+
+    return Some(syscall_detection(
+        s,
+        "Checked the DMI tables for a virtual machine",
+        Code::DmiCheck,
+        Severity::Medium,
+        15,
+        &["T1497.001", "T1082"],
+    ));
+}
+
+// "30", "1.5m", "2h" or "1d" as accepted by sleep(1)
+fn parse_sleep_duration(arg: &str) -> Option<f64> {
+    let (number, unit) = match arg.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&arg[..i], c),
+        _ => (arg, 's'),
+    };
+    let seconds: f64 = number.parse().ok()?;
+
+    return match unit {
+        's' => Some(seconds),
+        'm' => Some(seconds * 60.0),
+        'h' => Some(seconds * 3600.0),
+        'd' => Some(seconds * 86400.0),
+        _ => None,
+    };
+}
+
+fn long_sleep_detection(seconds: u64) -> Option<(String, Code)> {
+    if seconds < LONG_SLEEP_SECS {
+        return None;
+    }
+
+    return Some((
+        format!("Slept for {} seconds to outlast the sandbox", seconds),
+        Code::LongSleep(seconds),
+    ));
+}
+
+pub fn long_sleep(e: &SyslogEntry) -> Option<DetectionInfo> {
+    let p = created_process(e)?;
+
+    if image_name(image_of(p)) != "sleep" {
+        return None;
+    }
+
+    // sleep adds up all of its arguments
+    let seconds: f64 = command_line_of(p)
+        .split_whitespace()
+        .skip(1)
+        .filter_map(parse_sleep_duration)
+        .sum();
+    let (reason, code) = long_sleep_detection(seconds as u64)?;
+
+    return Some(event_detection(
+        e,
+        &reason,
+        code,
+        command_line_of(p),
+        Severity::Low,
+        10,
+        &["T1497.003"],
+    ));
+}
+
+pub fn long_sleep_syscall(s: &Syscall) -> Option<DetectionInfo> {
+    let request = match s.name.as_str() {
+        "nanosleep" => s.field("arg0")?,
+        // absolute deadlines are not durations
+        "clock_nanosleep" if !s.field("arg1")?.contains("TIMER_ABSTIME") => s.field("arg2")?,
+        _ => return None,
+    };
+
+    let seconds: u64 = request
+        .strip_prefix("{tv_sec=")?
+        .split(',')
+        .next()?
+        .parse()
+        .ok()?;
+    let (reason, code) = long_sleep_detection(seconds)?;
+
+    return Some(syscall_detection(
+        s,
+        &reason,
+        code,
+        Severity::Low,
+        10,
+        &["T1497.003"],
+    ));
+}
+
+// the path in the container with "." and ".." resolved, without " (deleted)" of unlinked executables
+fn guest_path(path: &str) -> String {
+    let path = path.strip_suffix(" (deleted)").unwrap_or(path);
+    let path = path
+        .strip_prefix(LXC_PATH)
+        .and_then(|p| p.split_once('/'))
+        .and_then(|(_, p)| p.strip_prefix("rootfs"))
+        .unwrap_or(path);
+    let mut components = vec![];
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+
+    return format!("/{}", components.join("/"));
+}
+
+pub fn self_delete(e: &SyslogEntry) -> Option<DetectionInfo> {
+    if let SysmonEventData::FileDelete(f) = &e.sysmon_event.data {
+        let image = f.image.as_deref()?;
+
+        if guest_path(image) == guest_path(&f.target_filename) {
+            return Some(event_detection(
+                e,
+                &format!("Deleted its own executable {}", image),
+                Code::SelfDelete(image.to_string()),
+                &f.target_filename,
+                Severity::High,
+                25,
+                &["T1070.004"],
+            ));
+        }
+    }
+
+    return None;
+}
+
+pub fn prctl(s: &Syscall) -> Option<DetectionInfo> {
+    if s.name == "prctl" && s.field("arg0")? == "PR_SET_NAME" {
+        return Some(syscall_detection(
+            s,
+            &format!("Renamed process to {}", s.field("arg1")?),
+            Code::Prctl,
+            Severity::Medium,
+            15,
+            &["T1036.004"],
+        ));
+    }
+
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::{
+        syscall::StraceParser,
+        sysmon::{SysmonEvent, SysmonEventId},
+    };
+
+    fn file_delete(image: &str, target_filename: &str) -> SyslogEntry {
+        let fields = [
+            ("UtcTime", "2023-08-10 12:00:15.000"),
+            ("ProcessGuid", "{5bd6ab47-0002-64d4-0000-000000000000}"),
+            ("ProcessId", "100"),
+            ("Image", image),
+            ("TargetFilename", target_filename),
+            ("Hashes", "SHA256=abcd"),
+            ("User", "root"),
+        ];
+        let sysmon_event = SysmonEvent::from_fields(
+            SysmonEventId::FILE_DELETE_DETECTED,
+            DateTime::parse_from_rfc3339("2023-08-10T12:00:15Z").unwrap(),
+            fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<String, String>>(),
+        );
+
+        return SyslogEntry {
+            log: String::new(),
+            sysmon_event,
+        };
+    }
+
+    fn syscall(line: &str) -> Syscall {
+        return StraceParser::new().parse_line(line).unwrap().unwrap();
+    }
+
+    #[test]
+    fn self_delete_same_path() {
+        let info = self_delete(&file_delete("/root/target.bin", "/root/target.bin")).unwrap();
+
+        assert_eq!(info.code, Code::SelfDelete("/root/target.bin".to_string()));
+        assert_eq!(info.techniques, vec!["T1070.004"]);
+    }
+
+    #[test]
+    fn self_delete_with_rootfs_prefix() {
+        let e = file_delete(
+            "/root/target.bin",
+            "/var/lib/lxc/sandbox-0b1c/rootfs/root/target.bin",
+        );
+
+        assert!(self_delete(&e).is_some());
+    }
+
+    #[test]
+    fn self_delete_with_dot_components() {
+        assert!(self_delete(&file_delete("/root/target.bin", "/root/./target.bin")).is_some());
+        assert!(
+            self_delete(&file_delete("/tmp/../root/target.bin", "/root//target.bin")).is_some()
+        );
+    }
+
+    #[test]
+    fn self_delete_of_unlinked_image() {
+        let e = file_delete("/root/target.bin (deleted)", "/root/target.bin");
+
+        assert!(self_delete(&e).is_some());
+    }
+
+    #[test]
+    fn no_self_delete_of_other_file() {
+        assert!(self_delete(&file_delete("/usr/bin/rm", "/root/target.bin")).is_none());
+        assert!(self_delete(&file_delete("/tmp/root/target.bin", "/root/target.bin")).is_none());
+    }
+
+    #[test]
+    fn prctl_set_name() {
+        let s = syscall("4321  1691668812.101500 prctl(PR_SET_NAME, \"kworker/0:1\") = 0");
+        let info = prctl(&s).unwrap();
+
+        assert_eq!(info.code, Code::Prctl);
+        assert_eq!(info.techniques, vec!["T1036.004"]);
+        assert!(info.reason_for_detection.contains("kworker/0:1"));
+    }
+
+    #[test]
+    fn prctl_other_options() {
+        for line in [
+            "4321  1691668812.101500 prctl(PR_SET_DUMPABLE, SUID_DUMP_DISABLE) = 0",
+            "4321  1691668812.101500 prctl(PR_SET_PDEATHSIG, SIGKILL) = 0",
+            "4321  1691668812.101500 prctl(PR_GET_NAME, \"target.bin\") = 0",
+        ] {
+            assert!(prctl(&syscall(line)).is_none(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn ptrace_traceme() {
+        let s = syscall(
+            "4321  1691668812.101600 ptrace(PTRACE_TRACEME)  = -1 EPERM (Operation not permitted)",
+        );
+
+        assert_eq!(ptrace(&s).unwrap().code, Code::Ptrace);
+        assert!(ptrace(&syscall(
+            "4321  1691668812.101600 ptrace(PTRACE_ATTACH, 1) = 0"
+        ))
+        .is_none());
+    }
+
+    #[test]
+    fn tracer_pid_and_container_probes() {
+        let status = syscall(
+            "4321  1691668812.101700 openat(AT_FDCWD, \"/proc/self/status\", O_RDONLY) = 3",
+        );
+        let cgroup =
+            syscall("4321  1691668812.101800 openat(AT_FDCWD, \"/proc/1/cgroup\", O_RDONLY) = 3");
+        let maps =
+            syscall("4321  1691668812.101900 openat(AT_FDCWD, \"/proc/self/maps\", O_RDONLY) = 3");
+
+        assert!(tracer_pid_syscall(&status).is_some());
+        assert!(container_check_syscall(&cgroup).is_some());
+        assert!(tracer_pid_syscall(&maps).is_none());
+        assert!(container_check_syscall(&maps).is_none());
+    }
+
+    #[test]
+    fn long_sleeps() {
+        let long = syscall("4321  1691668812.160000 nanosleep({tv_sec=3600, tv_nsec=0}, NULL) = 0");
+        let short = syscall("4321  1691668812.160000 nanosleep({tv_sec=1, tv_nsec=0}, NULL) = 0");
+        let absolute = syscall(
+            "4321  1691668812.160000 clock_nanosleep(CLOCK_REALTIME, TIMER_ABSTIME, {tv_sec=1691672412, tv_nsec=0}, NULL) = 0",
+        );
+
+        assert_eq!(
+            long_sleep_syscall(&long).unwrap().code,
+            Code::LongSleep(3600)
+        );
+        assert!(long_sleep_syscall(&short).is_none());
+        assert!(long_sleep_syscall(&absolute).is_none());
+        assert_eq!(parse_sleep_duration("1.5m"), Some(90.0));
+        assert_eq!(parse_sleep_duration("2h"), Some(7200.0));
+        assert_eq!(parse_sleep_duration("x"), None);
+    }
+}
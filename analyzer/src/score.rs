@@ -0,0 +1,211 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::rule::{Correlation, DetectionInfo};
+
+pub const MAX_SCORE: u32 = 100;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Verdict {
+    Benign,
+    Suspicious,
+    Malicious,
+}
+
+impl Verdict {
+    pub fn exit_code(&self) -> i32 {
+        return match self {
+            Self::Benign => 0,
+            Self::Suspicious => 10,
+            Self::Malicious => 20,
+        };
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Benign => "benign",
+            Self::Suspicious => "suspicious",
+            Self::Malicious => "malicious",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    pub suspicious: u32,
+    pub malicious: u32,
+}
+
+#[derive(Debug)]
+pub struct ThreatScore {
+    pub score: u32,
+    pub verdict: Verdict,
+    pub reasons: Vec<String>,
+}
+
+impl ThreatScore {
+    pub fn compute(
+        detection_info: &[DetectionInfo],
+        correlations: &[Correlation],
+        thresholds: &Thresholds,
+    ) -> Self {
+        // each rule contributes once with its heaviest detection,
+        // so a sample deleting many files does not saturate the score by itself
+        let mut rule_weights: BTreeMap<&str, &DetectionInfo> = BTreeMap::new();
+
+        for info in detection_info {
+            let heaviest = rule_weights.entry(info.code.name()).or_insert(info);
+
+            if info.weight > heaviest.weight {
+                *heaviest = info;
+            }
+        }
+
+        let mut total = 0;
+        let mut reasons = vec![];
+
+        for (name, info) in &rule_weights {
+            total += info.weight;
+            reasons.push(format!(
+                "{} (+{}, {:?}): {}",
+                name, info.weight, info.severity, info.reason_for_detection
+            ));
+        }
+
+        for correlation in correlations {
+            total += correlation.bonus;
+            reasons.push(format!(
+                "correlation (+{}, {:?}): {}",
+                correlation.bonus, correlation.severity, correlation.reason
+            ));
+        }
+
+        let score = total.min(MAX_SCORE);
+        let verdict = if score >= thresholds.malicious {
+            Verdict::Malicious
+        } else if score >= thresholds.suspicious {
+            Verdict::Suspicious
+        } else {
+            Verdict::Benign
+        };
+
+        return Self {
+            score,
+            verdict,
+            reasons,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::rule::{Code, Severity};
+
+    const THRESHOLDS: Thresholds = Thresholds {
+        suspicious: 30,
+        malicious: 70,
+    };
+
+    fn detection(code: Code, weight: u32) -> DetectionInfo {
+        return DetectionInfo {
+            event_id: None,
+            time_created: DateTime::parse_from_rfc3339("2023-08-10T12:00:01Z").unwrap(),
+            reason_for_detection: format!("Detected {}", code.name()),
+            code,
+            evidence: String::new(),
+            severity: Severity::Medium,
+            weight,
+            techniques: vec![],
+        };
+    }
+
+    fn correlation(bonus: u32) -> Correlation {
+        return Correlation {
+            reason: "Detected to remove log".to_string(),
+            severity: Severity::High,
+            bonus,
+            techniques: vec![],
+        };
+    }
+
+    #[test]
+    fn benign_without_detections() {
+        let score = ThreatScore::compute(&[], &[], &THRESHOLDS);
+
+        assert_eq!(score.score, 0);
+        assert_eq!(score.verdict, Verdict::Benign);
+        assert!(score.reasons.is_empty());
+    }
+
+    #[test]
+    fn count_each_rule_once_with_its_heaviest_detection() {
+        let info = [
+            detection(Code::Rm(vec!["/root/a".to_string()]), 10),
+            detection(Code::Rm(vec!["/var/log/syslog".to_string()]), 20),
+            detection(Code::Rm(vec!["/root/b".to_string()]), 10),
+            detection(Code::Wget, 5),
+        ];
+        let score = ThreatScore::compute(&info, &[], &THRESHOLDS);
+
+        assert_eq!(score.score, 25);
+        assert_eq!(
+            score.reasons,
+            vec![
+                "rm (+20, Medium): Detected rm",
+                "wget (+5, Medium): Detected wget"
+            ]
+        );
+    }
+
+    #[test]
+    fn add_correlation_bonus() {
+        let info = [detection(Code::Wget, 5), detection(Code::Chmod, 5)];
+        let score = ThreatScore::compute(&info, &[correlation(30)], &THRESHOLDS);
+
+        assert_eq!(score.score, 40);
+        assert_eq!(score.verdict, Verdict::Suspicious);
+        assert_eq!(
+            score.reasons.last().unwrap(),
+            "correlation (+30, High): Detected to remove log"
+        );
+    }
+
+    #[test]
+    fn cap_score() {
+        let info = [
+            detection(Code::Stratum, 30),
+            detection(Code::Miner("xmrig".to_string()), 30),
+            detection(Code::LdPreload("/etc/ld.so.preload".to_string()), 30),
+        ];
+        let score = ThreatScore::compute(&info, &[correlation(40)], &THRESHOLDS);
+
+        assert_eq!(score.score, MAX_SCORE);
+        assert_eq!(score.verdict, Verdict::Malicious);
+    }
+
+    #[test]
+    fn verdict_at_thresholds() {
+        let verdict = |weight| {
+            ThreatScore::compute(&[detection(Code::Wget, weight)], &[], &THRESHOLDS).verdict
+        };
+
+        assert_eq!(verdict(29), Verdict::Benign);
+        assert_eq!(verdict(30), Verdict::Suspicious);
+        assert_eq!(verdict(69), Verdict::Suspicious);
+        assert_eq!(verdict(70), Verdict::Malicious);
+    }
+
+    #[test]
+    fn verdict_exit_codes() {
+        assert_eq!(Verdict::Benign.exit_code(), 0);
+        assert_eq!(Verdict::Suspicious.exit_code(), 10);
+        assert_eq!(Verdict::Malicious.exit_code(), 20);
+        assert_eq!(Verdict::Malicious.to_string(), "malicious");
+    }
+}
@@ -0,0 +1,163 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use crate::sysmon::SysmonEventId;
+
+#[derive(Debug)]
+pub struct ParseFailure {
+    pub record_number: u64,
+    pub raw: String,
+    pub error: String, // full error chain
+}
+
+#[derive(Debug, Default)]
+pub struct ParseStats {
+    pub total_records: u64,
+    pub event_records: u64,
+    pub parsed_events: BTreeMap<SysmonEventId, u64>,
+    pub failures: u64,
+    pub out_of_scope: u64, // parsed events outside of the target's process subtree
+    pub syscalls: u64,
+    pub failure_samples: Vec<ParseFailure>, // first max_failure_samples failures
+    max_failure_samples: usize,
+}
+
+impl ParseStats {
+    pub fn new(max_failure_samples: usize) -> Self {
+        return Self {
+            max_failure_samples,
+            ..Default::default()
+        };
+    }
+
+    pub fn add_record(&mut self, is_sysmon: bool) {
+        self.total_records += 1;
+
+        if is_sysmon {
+            self.event_records += 1;
+        }
+    }
+
+    pub fn add_event(&mut self, event_id: &SysmonEventId) {
+        *self.parsed_events.entry(event_id.clone()).or_insert(0) += 1;
+    }
+
+    pub fn add_syscall(&mut self) {
+        self.syscalls += 1;
+    }
+
+    pub fn add_out_of_scope(&mut self) {
+        self.out_of_scope += 1;
+    }
+
+    pub fn add_failure(&mut self, raw: &str, err: &dyn Display) {
+        self.failures += 1;
+
+        if self.failure_samples.len() < self.max_failure_samples {
+            self.failure_samples.push(ParseFailure {
+                record_number: self.total_records,
+                raw: raw.to_string(),
+                error: format!("{:#}", err),
+            });
+        }
+    }
+
+    pub fn parsed_total(&self) -> u64 {
+        return self.parsed_events.values().sum();
+    }
+
+    pub fn print(&self) {
+        println!("Parse statistics:");
+        println!("  Records: {}", self.total_records);
+        println!("  Event records: {}", self.event_records);
+        println!("  Parsed events: {}", self.parsed_total());
+
+        for (event_id, count) in &self.parsed_events {
+            println!("    {:?}: {}", event_id, count);
+        }
+
+        println!("  Out of scope events: {}", self.out_of_scope);
+        println!("  Syscalls: {}", self.syscalls);
+        println!("  Parse failures: {}", self.failures);
+
+        for failure in &self.failure_samples {
+            println!("    #{}: {}", failure.record_number, failure.error);
+            println!("      {}", failure.raw);
+        }
+
+        if self.failures > self.failure_samples.len() as u64 {
+            println!(
+                "    ... and {} more",
+                self.failures - self.failure_samples.len() as u64
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syscall::StraceParser;
+
+    // the last two lines were cut off by the end of the detonation
+    const TRACE: &str = r#"4321  1691668812.100000 execve("/root/target.bin", ["./target.bin"], 0x7ffd5e3e1a40 /* 10 vars */) = 0
+4321  1691668812.100400 getpid() = 4321
+4321  1691668812.100500 connect(3, {sa_family=AF_INET, sin_port=htons(3333)
+4321  1691668812.100600 ptrace(PTRACE_TRACE"#;
+
+    fn parse_trace(max_failure_samples: usize) -> ParseStats {
+        let mut stats = ParseStats::new(max_failure_samples);
+        let mut parser = StraceParser::new();
+
+        for line in TRACE.lines() {
+            stats.add_record(true);
+
+            match parser.parse_line(line) {
+                Ok(Some(_)) => stats.add_syscall(),
+                Ok(None) => (),
+                Err(err) => stats.add_failure(line, &err),
+            }
+        }
+
+        return stats;
+    }
+
+    #[test]
+    fn count_records_and_events() {
+        let mut stats = ParseStats::new(0);
+        stats.add_record(true);
+        stats.add_event(&SysmonEventId::PROCESS_CREATE);
+        stats.add_record(true);
+        stats.add_event(&SysmonEventId::PROCESS_CREATE);
+        stats.add_record(true);
+        stats.add_event(&SysmonEventId::NETWORK_CONNECT);
+        stats.add_out_of_scope();
+        stats.add_record(false);
+
+        assert_eq!(stats.total_records, 4);
+        assert_eq!(stats.event_records, 3);
+        assert_eq!(stats.parsed_total(), 3);
+        assert_eq!(stats.parsed_events[&SysmonEventId::PROCESS_CREATE], 2);
+        assert_eq!(stats.out_of_scope, 1);
+    }
+
+    #[test]
+    fn keep_first_failure_samples() {
+        let stats = parse_trace(1);
+
+        assert_eq!(stats.total_records, 4);
+        assert_eq!(stats.syscalls, 2);
+        assert_eq!(stats.failures, 2);
+        assert_eq!(stats.failure_samples.len(), 1);
+        assert_eq!(stats.failure_samples[0].record_number, 3);
+        assert!(stats.failure_samples[0].raw.contains("htons(3333)"));
+        assert!(!stats.failure_samples[0].error.is_empty());
+    }
+
+    #[test]
+    fn count_failures_without_samples() {
+        let stats = parse_trace(0);
+
+        assert_eq!(stats.failures, 2);
+        assert!(stats.failure_samples.is_empty());
+    }
+}
@@ -0,0 +1,321 @@
+use std::{collections::HashMap, net::IpAddr};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset};
+use regex::Regex;
+
+use crate::scope::parse_unix_time;
+
+// one line of `strace -f -ttt`, e.g.
+// 1234  1691668812.123456 ptrace(PTRACE_TRACEME) = -1 EPERM (Operation not permitted)
+#[derive(Debug, Clone)]
+pub struct Syscall {
+    pub pid: u32, // thread id with -f
+    pub time: DateTime<FixedOffset>,
+    pub name: String,
+    pub args: Vec<String>, // as decoded by strace
+    pub ret: String,
+    pub error: Option<String>, // errno name, e.g. EPERM
+    pub raw: String,
+}
+
+impl Syscall {
+    // "pid", "name", "args", "argN", "ret" and "error", used by user rules
+    pub fn field(&self, name: &str) -> Option<String> {
+        return match name {
+            "pid" => Some(self.pid.to_string()),
+            "name" => Some(self.name.clone()),
+            "args" => Some(self.args.join(", ")),
+            "ret" => Some(self.ret.clone()),
+            "error" => self.error.clone(),
+            _ => {
+                let i: usize = name.strip_prefix("arg")?.parse().ok()?;
+                self.args.get(i).cloned()
+            }
+        };
+    }
+
+    // address of connect and sendto, e.g.
+    // {sa_family=AF_INET, sin_port=htons(23), sin_addr=inet_addr("192.0.2.1")}
+    pub fn destination(&self) -> Option<(IpAddr, u16)> {
+        let sockaddr = match self.name.as_str() {
+            "connect" => self.args.get(1)?,
+            "sendto" => self.args.get(4)?,
+            _ => return None,
+        };
+
+        let port = sockaddr.split_once("htons(")?.1.split_once(')')?.0;
+        let addr = sockaddr.rsplit_once('"')?.0.rsplit_once('"')?.1;
+
+        return Some((addr.parse().ok()?, port.parse().ok()?));
+    }
+}
+
+// joins calls interrupted by other threads ("<unfinished ...>" and "<... name resumed>")
+#[derive(Debug)]
+pub struct StraceParser {
+    unfinished: HashMap<u32, String>,
+    result_regex: Regex, // strace pads the return value column with spaces
+}
+
+impl Default for StraceParser {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl StraceParser {
+    pub fn new() -> Self {
+        return Self {
+            unfinished: HashMap::new(),
+            result_regex: Regex::new(r"\)\s+= ").unwrap(),
+        };
+    }
+
+    // Ok(None) for signals, exits and unfinished calls
+    pub fn parse_line(&mut self, line: &str) -> Result<Option<Syscall>> {
+        // "[pid 1234] " if strace does not write to a file
+        let line = line.trim_start();
+        let line = match line.strip_prefix("[pid") {
+            Some(rest) => rest.trim_start().replacen(']', "", 1),
+            None => line.to_string(),
+        };
+        let (pid, rest) = line.split_once(' ').context("No pid in strace line")?;
+        let pid: u32 = pid.parse().context("Invalid pid in strace line")?;
+        let rest = rest.trim_start();
+
+        if let Some(call) = rest.strip_suffix("<unfinished ...>") {
+            self.unfinished.insert(pid, call.trim_end().to_string());
+            return Ok(None);
+        }
+
+        let line = match rest.split_once(' ').map(|(_, call)| call) {
+            Some(call) if call.starts_with("<... ") => {
+                let resumed = call
+                    .split_once("resumed>")
+                    .context("Invalid resumed call")?
+                    .1;
+                let start = self
+                    .unfinished
+                    .remove(&pid)
+                    .context("Resumed call was never started")?;
+                format!("{} {}", start, resumed.trim_start())
+            }
+            _ => rest.to_string(),
+        };
+
+        let (time, call) = line.split_once(' ').context("No time in strace line")?;
+
+        // "--- SIGCHLD ... ---" and "+++ exited with 0 +++"
+        if call.starts_with("---") || call.starts_with("+++") {
+            return Ok(None);
+        }
+
+        let open = call.find('(').context("No arguments in strace line")?;
+        let close = self
+            .result_regex
+            .find_iter(&call[open + 1..])
+            .last()
+            .context("No return value in strace line")?;
+        let args = &call[open + 1..open + 1 + close.start()];
+        let result = &call[open + 1 + close.end()..];
+        let (ret, error) = match result.split_once(' ') {
+            Some((ret, error)) => (ret, error.split_whitespace().next()),
+            None => (result, None),
+        };
+
+        return Ok(Some(Syscall {
+            pid,
+            time: parse_unix_time(time)?,
+            name: call[..open].to_string(),
+            args: split_args(args),
+            ret: ret.to_string(),
+            error: error.filter(|e| e.starts_with('E')).map(|e| e.to_string()),
+            raw: format!("{} {}", pid, line),
+        }));
+    }
+}
+
+// splits on commas outside of strings, arrays, structs and nested calls
+fn split_args(args: &str) -> Vec<String> {
+    let mut result = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for c in args.chars() {
+        if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+            current.push(c);
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '[' | '{' | '(' => depth += 1,
+            ']' | '}' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                result.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => (),
+        }
+
+        current.push(c);
+    }
+
+    if !current.trim().is_empty() {
+        result.push(current.trim().to_string());
+    }
+
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // strace -f -ttt -s 256 -o syscalls.log sh -c 'exec ./target.bin 3>&-'
+    const TRACE: &str = r#"4321  1691668812.100000 execve("./target.bin", ["./target.bin"], 0x7ffc8d1d5a38 /* 12 vars */) = 0
+4321  1691668812.100512 brk(NULL)               = 0x55d0c4a3e000
+4321  1691668812.101200 close(3)                = 0
+4321  1691668812.101300 getpid()                = 4321
+4321  1691668812.101400 memfd_create("x", 0)    = 3
+4321  1691668812.101500 prctl(PR_SET_NAME, "kworker/0:1") = 0
+4321  1691668812.101600 ptrace(PTRACE_TRACEME)  = -1 EPERM (Operation not permitted)
+4321  1691668812.102000 clone(child_stack=NULL, flags=CLONE_CHILD_CLEARTID|CLONE_CHILD_SETTID|SIGCHLD, child_tidptr=0x7f1c2b4f0a10) = 4322
+4322  1691668812.102100 connect(3, {sa_family=AF_INET, sin_port=htons(3333), sin_addr=inet_addr("192.0.2.10")}, 16 <unfinished ...>
+4321  1691668812.102200 wait4(-1,  <unfinished ...>
+4322  1691668812.150000 <... connect resumed>) = -1 ECONNREFUSED (Connection refused)
+4322  1691668812.150100 write(1, "done) = 1\n", 10) = 10
+4322  1691668812.150200 exit_group(1)           = ?
+4322  1691668812.150300 +++ exited with 1 +++
+4321  1691668812.150400 <... wait4 resumed>[{WIFEXITED(s) && WEXITSTATUS(s) == 1}], 0, NULL) = 4322
+4321  1691668812.150500 --- SIGCHLD {si_signo=SIGCHLD, si_code=CLD_EXITED, si_pid=4322, si_uid=0, si_status=1, si_utime=0, si_stime=0} ---
+4321  1691668812.160000 nanosleep({tv_sec=3600, tv_nsec=0},  <unfinished ...>
+4321  1691668812.170000 +++ killed by SIGKILL +++"#;
+
+    fn parse(trace: &str) -> Vec<Syscall> {
+        let mut parser = StraceParser::new();
+
+        return trace
+            .lines()
+            .filter_map(|line| parser.parse_line(line).unwrap())
+            .collect();
+    }
+
+    fn find<'a>(syscalls: &'a [Syscall], name: &str) -> &'a Syscall {
+        return syscalls.iter().find(|s| s.name == name).unwrap();
+    }
+
+    #[test]
+    fn parse_padded_short_calls() {
+        let syscalls = parse(TRACE);
+
+        let close = find(&syscalls, "close");
+        assert_eq!(close.args, vec!["3"]);
+        assert_eq!(close.ret, "0");
+
+        let getpid = find(&syscalls, "getpid");
+        assert!(getpid.args.is_empty());
+        assert_eq!(getpid.ret, "4321");
+
+        let memfd_create = find(&syscalls, "memfd_create");
+        assert_eq!(memfd_create.args, vec!["\"x\"", "0"]);
+        assert_eq!(memfd_create.ret, "3");
+        assert_eq!(memfd_create.pid, 4321);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let syscalls = parse(TRACE);
+
+        let ptrace = find(&syscalls, "ptrace");
+        assert_eq!(ptrace.args, vec!["PTRACE_TRACEME"]);
+        assert_eq!(ptrace.ret, "-1");
+        assert_eq!(ptrace.error.as_deref(), Some("EPERM"));
+
+        let exit_group = find(&syscalls, "exit_group");
+        assert_eq!(exit_group.ret, "?");
+        assert_eq!(exit_group.error, None);
+    }
+
+    #[test]
+    fn join_unfinished_and_resumed_calls() {
+        let syscalls = parse(TRACE);
+
+        let connect = find(&syscalls, "connect");
+        assert_eq!(connect.pid, 4322);
+        assert_eq!(connect.args.len(), 3);
+        assert_eq!(connect.error.as_deref(), Some("ECONNREFUSED"));
+        assert_eq!(
+            connect.destination(),
+            Some(("192.0.2.10".parse().unwrap(), 3333))
+        );
+        assert_eq!(connect.time.timestamp_micros(), 1691668812102100);
+
+        let wait4 = find(&syscalls, "wait4");
+        assert_eq!(wait4.args.len(), 4);
+        assert_eq!(wait4.ret, "4322");
+    }
+
+    #[test]
+    fn skip_signals_and_exits() {
+        let names: Vec<String> = parse(TRACE).into_iter().map(|s| s.name).collect();
+
+        assert_eq!(
+            names,
+            vec![
+                "execve",
+                "brk",
+                "close",
+                "getpid",
+                "memfd_create",
+                "prctl",
+                "ptrace",
+                "clone",
+                "connect",
+                "write",
+                "exit_group",
+                "wait4"
+            ]
+        );
+    }
+
+    #[test]
+    fn return_value_after_string_argument() {
+        let syscalls = parse(TRACE);
+        let write = find(&syscalls, "write");
+
+        assert_eq!(write.args, vec!["1", "\"done) = 1\\n\"", "10"]);
+        assert_eq!(write.ret, "10");
+    }
+
+    #[test]
+    fn parse_pid_prefix_of_terminal_output() {
+        let mut parser = StraceParser::new();
+        let syscall = parser
+            .parse_line("[pid  4322] 1691668812.101400 memfd_create(\"x\", MFD_CLOEXEC) = 4")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(syscall.pid, 4322);
+        assert_eq!(syscall.field("arg1").as_deref(), Some("MFD_CLOEXEC"));
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        let mut parser = StraceParser::new();
+
+        assert!(parser.parse_line("strace: Process 4322 attached").is_err());
+        assert!(parser
+            .parse_line("4321  1691668812.100000 <... read resumed>) = 0")
+            .is_err());
+        assert!(parser
+            .parse_line("4321  1691668812.100000 close(3")
+            .is_err());
+    }
+}
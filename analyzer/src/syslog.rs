@@ -1,6 +1,10 @@
-use regex::Regex;
+use anyhow::Result;
 
-use crate::sysmon::SysmonEvent;
+use crate::{
+    collector,
+    logsource::{self, EventPayload, LogFormat, LogSource},
+    sysmon::SysmonEvent,
+};
 
 #[derive(Debug, Clone)]
 pub struct SyslogEntry {
@@ -9,24 +13,128 @@ pub struct SyslogEntry {
 }
 
 impl SyslogEntry {
-    pub fn parse(log: String) -> Option<Self> {
-        let regex =
-            Regex::new(r"[A-Z][a-z]{2}\s+\d+\s\d\d:\d\d:\d\d\s\S+\ssysmon\S*:\s(.+)").unwrap();
-
-        match regex.is_match(&log) {
-            true => (),
-            false => return None,
+    // Ok(None) if the record is an event that has no Sysmon equivalent
+    pub fn parse(log: &str, payload: &EventPayload) -> Result<Option<Self>> {
+        let sysmon_event = match payload {
+            EventPayload::SysmonXml(xml) => Some(SysmonEvent::from_xml(xml)?),
+            EventPayload::Auditd(lines) => collector::auditd_event(lines)?,
+            EventPayload::Ebpf(line) => collector::ebpf_event(line)?,
         };
 
-        let captures = regex.captures(&log).unwrap();
+        return Ok(sysmon_event.map(|sysmon_event| Self {
+            sysmon_event,
+            log: log.to_string(),
+        }));
+    }
+}
 
-        // parse
-        return match SysmonEvent::from_xml(&captures[1]) {
-            Ok(event) => Some(Self {
-                sysmon_event: event,
-                log,
-            }),
-            Err(_) => None,
-        };
+// the events of any Sysmon for Linux log, e.g. /var/log/syslog or the output of journalctl,
+// records of other programs are skipped
+pub struct SyslogReader {
+    source: Box<dyn LogSource>,
+}
+
+impl SyslogReader {
+    pub fn new(source: Box<dyn LogSource>) -> Self {
+        return Self { source };
+    }
+
+    pub fn open(path: &str, format: LogFormat) -> Result<Self> {
+        return Ok(Self::new(logsource::open_file(path, format)?));
+    }
+
+    pub fn format(&self) -> LogFormat {
+        return self.source.format();
+    }
+}
+
+impl Iterator for SyslogReader {
+    type Item = Result<SyslogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let record = match self.source.next_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            };
+
+            let payload = match &record.payload {
+                Some(payload) => payload,
+                None => continue,
+            };
+
+            match SyslogEntry::parse(&record.raw, payload) {
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => continue,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::sysmon::SysmonEventId;
+
+    const EVENT: &str = r#"<Event><System><Provider Name="Linux-Sysmon" Guid="{ff032593-a8d3-4f13-b0d6-01fc615a0f97}"/><EventID>5</EventID><Version>3</Version><Level>4</Level><Task>5</Task><Opcode>0</Opcode><Keywords>0x8000000000000000</Keywords><TimeCreated SystemTime="2023-08-10T12:00:20.000000000Z"/><EventRecordID>9</EventRecordID><Correlation/><Execution ProcessID="100" ThreadID="100"/><Channel>Linux-Sysmon/Operational</Channel><Computer>sandbox</Computer><Security UserId="0"/></System><EventData><Data Name="RuleName">-</Data><Data Name="UtcTime">2023-08-10 12:00:20.000</Data><Data Name="ProcessGuid">{5bd6ab47-0002-64d4-0000-000000000000}</Data><Data Name="ProcessId">100</Data><Data Name="Image">/usr/bin/bash</Data><Data Name="User">root</Data></EventData></Event>"#;
+
+    fn reader(log: &str, format: LogFormat) -> SyslogReader {
+        return SyslogReader::new(
+            logsource::open(Box::new(Cursor::new(log.as_bytes().to_vec())), format).unwrap(),
+        );
+    }
+
+    #[test]
+    fn read_sysmon_events_of_syslog() {
+        let log = format!(
+            "Aug 10 12:00:01 sandbox systemd[1]: Started Session 3 of user root.\n\
+             Aug 10 12:00:20 sandbox sysmon: {}\n",
+            EVENT
+        );
+        let mut reader = reader(&log, LogFormat::Auto);
+
+        assert_eq!(reader.format(), LogFormat::Syslog);
+
+        let entry = reader.next().unwrap().unwrap();
+
+        assert_eq!(
+            entry.sysmon_event.event_id,
+            SysmonEventId::PROCESS_TERMINATE
+        );
+        assert!(entry.log.ends_with(EVENT));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn skip_events_without_sysmon_equivalent() {
+        let log = "\
+1691668810.100000\tclose\t101\t100\t0\tbash\t3
+1691668810.200000\texec\t102\t100\t0\tbash\t/usr/bin/id\tid -u
+";
+        let entries: Vec<SyslogEntry> =
+            reader(log, LogFormat::Ebpf).collect::<Result<_>>().unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].sysmon_event.event_id,
+            SysmonEventId::PROCESS_CREATE
+        );
+    }
+
+    #[test]
+    fn report_invalid_events() {
+        let log = format!(
+            "Aug 10 12:00:20 sandbox sysmon: {}\nAug 10 12:00:21 sandbox sysmon: {}\n",
+            &EVENT[..200],
+            EVENT
+        );
+        let mut reader = reader(&log, LogFormat::Syslog);
+
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().unwrap().is_ok());
     }
 }
@@ -1,13 +1,15 @@
 use std::fmt;
+use std::net::IpAddr;
 use std::num::NonZeroU8;
 use std::{collections::HashMap, str::FromStr};
 
-use anyhow::{Context, Error, Result};
-use chrono::{DateTime, FixedOffset};
+use anyhow::{anyhow, Context, Error, Result};
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
 use roxmltree::Document;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-#[derive(PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, Clone)]
 pub struct SysmonEventId(NonZeroU8);
 
 impl SysmonEventId {
@@ -39,39 +41,45 @@ impl SysmonEventId {
     const fn new_unchecked(n: u8) -> Self {
         Self(unsafe { NonZeroU8::new_unchecked(n) })
     }
-}
 
-impl fmt::Debug for SysmonEventId {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let event_name = match self {
-            &Self::PROCESS_CREATE => "Process Create",
-            &Self::FILE_CREATE_TIME => "File creation time changed",
-            &Self::NETWORK_CONNECT => "Network connection detected",
-            &Self::PROCESS_TERMINATE => "Process terminated",
-            &Self::DRIVER_LOAD => "Driver loaded",
-            &Self::IMAGE_LOAD => "Image loaded",
-            &Self::CREATE_REMOTE_THREAD => "CreateRemoteThread detected",
-            &Self::RAW_ACCESS_READ => "RawAccessRead detected",
-            &Self::PROCESS_ACCESS => "Process accessed",
-            &Self::FILE_CREATE => "File created",
-            &Self::REGISTRY_EVENT_ADD_DELETE => "Registry object added or deleted",
-            &Self::REGISTRY_EVENT_SET => "Registry value set",
-            &Self::REGISTRY_EVENT_RENAME => "Registry object renamed",
-            &Self::FILE_CREATE_STREAM_HASH => "File stream created",
-            &Self::PIPE_EVENT_CREATE => "Pipe Created",
-            &Self::PIPE_EVENT_CONNECT => "Pipe Connected",
-            &Self::WMI_EVENT_FILTER => "WmiEventFilter activity detected",
-            &Self::WMI_EVENT_CONSUMER => "WmiEventConsumer activity detected",
-            &Self::WMI_EVENT_CONSUMER_FILTER => "WmiEventConsumerToFilter activity detected",
-            &Self::DNS_QUERY => "Dns query",
-            &Self::FILE_DELETE => "File Delete archived",
-            &Self::CLIPBOARD_CHANGE => "Clipboard changed",
-            &Self::PROCESS_TAMPERING => "Process Tampering",
-            &Self::FILE_DELETE_DETECTED => "File Delete logged",
+    pub fn number(&self) -> u8 {
+        return u8::from(self.0);
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match *self {
+            Self::PROCESS_CREATE => "Process Create",
+            Self::FILE_CREATE_TIME => "File creation time changed",
+            Self::NETWORK_CONNECT => "Network connection detected",
+            Self::PROCESS_TERMINATE => "Process terminated",
+            Self::DRIVER_LOAD => "Driver loaded",
+            Self::IMAGE_LOAD => "Image loaded",
+            Self::CREATE_REMOTE_THREAD => "CreateRemoteThread detected",
+            Self::RAW_ACCESS_READ => "RawAccessRead detected",
+            Self::PROCESS_ACCESS => "Process accessed",
+            Self::FILE_CREATE => "File created",
+            Self::REGISTRY_EVENT_ADD_DELETE => "Registry object added or deleted",
+            Self::REGISTRY_EVENT_SET => "Registry value set",
+            Self::REGISTRY_EVENT_RENAME => "Registry object renamed",
+            Self::FILE_CREATE_STREAM_HASH => "File stream created",
+            Self::PIPE_EVENT_CREATE => "Pipe Created",
+            Self::PIPE_EVENT_CONNECT => "Pipe Connected",
+            Self::WMI_EVENT_FILTER => "WmiEventFilter activity detected",
+            Self::WMI_EVENT_CONSUMER => "WmiEventConsumer activity detected",
+            Self::WMI_EVENT_CONSUMER_FILTER => "WmiEventConsumerToFilter activity detected",
+            Self::DNS_QUERY => "Dns query",
+            Self::FILE_DELETE => "File Delete archived",
+            Self::CLIPBOARD_CHANGE => "Clipboard changed",
+            Self::PROCESS_TAMPERING => "Process Tampering",
+            Self::FILE_DELETE_DETECTED => "File Delete logged",
             _ => "Unknown event",
         };
+    }
+}
 
-        write!(f, "{} {}", u8::from(self.0), event_name)
+impl fmt::Debug for SysmonEventId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.number(), self.name())
     }
 }
 
@@ -89,7 +97,11 @@ impl FromStr for SysmonEventId {
 pub struct SysmonEvent {
     pub event_id: SysmonEventId,
     pub time_created: DateTime<FixedOffset>,
-    pub event_data: HashMap<String, String>,
+    pub event_data: HashMap<String, String>, // raw EventData
+    pub data: SysmonEventData,
+    // why EventData could not be parsed into data, the event is kept as Other with its raw EventData
+    #[serde(default)]
+    pub data_error: Option<String>,
 }
 
 impl SysmonEvent {
@@ -141,17 +153,522 @@ impl SysmonEvent {
                     node.attribute("Name")
                         .context("EventData/Data has no Name attribute")?
                         .to_string(),
-                    node.text()
-                        .context("EventData/Data has no text")?
-                        .to_string(),
+                    node.text().unwrap_or_default().to_string(),
                 );
             }
         }
 
-        Ok(SysmonEvent {
+        return Ok(Self::from_fields(event_id, time_created, event_data));
+    }
+
+    // event_data uses the Sysmon field names, also used to normalise events of other collectors
+    pub fn from_fields(
+        event_id: SysmonEventId,
+        time_created: DateTime<FixedOffset>,
+        event_data: HashMap<String, String>,
+    ) -> Self {
+        let (data, data_error) = match SysmonEventData::parse(&event_id, &event_data) {
+            Ok(data) => (data, None),
+            Err(err) => (
+                SysmonEventData::Other,
+                Some(format!(
+                    "Failed to parse EventData of {:?}: {:#}",
+                    event_id, err
+                )),
+            ),
+        };
+
+        return SysmonEvent {
             event_id,
             time_created,
             event_data,
-        })
+            data,
+            data_error,
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Hashes {
+    pub md5: Option<String>,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl FromStr for Hashes {
+    type Err = Error;
+
+    // e.g. "SHA1=...,MD5=...,SHA256=..."
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut hashes = Self::default();
+
+        for pair in s.split(',').filter(|p| !p.is_empty() && *p != "-") {
+            let (algorithm, hash) = pair
+                .split_once('=')
+                .with_context(|| format!("Invalid hash \"{}\"", pair))?;
+            let hash = Some(hash.to_lowercase());
+
+            match algorithm.to_uppercase().as_str() {
+                "MD5" => hashes.md5 = hash,
+                "SHA1" => hashes.sha1 = hash,
+                "SHA256" => hashes.sha256 = hash,
+                _ => (),
+            }
+        }
+
+        Ok(hashes)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProcessCreate {
+    pub utc_time: NaiveDateTime,
+    pub process_guid: Uuid,
+    pub process_id: u32,
+    pub image: Option<String>,
+    pub command_line: Option<String>,
+    pub current_directory: Option<String>,
+    pub user: Option<String>,
+    pub logon_guid: Option<Uuid>,
+    pub logon_id: Option<u64>,
+    pub terminal_session_id: Option<u32>,
+    pub integrity_level: Option<String>,
+    pub hashes: Hashes,
+    pub parent_process_guid: Option<Uuid>,
+    pub parent_process_id: Option<u32>,
+    pub parent_image: Option<String>,
+    pub parent_command_line: Option<String>,
+    pub parent_user: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NetworkConnect {
+    pub utc_time: NaiveDateTime,
+    pub process_guid: Uuid,
+    pub process_id: u32,
+    pub image: Option<String>,
+    pub user: Option<String>,
+    pub protocol: Option<String>,
+    pub initiated: bool,
+    pub source_ip: Option<IpAddr>,
+    pub source_hostname: Option<String>,
+    pub source_port: Option<u16>,
+    pub destination_ip: IpAddr,
+    pub destination_hostname: Option<String>,
+    pub destination_port: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProcessTerminate {
+    pub utc_time: NaiveDateTime,
+    pub process_guid: Uuid,
+    pub process_id: u32,
+    pub image: Option<String>,
+    pub user: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawAccessRead {
+    pub utc_time: NaiveDateTime,
+    pub process_guid: Uuid,
+    pub process_id: u32,
+    pub image: Option<String>,
+    pub device: String,
+    pub user: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProcessAccess {
+    pub utc_time: NaiveDateTime,
+    pub source_process_guid: Uuid,
+    pub source_process_id: u32,
+    pub source_thread_id: Option<u32>,
+    pub source_image: Option<String>,
+    pub target_process_guid: Uuid,
+    pub target_process_id: u32,
+    pub target_image: Option<String>,
+    pub granted_access: Option<u32>,
+    pub call_trace: Option<String>,
+    pub source_user: Option<String>,
+    pub target_user: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileCreate {
+    pub utc_time: NaiveDateTime,
+    pub process_guid: Uuid,
+    pub process_id: u32,
+    pub image: Option<String>,
+    pub target_filename: String,
+    pub creation_utc_time: Option<NaiveDateTime>,
+    pub user: Option<String>,
+}
+
+// FILE_DELETE and FILE_DELETE_DETECTED
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileDelete {
+    pub utc_time: NaiveDateTime,
+    pub process_guid: Uuid,
+    pub process_id: u32,
+    pub image: Option<String>,
+    pub target_filename: String,
+    pub hashes: Hashes,
+    pub is_executable: Option<bool>,
+    pub archived: Option<bool>,
+    pub user: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DnsQuery {
+    pub utc_time: NaiveDateTime,
+    pub process_guid: Uuid,
+    pub process_id: u32,
+    pub image: Option<String>,
+    pub query_name: String,
+    pub query_status: Option<String>,
+    pub query_results: Option<String>,
+    pub user: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SysmonEventData {
+    ProcessCreate(ProcessCreate),
+    NetworkConnect(NetworkConnect),
+    ProcessTerminate(ProcessTerminate),
+    RawAccessRead(RawAccessRead),
+    ProcessAccess(ProcessAccess),
+    FileCreate(FileCreate),
+    FileDelete(FileDelete),
+    DnsQuery(DnsQuery),
+    // event types that are not emitted by Sysmon for Linux or whose EventData could not be parsed,
+    // only available as raw EventData
+    Other,
+}
+
+impl SysmonEventData {
+    pub fn parse(event_id: &SysmonEventId, event_data: &HashMap<String, String>) -> Result<Self> {
+        let d = EventDataFields(event_data);
+
+        return Ok(match *event_id {
+            SysmonEventId::PROCESS_CREATE => Self::ProcessCreate(ProcessCreate {
+                utc_time: d.time("UtcTime")?,
+                process_guid: d.guid("ProcessGuid")?,
+                process_id: d.number("ProcessId")?,
+                image: d.opt_string("Image"),
+                command_line: d.opt_string("CommandLine"),
+                current_directory: d.opt_string("CurrentDirectory"),
+                user: d.opt_string("User"),
+                logon_guid: d.opt_guid("LogonGuid")?,
+                logon_id: d.opt_number("LogonId")?,
+                terminal_session_id: d.opt_number("TerminalSessionId")?,
+                integrity_level: d.opt_string("IntegrityLevel"),
+                hashes: d.hashes("Hashes")?,
+                parent_process_guid: d.opt_guid("ParentProcessGuid")?,
+                parent_process_id: d.opt_number("ParentProcessId")?,
+                parent_image: d.opt_string("ParentImage"),
+                parent_command_line: d.opt_string("ParentCommandLine"),
+                parent_user: d.opt_string("ParentUser"),
+            }),
+            SysmonEventId::NETWORK_CONNECT => Self::NetworkConnect(NetworkConnect {
+                utc_time: d.time("UtcTime")?,
+                process_guid: d.guid("ProcessGuid")?,
+                process_id: d.number("ProcessId")?,
+                image: d.opt_string("Image"),
+                user: d.opt_string("User"),
+                protocol: d.opt_string("Protocol"),
+                initiated: d.opt_bool("Initiated")?.unwrap_or(false),
+                source_ip: d.opt_ip("SourceIp")?,
+                source_hostname: d.opt_string("SourceHostname"),
+                source_port: d.opt_number("SourcePort")?,
+                destination_ip: d.ip("DestinationIp")?,
+                destination_hostname: d.opt_string("DestinationHostname"),
+                destination_port: d.number("DestinationPort")?,
+            }),
+            SysmonEventId::PROCESS_TERMINATE => Self::ProcessTerminate(ProcessTerminate {
+                utc_time: d.time("UtcTime")?,
+                process_guid: d.guid("ProcessGuid")?,
+                process_id: d.number("ProcessId")?,
+                image: d.opt_string("Image"),
+                user: d.opt_string("User"),
+            }),
+            SysmonEventId::RAW_ACCESS_READ => Self::RawAccessRead(RawAccessRead {
+                utc_time: d.time("UtcTime")?,
+                process_guid: d.guid("ProcessGuid")?,
+                process_id: d.number("ProcessId")?,
+                image: d.opt_string("Image"),
+                device: d.string("Device")?,
+                user: d.opt_string("User"),
+            }),
+            SysmonEventId::PROCESS_ACCESS => Self::ProcessAccess(ProcessAccess {
+                utc_time: d.time("UtcTime")?,
+                source_process_guid: d.guid("SourceProcessGUID")?,
+                source_process_id: d.number("SourceProcessId")?,
+                source_thread_id: d.opt_number("SourceThreadId")?,
+                source_image: d.opt_string("SourceImage"),
+                target_process_guid: d.guid("TargetProcessGUID")?,
+                target_process_id: d.number("TargetProcessId")?,
+                target_image: d.opt_string("TargetImage"),
+                granted_access: d.opt_number("GrantedAccess")?,
+                call_trace: d.opt_string("CallTrace"),
+                source_user: d.opt_string("SourceUser"),
+                target_user: d.opt_string("TargetUser"),
+            }),
+            SysmonEventId::FILE_CREATE => Self::FileCreate(FileCreate {
+                utc_time: d.time("UtcTime")?,
+                process_guid: d.guid("ProcessGuid")?,
+                process_id: d.number("ProcessId")?,
+                image: d.opt_string("Image"),
+                target_filename: d.string("TargetFilename")?,
+                creation_utc_time: d.opt_time("CreationUtcTime")?,
+                user: d.opt_string("User"),
+            }),
+            SysmonEventId::FILE_DELETE | SysmonEventId::FILE_DELETE_DETECTED => {
+                Self::FileDelete(FileDelete {
+                    utc_time: d.time("UtcTime")?,
+                    process_guid: d.guid("ProcessGuid")?,
+                    process_id: d.number("ProcessId")?,
+                    image: d.opt_string("Image"),
+                    target_filename: d.string("TargetFilename")?,
+                    hashes: d.hashes("Hashes")?,
+                    is_executable: d.opt_bool("IsExecutable")?,
+                    archived: d.opt_bool("Archived")?,
+                    user: d.opt_string("User"),
+                })
+            }
+            SysmonEventId::DNS_QUERY => Self::DnsQuery(DnsQuery {
+                utc_time: d.time("UtcTime")?,
+                process_guid: d.guid("ProcessGuid")?,
+                process_id: d.number("ProcessId")?,
+                image: d.opt_string("Image"),
+                query_name: d.string("QueryName")?,
+                query_status: d.opt_string("QueryStatus"),
+                query_results: d.opt_string("QueryResults"),
+                user: d.opt_string("User"),
+            }),
+            _ => Self::Other,
+        });
+    }
+
+    // guid of the process that caused the event
+    pub fn process_guid(&self) -> Option<Uuid> {
+        return match self {
+            Self::ProcessCreate(p) => Some(p.process_guid),
+            Self::NetworkConnect(n) => Some(n.process_guid),
+            Self::ProcessTerminate(p) => Some(p.process_guid),
+            Self::RawAccessRead(r) => Some(r.process_guid),
+            Self::ProcessAccess(p) => Some(p.source_process_guid),
+            Self::FileCreate(f) => Some(f.process_guid),
+            Self::FileDelete(f) => Some(f.process_guid),
+            Self::DnsQuery(d) => Some(d.process_guid),
+            Self::Other => None,
+        };
+    }
+}
+
+struct EventDataFields<'a>(&'a HashMap<String, String>);
+
+impl EventDataFields<'_> {
+    fn raw(&self, name: &str) -> Option<&str> {
+        // Sysmon writes "-" for fields that have no value
+        return match self.0.get(name).map(|v| v.trim()) {
+            None | Some("") | Some("-") => None,
+            value => value,
+        };
+    }
+
+    fn required(&self, name: &str) -> Result<&str> {
+        return self.raw(name).with_context(|| format!("No {}", name));
+    }
+
+    fn string(&self, name: &str) -> Result<String> {
+        // keep the value as is, empty strings are valid (e.g. TargetFilename of a deleted directory entry)
+        return self
+            .0
+            .get(name)
+            .cloned()
+            .with_context(|| format!("No {}", name));
+    }
+
+    fn opt_string(&self, name: &str) -> Option<String> {
+        return self.raw(name).map(|v| v.to_string());
+    }
+
+    fn number<T: ParseNumber>(&self, name: &str) -> Result<T> {
+        return T::parse_number(self.required(name)?).with_context(|| format!("Invalid {}", name));
+    }
+
+    fn opt_number<T: ParseNumber>(&self, name: &str) -> Result<Option<T>> {
+        return match self.raw(name) {
+            Some(_) => self.number(name).map(Some),
+            None => Ok(None),
+        };
+    }
+
+    fn guid(&self, name: &str) -> Result<Uuid> {
+        return Uuid::parse_str(self.required(name)?).with_context(|| format!("Invalid {}", name));
+    }
+
+    fn opt_guid(&self, name: &str) -> Result<Option<Uuid>> {
+        return match self.raw(name) {
+            Some(_) => self.guid(name).map(Some),
+            None => Ok(None),
+        };
+    }
+
+    fn ip(&self, name: &str) -> Result<IpAddr> {
+        return self
+            .required(name)?
+            .parse::<IpAddr>()
+            .with_context(|| format!("Invalid {}", name));
+    }
+
+    fn opt_ip(&self, name: &str) -> Result<Option<IpAddr>> {
+        return match self.raw(name) {
+            Some(_) => self.ip(name).map(Some),
+            None => Ok(None),
+        };
+    }
+
+    fn opt_bool(&self, name: &str) -> Result<Option<bool>> {
+        return match self.raw(name).map(|v| v.to_lowercase()).as_deref() {
+            None => Ok(None),
+            Some("true") => Ok(Some(true)),
+            Some("false") => Ok(Some(false)),
+            Some(v) => Err(anyhow!("Invalid {} \"{}\"", name, v)),
+        };
+    }
+
+    fn time(&self, name: &str) -> Result<NaiveDateTime> {
+        return NaiveDateTime::parse_from_str(self.required(name)?, "%Y-%m-%d %H:%M:%S%.f")
+            .with_context(|| format!("Invalid {}", name));
+    }
+
+    fn opt_time(&self, name: &str) -> Result<Option<NaiveDateTime>> {
+        return match self.raw(name) {
+            Some(_) => self.time(name).map(Some),
+            None => Ok(None),
+        };
+    }
+
+    fn hashes(&self, name: &str) -> Result<Hashes> {
+        return match self.raw(name) {
+            Some(v) => v.parse::<Hashes>(),
+            None => Ok(Hashes::default()),
+        };
+    }
+}
+
+// decimal or "0x" prefixed hexadecimal, as Sysmon uses both (e.g. GrantedAccess, LogonId)
+trait ParseNumber: Sized {
+    fn parse_number(s: &str) -> Result<Self>;
+}
+
+macro_rules! impl_parse_number {
+    ($($t:ty),*) => {
+        $(
+            impl ParseNumber for $t {
+                fn parse_number(s: &str) -> Result<Self> {
+                    return Ok(match s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+                        Some(hex) => <$t>::from_str_radix(hex, 16)?,
+                        None => s.parse::<$t>()?,
+                    });
+                }
+            }
+        )*
+    };
+}
+
+impl_parse_number!(u16, u32, u64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // as written by Sysmon for Linux 1.2 to syslog, without the syslog header
+    const PROCESS_CREATE: &str = r#"<Event><System><Provider Name="Linux-Sysmon" Guid="{ff032593-a8d3-4f13-b0d6-01fc615a0f97}"/><EventID>1</EventID><Version>5</Version><Level>4</Level><Task>1</Task><Opcode>0</Opcode><Keywords>0x8000000000000000</Keywords><TimeCreated SystemTime="2023-08-10T12:00:01.123456000Z"/><EventRecordID>1</EventRecordID><Correlation/><Execution ProcessID="100" ThreadID="100"/><Channel>Linux-Sysmon/Operational</Channel><Computer>sandbox</Computer><Security UserId="0"/></System><EventData><Data Name="RuleName">-</Data><Data Name="UtcTime">2023-08-10 12:00:01.123</Data><Data Name="ProcessGuid">{5bd6ab47-0001-64d4-0000-000000000000}</Data><Data Name="ProcessId">50</Data><Data Name="Image">/usr/bin/mkdir</Data><Data Name="FileVersion">-</Data><Data Name="Description">-</Data><Data Name="Product">-</Data><Data Name="Company">-</Data><Data Name="OriginalFileName">-</Data><Data Name="CommandLine">mkdir /root/Documents</Data><Data Name="CurrentDirectory">/root</Data><Data Name="User">root</Data><Data Name="LogonGuid">{5bd6ab47-0000-64d4-0000-000000000000}</Data><Data Name="LogonId">0</Data><Data Name="TerminalSessionId">3</Data><Data Name="IntegrityLevel">no level</Data><Data Name="Hashes">SHA256=abcd</Data><Data Name="ParentProcessGuid">{5bd6ab47-0000-64d4-0000-000000000000}</Data><Data Name="ParentProcessId">1</Data><Data Name="ParentImage">/usr/bin/bash</Data><Data Name="ParentCommandLine">bash</Data><Data Name="ParentUser">root</Data></EventData></Event>"#;
+    const NETWORK_CONNECT: &str = r#"<Event><System><Provider Name="Linux-Sysmon" Guid="{ff032593-a8d3-4f13-b0d6-01fc615a0f97}"/><EventID>3</EventID><Version>5</Version><Level>4</Level><Task>3</Task><Opcode>0</Opcode><Keywords>0x8000000000000000</Keywords><TimeCreated SystemTime="2023-08-10T12:00:12.000000000Z"/><EventRecordID>5</EventRecordID><Correlation/><Execution ProcessID="100" ThreadID="100"/><Channel>Linux-Sysmon/Operational</Channel><Computer>sandbox</Computer><Security UserId="0"/></System><EventData><Data Name="RuleName">-</Data><Data Name="UtcTime">2023-08-10 12:00:12.000</Data><Data Name="ProcessGuid">{5bd6ab47-0003-64d4-0000-000000000000}</Data><Data Name="ProcessId">101</Data><Data Name="Image">/usr/bin/wget</Data><Data Name="User">root</Data><Data Name="Protocol">tcp</Data><Data Name="Initiated">true</Data><Data Name="SourceIsIpv6">false</Data><Data Name="SourceIp">10.0.3.100</Data><Data Name="SourceHostname">-</Data><Data Name="SourcePort">45678</Data><Data Name="SourcePortName">-</Data><Data Name="DestinationIsIpv6">false</Data><Data Name="DestinationIp">93.184.216.34</Data><Data Name="DestinationHostname">-</Data><Data Name="DestinationPort">80</Data><Data Name="DestinationPortName">-</Data></EventData></Event>"#;
+
+    #[test]
+    fn parse_process_create() {
+        let event = SysmonEvent::from_xml(PROCESS_CREATE).unwrap();
+
+        assert_eq!(event.event_id, SysmonEventId::PROCESS_CREATE);
+        assert!(event.data_error.is_none());
+
+        match event.data {
+            SysmonEventData::ProcessCreate(p) => {
+                assert_eq!(p.process_id, 50);
+                assert_eq!(p.image.as_deref(), Some("/usr/bin/mkdir"));
+                assert_eq!(p.command_line.as_deref(), Some("mkdir /root/Documents"));
+                assert_eq!(p.parent_process_id, Some(1));
+                assert_eq!(p.hashes.sha256.as_deref(), Some("abcd"));
+            }
+            data => panic!("Unexpected data {:?}", data),
+        }
+    }
+
+    #[test]
+    fn parse_network_connect() {
+        let event = SysmonEvent::from_xml(NETWORK_CONNECT).unwrap();
+
+        match event.data {
+            SysmonEventData::NetworkConnect(n) => {
+                assert!(n.initiated);
+                assert_eq!(n.destination_ip.to_string(), "93.184.216.34");
+                assert_eq!(n.destination_port, 80);
+                assert_eq!(n.destination_hostname, None);
+            }
+            data => panic!("Unexpected data {:?}", data),
+        }
+    }
+
+    #[test]
+    fn keep_raw_event_data_on_typed_failure() {
+        let xml = PROCESS_CREATE.replace(
+            r#"<Data Name="ProcessId">50</Data>"#,
+            r#"<Data Name="ProcessId">fifty</Data>"#,
+        );
+        let event = SysmonEvent::from_xml(&xml).unwrap();
+
+        assert!(matches!(event.data, SysmonEventData::Other));
+        assert!(event.data_error.unwrap().contains("ProcessId"));
+        assert_eq!(event.event_data["ProcessId"], "fifty");
+        assert_eq!(event.event_data["Image"], "/usr/bin/mkdir");
+    }
+
+    #[test]
+    fn parse_partial_process_create() {
+        let xml = PROCESS_CREATE
+            .replace(
+                r#"<Data Name="CommandLine">mkdir /root/Documents</Data>"#,
+                "",
+            )
+            .replace(r#"<Data Name="CurrentDirectory">/root</Data>"#, "");
+        let event = SysmonEvent::from_xml(&xml).unwrap();
+
+        assert!(event.data_error.is_none());
+
+        match event.data {
+            SysmonEventData::ProcessCreate(p) => {
+                assert_eq!(p.image.as_deref(), Some("/usr/bin/mkdir"));
+                assert_eq!(p.command_line, None);
+                assert_eq!(p.current_directory, None);
+            }
+            data => panic!("Unexpected data {:?}", data),
+        }
+    }
+
+    #[test]
+    fn reject_event_without_event_id() {
+        let xml = PROCESS_CREATE.replace("<EventID>1</EventID>", "");
+
+        assert!(SysmonEvent::from_xml(&xml).is_err());
+    }
+
+    #[test]
+    fn parse_hex_numbers() {
+        assert_eq!(u32::parse_number("0x1F0FFF").unwrap(), 0x1F0FFF);
+        assert_eq!(u64::parse_number("42").unwrap(), 42);
+        assert!(u16::parse_number("70000").is_err());
     }
 }
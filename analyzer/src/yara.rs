@@ -0,0 +1,334 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use boreal::{Compiler, MetadataValue, Scanner};
+use chrono::{DateTime, FixedOffset, Utc};
+use common::*;
+
+use crate::{
+    attack,
+    report::collect_files,
+    rule::{Code, DetectionInfo, Severity},
+};
+
+const DEFAULT_WEIGHT: u32 = 30;
+
+#[derive(Debug, Clone)]
+pub struct YaraString {
+    pub name: String,
+    pub offset: usize,
+    pub length: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct YaraMatch {
+    pub file: String, // relative to the result directory
+    pub modified: DateTime<FixedOffset>,
+    pub rule: String,
+    pub namespace: String,
+    pub tags: Vec<String>,
+    pub metadata: Vec<(String, String)>,
+    pub strings: Vec<YaraString>,
+}
+
+impl YaraMatch {
+    fn meta(&self, name: &str) -> Option<&str> {
+        return self
+            .metadata
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str());
+    }
+
+    // "severity", "weight" and "mitre_att" metadata override the defaults of a high severity match
+    pub fn detection(&self) -> DetectionInfo {
+        let severity = match self.meta("severity").map(|s| s.to_lowercase()).as_deref() {
+            Some("info") => Severity::Info,
+            Some("low") => Severity::Low,
+            Some("medium") => Severity::Medium,
+            _ => Severity::High,
+        };
+        let techniques = self
+            .meta("mitre_att")
+            .unwrap_or_default()
+            .split([',', ' '])
+            .filter(|t| attack::is_valid_technique_id(t))
+            .map(|t| t.to_string())
+            .collect();
+        let evidence = self
+            .strings
+            .iter()
+            .map(|s| format!("{}@{:#x}", s.name, s.offset))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        return DetectionInfo {
+            event_id: None,
+            time_created: self.modified,
+            reason_for_detection: format!("YARA rule {} matched {}", self.rule, self.file),
+            code: Code::Yara(self.rule.clone()),
+            evidence: format!("{}: {}", self.file, evidence),
+            severity,
+            weight: self
+                .meta("weight")
+                .and_then(|w| w.parse().ok())
+                .unwrap_or(DEFAULT_WEIGHT),
+            techniques,
+        };
+    }
+}
+
+pub struct YaraScanner {
+    scanner: Scanner,
+}
+
+impl YaraScanner {
+    // a rule file, or a directory of .yar and .yara files each in its own namespace
+    pub fn load(path: &str) -> Result<Self> {
+        let mut compiler = Compiler::new();
+
+        let rule_files = if Path::new(path).is_dir() {
+            let mut files = vec![];
+            collect_files(Path::new(path), &mut files);
+            files.retain(|f| f.extension().is_some_and(|e| e == "yar" || e == "yara"));
+            files
+        } else {
+            vec![PathBuf::from(path)]
+        };
+
+        if rule_files.is_empty() {
+            bail!("No YARA rule files in {}", path);
+        }
+
+        for file in &rule_files {
+            let namespace = file
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            compiler
+                .add_rules_file_in_namespace(file, namespace)
+                .with_context(|| format!("Failed to compile {}", file.display()))?;
+        }
+
+        return Ok(Self {
+            scanner: compiler.finalize(),
+        });
+    }
+
+    pub fn scan_file(&self, path: &Path, name: &str) -> Result<Vec<YaraMatch>> {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", name))?;
+        let modified: DateTime<Utc> = fs::metadata(path)?.modified()?.into();
+        let result = self
+            .scanner
+            .scan_mem(&data)
+            .map_err(|(err, _)| anyhow::anyhow!("Failed to scan {}: {}", name, err))?;
+
+        return Ok(result
+            .rules
+            .iter()
+            .map(|rule| YaraMatch {
+                file: name.to_string(),
+                modified: modified.fixed_offset(),
+                rule: rule.name.to_string(),
+                namespace: rule.namespace.to_string(),
+                tags: rule
+                    .tags
+                    .iter()
+                    .map(|t| self.scanner.get_string_symbol(*t).to_string())
+                    .collect(),
+                metadata: rule
+                    .metadatas
+                    .iter()
+                    .map(|m| {
+                        let value = match m.value {
+                            MetadataValue::Bytes(b) => {
+                                String::from_utf8_lossy(self.scanner.get_bytes_symbol(b))
+                                    .to_string()
+                            }
+                            MetadataValue::Integer(i) => i.to_string(),
+                            MetadataValue::Boolean(b) => b.to_string(),
+                        };
+                        (self.scanner.get_string_symbol(m.name).to_string(), value)
+                    })
+                    .collect(),
+                strings: rule
+                    .matches
+                    .iter()
+                    .flat_map(|s| {
+                        s.matches.iter().map(|m| YaraString {
+                            name: s.name.to_string(),
+                            offset: m.base + m.offset,
+                            length: m.length,
+                        })
+                    })
+                    .collect(),
+            })
+            .collect());
+    }
+
+    // the target, the files it dropped and the memory dumps of its processes
+    pub fn scan_result_dir(&self, target_root_dir: &str) -> Vec<YaraMatch> {
+        let root = Path::new(target_root_dir);
+        let mut files = vec![root.join(TARGETS_DIR_NAME).join(TARGET_FILE_NAME)];
+        collect_files(&root.join(DROPPED_DIR_NAME), &mut files);
+        collect_files(&root.join(MEMORY_DIR_NAME), &mut files);
+        files.retain(|f| *f != root.join(MEMORY_DIR_NAME).join(MEMORY_INDEX_FILE_NAME));
+
+        let mut matches = vec![];
+
+        for file in files {
+            let name = file
+                .strip_prefix(root)
+                .unwrap_or(&file)
+                .to_string_lossy()
+                .to_string();
+
+            match self.scan_file(&file, &name) {
+                Ok(found) => matches.extend(found),
+                Err(err) => println!("YARA: {:#}", err),
+            }
+        }
+
+        return matches;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    const RULES: &str = r#"
+rule xmrig_config {
+    meta:
+        severity = "medium"
+        weight = 25
+        mitre_att = "T1496"
+    strings:
+        $pool = "stratum+tcp://"
+        $algo = "\"algo\": \"rx/0\""
+    condition:
+        any of them
+}
+
+rule upx_packed {
+    strings:
+        $upx = "UPX!"
+    condition:
+        $upx
+}
+"#;
+
+    fn result_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("elf-sandbox-yara-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join(TARGETS_DIR_NAME)).unwrap();
+        fs::create_dir_all(dir.join(DROPPED_DIR_NAME).join("tmp/.x")).unwrap();
+        fs::create_dir_all(dir.join(MEMORY_DIR_NAME).join("4321")).unwrap();
+        fs::write(dir.join("rules.yar"), RULES).unwrap();
+
+        return dir;
+    }
+
+    #[test]
+    fn scan_target_dropped_files_and_memory() {
+        let dir = result_dir("scan");
+        fs::write(
+            dir.join(TARGETS_DIR_NAME).join(TARGET_FILE_NAME),
+            b"\x7fELF\x02\x01\x01\x00UPX!\x0d\x16\x08\x07",
+        )
+        .unwrap();
+        fs::write(
+            dir.join(DROPPED_DIR_NAME).join("tmp/.x/config.json"),
+            r#"{"pools": [{"url": "stratum+tcp://pool.supportxmr.com:3333"}], "algo": "rx/0"}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join(MEMORY_DIR_NAME)
+                .join("4321/7f1c2a000000-7f1c2a021000.dump"),
+            b"\x00\x00UPX!\x00\x00",
+        )
+        .unwrap();
+        // the index names the dumps, it is not memory of the target
+        fs::write(
+            dir.join(MEMORY_DIR_NAME).join(MEMORY_INDEX_FILE_NAME),
+            "# pid\timage\tregion\tpermissions\tsize\tsha256\tfile\nUPX!\n",
+        )
+        .unwrap();
+
+        let scanner = YaraScanner::load(dir.join("rules.yar").to_str().unwrap()).unwrap();
+        let mut matches: Vec<(String, String)> = scanner
+            .scan_result_dir(dir.to_str().unwrap())
+            .into_iter()
+            .map(|m| (m.file, m.rule))
+            .collect();
+        matches.sort();
+
+        assert_eq!(
+            matches,
+            vec![
+                (
+                    "dropped/tmp/.x/config.json".to_string(),
+                    "xmrig_config".to_string()
+                ),
+                (
+                    "memory/4321/7f1c2a000000-7f1c2a021000.dump".to_string(),
+                    "upx_packed".to_string()
+                ),
+                ("targets/target.bin".to_string(), "upx_packed".to_string()),
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn detection_from_metadata() {
+        let dir = result_dir("metadata");
+        let dropped = dir.join(DROPPED_DIR_NAME).join("tmp/.x/config.json");
+        fs::write(&dropped, "-o stratum+tcp://pool.minexmr.com:4444").unwrap();
+
+        let scanner = YaraScanner::load(dir.join("rules.yar").to_str().unwrap()).unwrap();
+        let matches = scanner
+            .scan_file(&dropped, "dropped/tmp/.x/config.json")
+            .unwrap();
+        let info = matches[0].detection();
+
+        assert_eq!(info.code, Code::Yara("xmrig_config".to_string()));
+        assert_eq!(info.severity, Severity::Medium);
+        assert_eq!(info.weight, 25);
+        assert_eq!(info.techniques, vec!["T1496"]);
+        assert_eq!(info.evidence, "dropped/tmp/.x/config.json: pool@0x3");
+
+        let upx = scanner
+            .scan_file(&dir.join("rules.yar"), "rules.yar")
+            .unwrap()
+            .into_iter()
+            .find(|m| m.rule == "upx_packed")
+            .unwrap()
+            .detection();
+        assert_eq!(upx.severity, Severity::High);
+        assert_eq!(upx.weight, DEFAULT_WEIGHT);
+        assert!(upx.techniques.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_rule_directory() {
+        let dir = result_dir("load");
+
+        assert!(YaraScanner::load(dir.to_str().unwrap()).is_ok());
+        assert!(YaraScanner::load(dir.join(TARGETS_DIR_NAME).to_str().unwrap()).is_err());
+
+        fs::write(dir.join("broken.yara"), "rule broken { condition: }").unwrap();
+        assert!(YaraScanner::load(dir.to_str().unwrap()).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,36 @@
+pub mod manifest;
+
+// relative to the working directory of the sandbox, one directory per run
+pub const RESULTS_DIR_PATH: &str = "./sandbox_results";
+// SQLite index of the analyzed runs in the results directory
+pub const INDEX_DATABASE_FILE_NAME: &str = "index.db";
+// one copy of every target in the results directory, named by its sha256
+pub const SAMPLES_DIR_NAME: &str = "samples";
+
 pub const SYSLOG_FILE_NAME: &str = "syslog";
+pub const AUDIT_LOG_FILE_NAME: &str = "audit.log";
+pub const EBPF_LOG_FILE_NAME: &str = "ebpf.log";
+pub const SYSCALL_LOG_FILE_NAME: &str = "syscalls.log";
 pub const TARGETS_DIR_NAME: &str = "targets";
 pub const TARGET_FILE_NAME: &str = "target.bin";
 pub const SETUP_SH_FILE_NAME: &str = "setup.sh";
+pub const DROPPED_DIR_NAME: &str = "dropped";
+pub const MEMORY_DIR_NAME: &str = "memory";
+pub const MEMORY_INDEX_FILE_NAME: &str = "index";
+pub const DETONATION_FILE_NAME: &str = "detonation";
+pub const MANIFEST_FILE_NAME: &str = "manifest";
+// written into the result directory when the daemon analyzes a run
+pub const HTML_REPORT_FILE_NAME: &str = "report.html";
+pub const JSON_REPORT_FILE_NAME: &str = "report.json";
+
+// keys of the detonation marker file, one "key=value" per line
+pub const RUN_ID_MARKER: &str = "elf-sandbox-run";
+pub const STARTED_AT_MARKER: &str = "started_at";
+pub const TARGET_NAME_MARKER: &str = "target_name"; // file name of the sample before it was copied
+pub const TARGET_SHA256_MARKER: &str = "target_sha256";
+// hash of the sandbox configuration, runs of the same target with the same profile are reused
+pub const PROFILE_MARKER: &str = "profile";
+pub const ENDED_AT_MARKER: &str = "ended_at";
+
+// set in the target's environment, so that its processes are found even after they daemonize
+pub const RUN_ID_ENV: &str = "ELF_SANDBOX_RUN";
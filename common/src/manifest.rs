@@ -0,0 +1,251 @@
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::MANIFEST_FILE_NAME;
+
+const MANIFEST_HEADER: &str = "# elf-sandbox manifest v1";
+const CHAIN_PREFIX: &str = "# chain ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub path: String, // relative to the result directory, '/' separated
+    pub sha256: String,
+}
+
+// hashes of every artifact in a result directory, in `sha256sum` format
+// each entry is chained to the previous one so that edited, reordered or removed lines are detected
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+    pub chain: String,
+}
+
+impl Manifest {
+    pub fn create(root: &Path, seed: &str) -> io::Result<Self> {
+        let mut paths = vec![];
+        collect_files(root, root, &mut paths)?;
+        paths.sort();
+
+        let mut entries = vec![];
+
+        for path in paths {
+            if path == MANIFEST_FILE_NAME {
+                continue;
+            }
+
+            entries.push(ManifestEntry {
+                sha256: hash_file(&root.join(&path))?,
+                path,
+            });
+        }
+
+        return Ok(Self {
+            chain: chain(seed, &entries),
+            entries,
+        });
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, message.to_string());
+
+        let mut lines = text.lines();
+
+        if lines.next() != Some(MANIFEST_HEADER) {
+            return Err(invalid("Unknown manifest header"));
+        }
+
+        let mut entries = vec![];
+        let mut chain = None;
+
+        for line in lines {
+            if let Some(hash) = line.strip_prefix(CHAIN_PREFIX) {
+                chain = Some(hash.to_string());
+                continue;
+            }
+
+            let (sha256, path) = line
+                .split_once("  ")
+                .ok_or_else(|| invalid("Invalid manifest entry"))?;
+
+            entries.push(ManifestEntry {
+                path: path.to_string(),
+                sha256: sha256.to_string(),
+            });
+        }
+
+        return Ok(Self {
+            entries,
+            chain: chain.ok_or_else(|| invalid("No chain hash in manifest"))?,
+        });
+    }
+
+    // returns a description of every mismatch, empty if the result directory is intact
+    pub fn verify(&self, root: &Path, seed: &str) -> Vec<String> {
+        let mut problems = vec![];
+
+        if chain(seed, &self.entries) != self.chain {
+            problems.push("manifest: chain hash does not match its entries".to_string());
+        }
+
+        for entry in &self.entries {
+            match hash_file(&root.join(&entry.path)) {
+                Ok(sha256) if sha256 == entry.sha256 => (),
+                Ok(sha256) => problems.push(format!(
+                    "{}: sha256 is {}, expected {}",
+                    entry.path, sha256, entry.sha256
+                )),
+                Err(err) => problems.push(format!("{}: {}", entry.path, err)),
+            }
+        }
+
+        return problems;
+    }
+}
+
+impl std::fmt::Display for Manifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", MANIFEST_HEADER)?;
+
+        for entry in &self.entries {
+            writeln!(f, "{}  {}", entry.sha256, entry.path)?;
+        }
+
+        return writeln!(f, "{}{}", CHAIN_PREFIX, self.chain);
+    }
+}
+
+fn chain(seed: &str, entries: &[ManifestEntry]) -> String {
+    let mut hash = format!("{:x}", Sha256::digest(seed.as_bytes()));
+
+    for entry in entries {
+        let link = format!("{}  {}  {}", hash, entry.sha256, entry.path);
+        hash = format!("{:x}", Sha256::digest(link.as_bytes()));
+    }
+
+    return hash;
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    return Ok(format!("{:x}", hasher.finalize()));
+}
+
+fn collect_files(root: &Path, dir: &Path, paths: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_files(root, &path, paths)?;
+        } else {
+            let relative = path.strip_prefix(root).unwrap();
+            paths.push(
+                relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+            );
+        }
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    const RUN_ID: &str = "0b1c9a9e-2f4d-4c8e-9a51-6f1f4e0a7d21";
+
+    fn result_dir(name: &str) -> std::path::PathBuf {
+        let dir = env::temp_dir().join(format!("elf-sandbox-manifest-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("targets")).unwrap();
+        fs::write(dir.join("syslog"), "Aug 10 12:00:00 sandbox\n").unwrap();
+        fs::write(dir.join("targets/target.bin"), "\x7fELF").unwrap();
+        fs::write(dir.join(MANIFEST_FILE_NAME), "stale").unwrap();
+
+        return dir;
+    }
+
+    #[test]
+    fn create_and_verify() {
+        let dir = result_dir("verify");
+        let manifest = Manifest::create(&dir, RUN_ID).unwrap();
+        let paths: Vec<&str> = manifest.entries.iter().map(|e| e.path.as_str()).collect();
+
+        assert_eq!(paths, vec!["syslog", "targets/target.bin"]);
+        assert_eq!(
+            manifest.entries[1].sha256,
+            "3bdbb4fe8397cd2b842430b39ccff01a8663c751945ef5e9a09e267fb8b1d359"
+        );
+        assert!(manifest.verify(&dir, RUN_ID).is_empty());
+
+        let parsed = Manifest::parse(&manifest.to_string()).unwrap();
+
+        assert_eq!(parsed.entries, manifest.entries);
+        assert_eq!(parsed.chain, manifest.chain);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn detect_tampering() {
+        let dir = result_dir("tampering");
+        let manifest = Manifest::create(&dir, RUN_ID).unwrap();
+
+        // another run's manifest
+        assert_eq!(
+            manifest.verify(&dir, "other"),
+            vec!["manifest: chain hash does not match its entries"]
+        );
+
+        fs::write(dir.join("syslog"), "").unwrap();
+        fs::remove_file(dir.join("targets/target.bin")).unwrap();
+        let problems = manifest.verify(&dir, RUN_ID);
+
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("syslog: sha256 is e3b0c442"));
+        assert!(problems[1].starts_with("targets/target.bin: "));
+
+        // a line removed from the manifest
+        let mut edited = Manifest::parse(&manifest.to_string()).unwrap();
+        edited.entries.remove(0);
+
+        assert_eq!(
+            edited.verify(&dir, RUN_ID)[0],
+            "manifest: chain hash does not match its entries"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_manifests() {
+        let hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+        for (text, error) in [
+            ("", "Unknown manifest header"),
+            ("# elf-sandbox manifest v2\n", "Unknown manifest header"),
+            (
+                &format!("# elf-sandbox manifest v1\n{} syslog\n", hash),
+                "Invalid manifest entry",
+            ),
+            (
+                &format!("# elf-sandbox manifest v1\n{}  syslog\n", hash),
+                "No chain hash in manifest",
+            ),
+        ] {
+            assert_eq!(Manifest::parse(text).unwrap_err().to_string(), error);
+        }
+    }
+}
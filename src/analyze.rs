@@ -0,0 +1,106 @@
+use std::{fmt::Display, iter, path::Path, process::exit};
+
+use analyzer::cli::{self, Arguments};
+use clap::Parser;
+use common::*;
+
+// runs in the foreground and exits with the analyzer's status, e.g. the verdict
+pub fn exit_with<E: Display>(result: Result<i32, E>) -> ! {
+    match result {
+        Ok(code) => exit(code),
+        Err(err) => panic!("{:#}", err),
+    }
+}
+
+// analyzes a run with the arguments of analyze_args
+pub fn analyze_run(
+    result_dir_path: &str,
+    html_report: Option<String>,
+    json_report: Option<String>,
+    index: bool,
+) -> ! {
+    let args = Arguments::parse_from(iter::once("analyzer".to_string()).chain(analyze_args(
+        result_dir_path,
+        html_report,
+        json_report,
+        index,
+    )));
+
+    exit_with(cli::analyze(&args.analyze));
+}
+
+// analyzes a run and writes both reports, by default into its result directory
+pub fn analyze_args(
+    result_dir_path: &str,
+    html_report: Option<String>,
+    json_report: Option<String>,
+    index: bool,
+) -> Vec<String> {
+    let mut args = vec![
+        "--target-root-dir".to_string(),
+        result_dir_path.to_string(),
+        "--html-report".to_string(),
+        html_report.unwrap_or(format!("{}/{}", result_dir_path, HTML_REPORT_FILE_NAME)),
+        "--json-report".to_string(),
+        json_report.unwrap_or(format!("{}/{}", result_dir_path, JSON_REPORT_FILE_NAME)),
+    ];
+
+    // a run analyzed again is already in the index
+    if !index {
+        args.push("--no-index".to_string());
+    }
+
+    return args;
+}
+
+// a result directory, or the id of a run in the results directory
+pub fn resolve_run(run: &str) -> String {
+    if Path::new(run).is_dir() {
+        return run.to_string();
+    }
+
+    return format!("{}/{}", RESULTS_DIR_PATH, run);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn reports_in_result_directory() {
+        assert_eq!(
+            analyze_args("./sandbox_results/run1", None, None, true),
+            vec![
+                "--target-root-dir",
+                "./sandbox_results/run1",
+                "--html-report",
+                "./sandbox_results/run1/report.html",
+                "--json-report",
+                "./sandbox_results/run1/report.json",
+            ]
+        );
+
+        let args = analyze_args(
+            "./sandbox_results/run1",
+            Some("out.html".to_string()),
+            None,
+            false,
+        );
+
+        assert_eq!(args[3], "out.html");
+        assert_eq!(args.last().unwrap(), "--no-index");
+    }
+
+    #[test]
+    fn resolve_run_ids() {
+        let dir = env::temp_dir();
+
+        assert_eq!(resolve_run(dir.to_str().unwrap()), dir.to_str().unwrap());
+        assert_eq!(
+            resolve_run("0b1c9a9e-2f4d-4c8e-9a51-6f1f4e0a7d21"),
+            format!("{}/0b1c9a9e-2f4d-4c8e-9a51-6f1f4e0a7d21", RESULTS_DIR_PATH)
+        );
+    }
+}
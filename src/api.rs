@@ -0,0 +1,524 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    process::{Child, Command, Stdio},
+    thread,
+};
+
+use clap::ValueEnum;
+use common::*;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use uuid::Uuid;
+
+use crate::{
+    collector::CollectorKind,
+    container::NetworkMode,
+    daemon::{value_name, DaemonHandle, Task, TaskState},
+};
+
+// larger uploads are rejected before they are written to the inbox
+const MAX_SAMPLE_SIZE: u64 = 256 * 1024 * 1024;
+const DEFAULT_SAMPLE_NAME: &str = "sample";
+const WORKERS: usize = 4;
+
+// POST /tasks?name=&priority=&timeout=&collectors=&strace=&network=&force= with the ELF as body
+// GET  /tasks, /tasks/<id>, /tasks/<id>/bundle, /tasks/<id>/report.json, /tasks/<id>/report.html
+pub struct Api {
+    server: Server,
+    daemon: DaemonHandle,
+}
+
+impl Api {
+    pub fn new(listen: &str, unix_socket: Option<&str>, daemon: DaemonHandle) -> Self {
+        let server = match unix_socket {
+            Some(path) => Server::http_unix(Path::new(path)),
+            None => Server::http(listen),
+        }
+        .expect("Failed to start the API server");
+
+        println!("API listening on {}", unix_socket.unwrap_or(listen));
+
+        return Self { server, daemon };
+    }
+
+    // uploads and bundles take a while, so requests are handled by a few workers at the same time
+    pub fn run(&self) {
+        thread::scope(|scope| {
+            for _ in 0..WORKERS {
+                scope.spawn(|| {
+                    for request in self.server.incoming_requests() {
+                        self.handle_request(request);
+                    }
+                });
+            }
+        });
+    }
+
+    fn handle_request(&self, mut request: Request) {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        let result = match (request.method(), segments.as_slice()) {
+            (Method::Post, ["tasks"]) => self.submit(&mut request, query),
+            (Method::Get, ["tasks"]) => Ok(self.list_tasks()),
+            (Method::Get, ["tasks", id]) => self
+                .task(id)
+                .map(|(id, task)| json_response(200, task_json(&id, &task))),
+            (Method::Get, ["tasks", id, "bundle"]) => self.bundle(id),
+            (Method::Get, ["tasks", id, "report.json"]) => {
+                self.report(id, JSON_REPORT_FILE_NAME, "application/json")
+            }
+            (Method::Get, ["tasks", id, "report.html"]) => {
+                self.report(id, HTML_REPORT_FILE_NAME, "text/html; charset=utf-8")
+            }
+            _ => Err(error_response(404, "no such endpoint")),
+        };
+
+        let response = match result {
+            Ok(response) | Err(response) => response,
+        };
+
+        if let Err(err) = request.respond(response) {
+            println!("Failed to respond to {}: {}", url, err);
+        }
+    }
+
+    fn submit(&self, request: &mut Request, query: &str) -> ApiResult {
+        let mut options = self.daemon.default_options.clone();
+        let mut name = DEFAULT_SAMPLE_NAME.to_string();
+        let mut priority = 0;
+
+        for (key, value) in parse_query(query) {
+            match key.as_str() {
+                // only the file name, the sample is stored in the inbox
+                "name" => match Path::new(&value).file_name() {
+                    Some(file_name) => name = file_name.to_string_lossy().to_string(),
+                    None => return Err(error_response(400, "invalid name")),
+                },
+                "priority" => priority = parse_value(&key, &value)?,
+                "timeout" => options.timeout = parse_value(&key, &value)?,
+                "strace" => options.strace = parse_value(&key, &value)?,
+                "force" => options.force = parse_value(&key, &value)?,
+                "network" => options.network = parse_enum::<NetworkMode>(&key, &value)?,
+                "collectors" => {
+                    options.collectors = value
+                        .split(',')
+                        .map(|c| parse_enum::<CollectorKind>(&key, c))
+                        .collect::<Result<_, _>>()?
+                }
+                _ => return Err(error_response(400, &format!("unknown option {}", key))),
+            }
+        }
+
+        let mut data = vec![];
+        request
+            .as_reader()
+            .take(MAX_SAMPLE_SIZE + 1)
+            .read_to_end(&mut data)
+            .map_err(|err| error_response(400, &err.to_string()))?;
+
+        if data.is_empty() {
+            return Err(error_response(400, "the request body must be the sample"));
+        }
+        if data.len() as u64 > MAX_SAMPLE_SIZE {
+            return Err(error_response(413, "sample is too large"));
+        }
+
+        let id = self
+            .daemon
+            .submit(&name, &data, priority, options)
+            .map_err(|err| error_response(500, &format!("failed to store sample: {}", err)))?;
+        let (id, task) = self.task(&id.to_string())?;
+
+        return Ok(json_response(201, task_json(&id, &task)));
+    }
+
+    fn list_tasks(&self) -> ApiResponse {
+        let tasks = self.daemon.tasks.lock().unwrap();
+        let tasks: Vec<Value> = tasks.iter().map(|(id, task)| task_json(id, task)).collect();
+
+        return json_response(200, json!(tasks));
+    }
+
+    fn task(&self, id: &str) -> Result<(Uuid, Task), ApiResponse> {
+        let id = Uuid::parse_str(id).map_err(|_| error_response(404, "no such task"))?;
+
+        return match self.daemon.tasks.lock().unwrap().get(&id) {
+            Some(task) => Ok((id, task.clone())),
+            None => Err(error_response(404, "no such task")),
+        };
+    }
+
+    // result directory of a finished task
+    fn result_dir(&self, id: &str) -> Result<String, ApiResponse> {
+        let (_, task) = self.task(id)?;
+
+        return match (task.state, task.result_dir) {
+            (TaskState::Done, Some(result_dir)) => Ok(result_dir),
+            (state, _) => Err(error_response(
+                409,
+                &format!("task is {}, not done", state.name()),
+            )),
+        };
+    }
+
+    fn report(&self, id: &str, file_name: &str, content_type: &str) -> ApiResult {
+        let result_dir = self.result_dir(id)?;
+
+        // runs reused from before the daemon have no reports
+        let file = File::open(Path::new(&result_dir).join(file_name))
+            .map_err(|_| error_response(404, "the run has no report"))?;
+        let response = Response::from_file(file).with_header(header("Content-Type", content_type));
+
+        return Ok(response.boxed());
+    }
+
+    // a gzipped tarball of the result directory, streamed from tar
+    fn bundle(&self, id: &str) -> ApiResult {
+        let result_dir = self.result_dir(id)?;
+        let result_dir = Path::new(&result_dir);
+        let (parent, name) = match (result_dir.parent(), result_dir.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return Err(error_response(500, "invalid result directory")),
+        };
+
+        let tar = Command::new("tar")
+            .arg("-czf")
+            .arg("-")
+            .arg("-C")
+            .arg(parent)
+            .arg(name)
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| error_response(500, &format!("failed to run tar: {}", err)))?;
+
+        let response = Response::new(
+            StatusCode(200),
+            vec![
+                header("Content-Type", "application/gzip"),
+                header(
+                    "Content-Disposition",
+                    &format!("attachment; filename=\"{}.tar.gz\"", name.to_string_lossy()),
+                ),
+            ],
+            Bundle(tar),
+            None,
+            None,
+        );
+
+        return Ok(response.boxed());
+    }
+}
+
+type ApiResponse = Response<Box<dyn Read + Send>>;
+// errors are responses as well
+type ApiResult = Result<ApiResponse, ApiResponse>;
+
+// tar's output, the process is reaped once the response is sent
+struct Bundle(Child);
+
+impl Read for Bundle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        return self.0.stdout.as_mut().unwrap().read(buf);
+    }
+}
+
+impl Drop for Bundle {
+    fn drop(&mut self) {
+        // the client may have disconnected before tar finished
+        self.0.kill().ok();
+        self.0.wait().ok();
+    }
+}
+
+fn task_json(id: &Uuid, task: &Task) -> Value {
+    return json!({
+        "id": id.to_string(),
+        "name": task.name,
+        "state": task.state.name(),
+        "priority": task.priority,
+        "attempts": task.attempts,
+        "options": {
+            "timeout": task.options.timeout,
+            "collectors": task.options.collectors.iter().map(value_name).collect::<Vec<String>>(),
+            "strace": task.options.strace,
+            "network": value_name(&task.options.network),
+            "force": task.options.force,
+        },
+        "verdict": task.verdict,
+        "result_dir": task.result_dir,
+        "error": task.error,
+    });
+}
+
+fn json_response(status: u16, value: Value) -> ApiResponse {
+    let response = Response::from_string(serde_json::to_string_pretty(&value).unwrap())
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"));
+
+    return response.boxed();
+}
+
+fn error_response(status: u16, message: &str) -> ApiResponse {
+    return json_response(status, json!({ "error": message }));
+}
+
+fn header(name: &str, value: &str) -> Header {
+    return Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap();
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, ApiResponse> {
+    return value
+        .parse()
+        .map_err(|_| error_response(400, &format!("invalid {}: {}", key, value)));
+}
+
+fn parse_enum<T: ValueEnum>(key: &str, value: &str) -> Result<T, ApiResponse> {
+    return T::from_str(value, true)
+        .map_err(|_| error_response(400, &format!("invalid {}: {}", key, value)));
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    return query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect();
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = s
+            .get(i + 1..i + 3)
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    return String::from_utf8_lossy(&decoded).to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        io::Write,
+        net::{SocketAddr, TcpStream},
+        process, thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::daemon::{tests::daemon, Daemon};
+
+    // an API on a free port, the daemon is kept so that submissions can be queued
+    fn api(name: &str) -> (Daemon, DaemonHandle, SocketAddr) {
+        let daemon = daemon(name);
+        let handle = daemon.handle();
+        let api = Api::new("127.0.0.1:0", None, daemon.handle());
+        let addr = api.server.server_addr().to_ip().unwrap();
+        thread::spawn(move || api.run());
+
+        return (daemon, handle, addr);
+    }
+
+    fn request(addr: SocketAddr, method: &str, url: &str, body: &[u8]) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+            method,
+            url,
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+
+        return (status, body.to_string());
+    }
+
+    fn json_body(body: &str) -> Value {
+        return serde_json::from_str(body).unwrap();
+    }
+
+    #[test]
+    fn requests_during_upload() {
+        let (_daemon, _handle, addr) = api("api-upload");
+
+        // the rest of the body is never sent, small bodies are read before the request is handled
+        let mut upload = TcpStream::connect(addr).unwrap();
+        write!(
+            upload,
+            "POST /tasks HTTP/1.1\r\nHost: localhost\r\nContent-Length: 65536\r\n\r\n\x7fE"
+        )
+        .unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        let (status, _) = request(addr, "GET", "/tasks", b"");
+
+        assert_eq!(status, 200);
+    }
+
+    #[test]
+    fn submit_and_fetch_tasks() {
+        let (_daemon, handle, addr) = api("api-submit");
+        let (status, body) = request(
+            addr,
+            "POST",
+            "/tasks?name=..%2F..%2Fkinsing.elf&priority=5&collectors=sysmon,auditd&strace=true",
+            b"\x7fELF",
+        );
+        let task = json_body(&body);
+
+        assert_eq!(status, 201);
+        assert_eq!(task["name"], "kinsing.elf");
+        assert_eq!(task["state"], "queued");
+        assert_eq!(task["priority"], 5);
+        assert_eq!(task["options"]["collectors"], json!(["sysmon", "auditd"]));
+        assert_eq!(task["options"]["strace"], true);
+        assert_eq!(task["options"]["network"], "none");
+
+        let id = task["id"].as_str().unwrap();
+        let (status, body) = request(addr, "GET", &format!("/tasks/{}", id), b"");
+
+        assert_eq!(status, 200);
+        assert_eq!(json_body(&body)["id"], id);
+
+        let (status, body) = request(addr, "GET", "/tasks", b"");
+
+        assert_eq!(status, 200);
+        assert_eq!(json_body(&body).as_array().unwrap().len(), 1);
+
+        let (status, body) = request(addr, "GET", &format!("/tasks/{}/report.json", id), b"");
+
+        assert_eq!(status, 409);
+        assert_eq!(json_body(&body)["error"], "task is queued, not done");
+
+        let result_dir = env::temp_dir().join(format!("elf-sandbox-api-run-{}", process::id()));
+        fs::create_dir_all(&result_dir).unwrap();
+        fs::write(result_dir.join(JSON_REPORT_FILE_NAME), "{\"score\": 80}").unwrap();
+        {
+            let mut tasks = handle.tasks.lock().unwrap();
+            let task = tasks.get_mut(&Uuid::parse_str(id).unwrap()).unwrap();
+            task.state = TaskState::Done;
+            task.result_dir = Some(result_dir.to_string_lossy().to_string());
+        }
+
+        assert_eq!(
+            request(addr, "GET", &format!("/tasks/{}/report.json", id), b""),
+            (200, "{\"score\": 80}".to_string())
+        );
+        assert_eq!(
+            request(addr, "GET", &format!("/tasks/{}/report.html", id), b"").0,
+            404
+        );
+
+        fs::remove_dir_all(&result_dir).unwrap();
+        fs::remove_dir_all(
+            env::temp_dir().join(format!("elf-sandbox-daemon-api-submit-{}", process::id())),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn reject_invalid_requests() {
+        let (_daemon, _handle, addr) = api("api-invalid");
+
+        for (method, url, body, status, error) in [
+            (
+                "POST",
+                "/tasks?name=a.elf",
+                &b""[..],
+                400,
+                "the request body must be the sample",
+            ),
+            (
+                "POST",
+                "/tasks?priority=high",
+                b"\x7fELF",
+                400,
+                "invalid priority: high",
+            ),
+            (
+                "POST",
+                "/tasks?network=wifi",
+                b"\x7fELF",
+                400,
+                "invalid network: wifi",
+            ),
+            (
+                "POST",
+                "/tasks?collectors=sysmon,strace",
+                b"\x7fELF",
+                400,
+                "invalid collectors: strace",
+            ),
+            (
+                "POST",
+                "/tasks?memory=1",
+                b"\x7fELF",
+                400,
+                "unknown option memory",
+            ),
+            ("POST", "/tasks?name=..", b"\x7fELF", 400, "invalid name"),
+            ("GET", "/tasks/42", b"", 404, "no such task"),
+            (
+                "GET",
+                "/tasks/0b1c9a9e-2f4d-4c8e-9a51-6f1f4e0a7d21/bundle",
+                b"",
+                404,
+                "no such task",
+            ),
+            ("DELETE", "/tasks", b"", 404, "no such endpoint"),
+        ] {
+            let (actual_status, response) = request(addr, method, url, body);
+
+            assert_eq!(actual_status, status, "{}", url);
+            assert_eq!(json_body(&response)["error"], error, "{}", url);
+        }
+
+        fs::remove_dir_all(
+            env::temp_dir().join(format!("elf-sandbox-daemon-api-invalid-{}", process::id())),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn query_strings() {
+        assert_eq!(
+            parse_query("name=my+sample%2Eelf&force&&timeout=60"),
+            vec![
+                ("name".to_string(), "my sample.elf".to_string()),
+                ("force".to_string(), String::new()),
+                ("timeout".to_string(), "60".to_string()),
+            ]
+        );
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%41"), "%zzA");
+        assert_eq!(percent_decode("%E2%9C%93"), "\u{2713}");
+    }
+}
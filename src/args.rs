@@ -1,8 +1,55 @@
-use clap::Parser;
+use analyzer::cli::{AnalyzeArguments, DiffArguments, SearchArguments};
+use clap::{Args, Parser, Subcommand};
+use uuid::Uuid;
+
+use crate::{collector::CollectorKind, container::NetworkMode};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Arguments {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Detonate a target in a new container
+    Run(RunArguments),
+    /// Analyze a run and print its detections, score and verdict
+    #[command(after_help = "Exit status: 0 = benign, 10 = suspicious, 20 = malicious")]
+    Analyze(Box<AnalyzeArguments>),
+    /// Write the HTML and JSON reports of a run and print its analysis
+    Report(ReportArguments),
+    /// Compare the behaviour of two runs, e.g. with different setup scripts or of two variants
+    #[command(after_help = "Exit status: 0 = same behaviour, 1 = different behaviour")]
+    Diff(DiffArguments),
+    /// Search the index of analyzed runs, conditions are combined with AND
+    Search(Box<SearchArguments>),
+    /// Destroy containers left behind by interrupted runs and remove old results
+    Cleanup(CleanupArguments),
+    /// Download the container image, so that the first run does not wait for it
+    PrepareBase {
+        /// Seconds to wait for the download
+        #[arg(long, default_value_t = 600)]
+        timeout: u64,
+    },
+    /// Detonate and analyze every sample put into an inbox directory
+    Daemon(DaemonArguments),
+    /// Run the daemon with an HTTP API to submit samples and fetch their results
+    Serve(ServeArguments),
+    /// Copy a file of a running container to stdout as it grows, used by the collectors
+    #[command(hide = true)]
+    Follow {
+        /// Host pid of the container's init process
+        #[arg(long)]
+        pid: u32,
+        /// Path of the file in the container
+        path: String,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct RunArguments {
     #[arg(long)]
     pub setup_sh_path: String,
     #[arg(long)]
@@ -11,4 +58,99 @@ pub struct Arguments {
     pub mount_dir_path: String,
     #[arg(long)]
     pub timeout: u64,
+    /// Event collectors to run during detonation
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "sysmon")]
+    pub collectors: Vec<CollectorKind>,
+    /// Trace the target's syscalls with strace
+    #[arg(long)]
+    pub strace: bool,
+    /// Network access of the container while the target runs
+    #[arg(long, value_enum, default_value_t = NetworkMode::Nat)]
+    pub network: NetworkMode,
+    /// Detonate the target even if it was already analyzed with the same profile
+    #[arg(long)]
+    pub force: bool,
+    /// Name of the LXC container, must be unique among sandboxes running at the same time
+    #[arg(long, default_value = "sandbox")]
+    pub container_name: String,
+    /// Id of the run and its result directory instead of a random one
+    #[arg(long)]
+    pub run_id: Option<Uuid>,
+    /// Analyze the run afterwards, print the analysis and write the reports into the result directory
+    #[arg(long)]
+    pub analyze: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ReportArguments {
+    /// Result directory of the run, or its run id
+    pub run: String,
+    /// Path of the HTML report, by default in the result directory
+    #[arg(long)]
+    pub html: Option<String>,
+    /// Path of the JSON report, by default in the result directory
+    #[arg(long)]
+    pub json: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct CleanupArguments {
+    /// Also remove results of runs started more than this many days ago
+    #[arg(long)]
+    pub older_than: Option<u64>,
+    /// Only print what would be removed
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DaemonArguments {
+    /// Directory watched for samples, those in a numbered subdirectory, e.g. inbox/10/, run first.
+    /// A sample is picked up once it is unchanged between two scans, copy it as <name>.part and
+    /// rename it when done to be sure
+    #[arg(long)]
+    pub inbox: String,
+    #[arg(long)]
+    pub setup_sh_path: String,
+    /// Directory under which each job gets its own mount directory
+    #[arg(long)]
+    pub mount_dir_path: String,
+    #[arg(long)]
+    pub timeout: u64,
+    /// Event collectors to run during detonation
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "sysmon")]
+    pub collectors: Vec<CollectorKind>,
+    /// Trace the target's syscalls with strace
+    #[arg(long)]
+    pub strace: bool,
+    /// Network access of the container while the target runs
+    #[arg(long, value_enum, default_value_t = NetworkMode::Nat)]
+    pub network: NetworkMode,
+    /// Detonate samples even if they were already analyzed with the same profile
+    #[arg(long)]
+    pub force: bool,
+    /// Number of sandboxes running at the same time
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub concurrency: u32,
+    /// Number of times a job is retried after the sandbox failed
+    #[arg(long, default_value_t = 3)]
+    pub max_retries: u32,
+    /// Seconds before the first retry, doubled for each further one up to 6 hours
+    #[arg(long, default_value_t = 60)]
+    pub retry_delay: u64,
+    /// Seconds between scans of the inbox
+    #[arg(long, default_value_t = 5)]
+    pub poll_interval: u64,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArguments {
+    #[command(flatten)]
+    pub daemon: DaemonArguments,
+    /// Address the API listens on, only reachable from this host by default
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub listen: String,
+    /// Listen on this unix socket instead of a TCP address
+    #[arg(long)]
+    pub unix_socket: Option<String>,
 }
@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use analyzer::database::{Database, RunSummary};
+
+// the most recent analyzed run of the same target and profile whose result directory still exists
+pub fn find_run(database_path: &Path, target_sha256: &str, profile: &str) -> Option<RunSummary> {
+    // not created before the first run is indexed
+    if !database_path.exists() {
+        return None;
+    }
+
+    let runs = match Database::open(database_path).and_then(|db| db.runs_of(target_sha256, profile))
+    {
+        Ok(runs) => runs,
+        Err(err) => {
+            println!("Failed to look up previous runs: {:#}", err);
+            return None;
+        }
+    };
+
+    return runs.into_iter().find(|run| Path::new(&run.path).is_dir());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use analyzer::{
+        database::RunRecord,
+        ioc::Ioc,
+        score::{ThreatScore, Verdict},
+    };
+    use chrono::DateTime;
+
+    use super::*;
+
+    const SHA256: &str = "3bdbb4fe8397cd2b842430b39ccff01a8663c751945ef5e9a09e267fb8b1d359";
+
+    fn index(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("elf-sandbox-cache-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("run-old")).unwrap();
+        fs::create_dir_all(dir.join("run-new")).unwrap();
+        fs::create_dir_all(dir.join("run-other")).unwrap();
+
+        let mut db = Database::open(&dir.join("index.db")).unwrap();
+        let started_at = DateTime::parse_from_rfc3339("2023-08-10T12:00:00+00:00").unwrap();
+        let iocs = [(
+            Ioc::File {
+                path: "/root/target.bin".to_string(),
+                dropped: false,
+                md5: "d1531b1622de54fe3a0187c3344600e9".to_string(),
+                sha1: "d47cbc8e977ffc6f492483716f00534153677778".to_string(),
+                sha256: SHA256.to_string(),
+            },
+            started_at,
+        )];
+
+        for (run_id, days, profile, verdict) in [
+            ("run-old", 0, "ef01", Verdict::Suspicious),
+            ("run-new", 1, "ef01", Verdict::Malicious),
+            ("run-gone", 2, "ef01", Verdict::Malicious),
+            ("run-other", 3, "a0b1", Verdict::Benign),
+        ] {
+            db.insert_run(&RunRecord {
+                run_id: run_id.to_string(),
+                path: dir.join(run_id).to_string_lossy().to_string(),
+                target_name: Some("target.bin".to_string()),
+                started_at: Some(started_at + chrono::Duration::days(days)),
+                ended_at: None,
+                profile: Some(profile.to_string()),
+                threat_score: &ThreatScore {
+                    score: 80,
+                    verdict,
+                    reasons: vec![],
+                },
+                detection_info: &[],
+                iocs: &iocs,
+                techniques: &[],
+            })
+            .unwrap();
+        }
+
+        return dir;
+    }
+
+    #[test]
+    fn find_latest_existing_run() {
+        let dir = index("runs");
+        let database_path = dir.join("index.db");
+        let run = find_run(&database_path, SHA256, "ef01").unwrap();
+
+        // run-gone was removed by the cleanup, run-other ran with other options
+        assert_eq!(run.run_id, "run-new");
+        assert_eq!(run.verdict, "malicious");
+        assert_eq!(run.score, 80);
+        assert_eq!(
+            find_run(&database_path, SHA256, "a0b1").map(|r| r.run_id),
+            Some("run-other".to_string())
+        );
+        assert!(find_run(&database_path, &SHA256.replace('3', "4"), "ef01").is_none());
+
+        fs::remove_dir_all(dir.join("run-new")).unwrap();
+
+        assert_eq!(
+            find_run(&database_path, SHA256, "ef01").map(|r| r.run_id),
+            Some("run-old".to_string())
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn no_index() {
+        let dir = env::temp_dir().join(format!("elf-sandbox-cache-none-{}", process::id()));
+
+        assert!(find_run(&dir.join("missing.db"), SHA256, "ef01").is_none());
+        assert!(!dir.join("missing.db").exists());
+    }
+}
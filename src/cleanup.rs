@@ -0,0 +1,187 @@
+use std::{
+    fs,
+    os::unix::fs::MetadataExt,
+    path::Path,
+    process::Command,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use common::*;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::{args::CleanupArguments, container};
+
+// the container of a run is "sandbox" by default, "sandbox-<run id>" in the daemon
+const CONTAINER_NAME_PREFIX: &str = "sandbox";
+
+pub fn cleanup(args: CleanupArguments) {
+    destroy_containers(args.dry_run);
+
+    if let Some(days) = args.older_than {
+        let cutoff = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+        remove_results(cutoff, args.dry_run);
+    }
+}
+
+fn destroy_containers(dry_run: bool) {
+    let output = Command::new("sudo")
+        .args(["lxc-ls", "-1"])
+        .output()
+        .expect("Failed to list containers");
+    let names = String::from_utf8_lossy(&output.stdout);
+    let names = names.split_whitespace().filter(|name| {
+        *name == CONTAINER_NAME_PREFIX || name.starts_with(&format!("{}-", CONTAINER_NAME_PREFIX))
+    });
+
+    for name in names {
+        // locked by the sandbox or daemon job that is still using it
+        let _lock = match container::try_lock(name) {
+            Ok(Some(lock)) => lock,
+            Ok(None) => {
+                println!("Container {} is in use, skipping", name);
+                continue;
+            }
+            Err(err) => {
+                println!("Failed to lock container {}: {}", name, err);
+                continue;
+            }
+        };
+
+        println!("Destroying container {}...", name);
+
+        if dry_run {
+            continue;
+        }
+
+        // not running if the run was interrupted after the target finished
+        Command::new("sudo")
+            .args(["lxc-stop", "-k", "-n", name])
+            .status()
+            .ok();
+
+        match Command::new("sudo")
+            .args(["lxc-destroy", "-n", name])
+            .status()
+        {
+            Ok(status) if status.success() => println!("Destroyed container!"),
+            _ => println!("Failed to destroy container {}", name),
+        }
+    }
+}
+
+fn remove_results(cutoff: SystemTime, dry_run: bool) {
+    let entries = match fs::read_dir(RESULTS_DIR_PATH) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let index = Connection::open_with_flags(
+        Path::new(RESULTS_DIR_PATH).join(INDEX_DATABASE_FILE_NAME),
+        OpenFlags::SQLITE_OPEN_READ_WRITE,
+    )
+    .and_then(|connection| {
+        connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+        Ok(connection)
+    })
+    .ok();
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if !path.is_dir() || entry.file_name() == SAMPLES_DIR_NAME {
+            continue;
+        }
+        if started_at(&path).is_some_and(|started_at| started_at >= cutoff) {
+            continue;
+        }
+
+        println!("Removing {}...", path.display());
+
+        if dry_run {
+            continue;
+        }
+
+        // the index stores the absolute path of each run
+        let absolute_path = fs::canonicalize(&path).unwrap_or(path.clone());
+
+        if let Err(err) = fs::remove_dir_all(&path) {
+            println!("Failed to remove {}: {}", path.display(), err);
+            continue;
+        }
+
+        if let Some(index) = &index {
+            index
+                .execute(
+                    "DELETE FROM runs WHERE path = ?1",
+                    [absolute_path.to_string_lossy()],
+                )
+                .ok();
+        }
+    }
+
+    if !dry_run {
+        remove_unused_samples();
+    }
+}
+
+// from the detonation marker, otherwise the time the result directory was last modified
+fn started_at(result_dir: &Path) -> Option<SystemTime> {
+    let marker = fs::read_to_string(result_dir.join(DETONATION_FILE_NAME)).unwrap_or_default();
+    let started_at = marker
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(key, _)| *key == STARTED_AT_MARKER)
+        .and_then(|(_, value)| value.parse::<f64>().ok());
+
+    return match started_at {
+        Some(seconds) => Some(UNIX_EPOCH + Duration::from_secs_f64(seconds)),
+        None => result_dir.metadata().and_then(|m| m.modified()).ok(),
+    };
+}
+
+// samples are hard-linked into each run, a single link is only the stored copy
+fn remove_unused_samples() {
+    let samples_dir_path = Path::new(RESULTS_DIR_PATH).join(SAMPLES_DIR_NAME);
+
+    for entry in fs::read_dir(samples_dir_path)
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        if entry.metadata().is_ok_and(|m| m.nlink() == 1) {
+            println!(
+                "Removing unused sample {}...",
+                entry.file_name().to_string_lossy()
+            );
+            fs::remove_file(entry.path()).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn started_at_from_marker() {
+        let dir = env::temp_dir().join(format!("elf-sandbox-cleanup-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(DETONATION_FILE_NAME),
+            "elf-sandbox-run=0b1c9a9e-2f4d-4c8e-9a51-6f1f4e0a7d21\nstarted_at=1691668800.500000000\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            started_at(&dir),
+            Some(UNIX_EPOCH + Duration::from_millis(1691668800500))
+        );
+
+        // interrupted before the marker was written
+        fs::remove_file(dir.join(DETONATION_FILE_NAME)).unwrap();
+        assert_eq!(started_at(&dir), dir.metadata().unwrap().modified().ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
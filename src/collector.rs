@@ -0,0 +1,269 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use common::*;
+use wait_timeout::ChildExt;
+
+use crate::container::Container;
+
+const STREAM_TIMEOUT_SECS: u64 = 10;
+const AUDIT_LOG_PATH: &str = "/var/log/audit/audit.log";
+const AUDIT_KEY: &str = "elf-sandbox";
+const CGROUP_ROOT_PATH: &str = "/sys/fs/cgroup";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub enum CollectorKind {
+    /// Sysmon for Linux, installed by the setup script
+    Sysmon,
+    /// auditd with execve, connect, unlink and file creation rules
+    Auditd,
+    /// bpftrace on the host, filtered by the container's cgroups
+    Ebpf,
+}
+
+pub trait Collector: fmt::Debug {
+    fn kind(&self) -> CollectorKind;
+
+    // prepares the guest after the setup script has run
+    fn install(&self, _container: &mut Container) {}
+
+    // starts writing events into the result directory
+    fn start(&self, container: &Container, result_dir_path: &str) -> Option<Child>;
+
+    // called after the container is stopped
+    fn stop(&self, stream: Child) {
+        wait_stream(stream);
+    }
+}
+
+pub fn new(kind: CollectorKind) -> Box<dyn Collector> {
+    return match kind {
+        CollectorKind::Sysmon => Box::new(Sysmon),
+        CollectorKind::Auditd => Box::new(Auditd),
+        CollectorKind::Ebpf => Box::new(Ebpf),
+    };
+}
+
+fn create_log_file(result_dir_path: &str, file_name: &str) -> File {
+    return File::create(format!("{}/{}", result_dir_path, file_name))
+        .expect("Failed to create collector log file");
+}
+
+// streams end when the container is stopped
+fn wait_stream(mut stream: Child) {
+    match stream
+        .wait_timeout(Duration::from_secs(STREAM_TIMEOUT_SECS))
+        .expect("Failed to wait for log streaming")
+    {
+        Some(_) => (),
+        None => {
+            println!("Log streaming did not stop, killing it");
+            stream.kill().expect("Failed to kill log streaming");
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Sysmon;
+
+impl Collector for Sysmon {
+    fn kind(&self) -> CollectorKind {
+        return CollectorKind::Sysmon;
+    }
+
+    fn start(&self, container: &Container, result_dir_path: &str) -> Option<Child> {
+        return container.stream_log(create_log_file(result_dir_path, SYSLOG_FILE_NAME));
+    }
+}
+
+#[derive(Debug)]
+struct Auditd;
+
+impl Collector for Auditd {
+    fn kind(&self) -> CollectorKind {
+        return CollectorKind::Auditd;
+    }
+
+    fn install(&self, container: &mut Container) {
+        // only file opens with O_CREAT (0100) are recorded
+        let rules = [
+            "-S execve,execveat",
+            "-S connect",
+            "-S unlink,unlinkat",
+            "-S creat",
+            "-S open -F a1&0100",
+            "-S openat -F a2&0100",
+        ]
+        .iter()
+        .map(|rule| {
+            format!(
+                "auditctl -a always,exit -F arch=b64 {} -k {}",
+                rule, AUDIT_KEY
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(" && ");
+
+        container.attach_shell(&format!(
+            "apt install auditd -y && service auditd start; auditctl -D && {}",
+            rules
+        ));
+    }
+
+    fn start(&self, container: &Container, result_dir_path: &str) -> Option<Child> {
+        return container.follow(
+            AUDIT_LOG_PATH,
+            create_log_file(result_dir_path, AUDIT_LOG_FILE_NAME),
+        );
+    }
+}
+
+#[derive(Debug)]
+struct Ebpf;
+
+// tab separated: time, kind, pid:start time, ppid:start time, uid, comm, then kind specific fields
+const EBPF_SCRIPT: &str = r#"
+tracepoint:syscalls:sys_enter_execve /IN_CONTAINER/ {
+    printf("%s\texec\t%d:%lu\t%d:%lu\t%d\t%s\t%s\t", strftime("%s.%f", nsecs), pid, curtask->group_leader->start_time, curtask->real_parent->tgid, curtask->real_parent->group_leader->start_time, uid, comm, str(args->filename));
+    join(args->argv);
+}
+tracepoint:syscalls:sys_enter_connect /IN_CONTAINER/ {
+    $sa = (struct sockaddr_in *)args->uservaddr;
+    if ($sa->sin_family == 2) {
+        printf("%s\tconnect\t%d:%lu\t%d:%lu\t%d\t%s\t%s\t%d\n", strftime("%s.%f", nsecs), pid, curtask->group_leader->start_time, curtask->real_parent->tgid, curtask->real_parent->group_leader->start_time, uid, comm, ntop(2, $sa->sin_addr.s_addr), (($sa->sin_port & 0xff) << 8) | ($sa->sin_port >> 8));
+    }
+}
+tracepoint:syscalls:sys_enter_unlink /IN_CONTAINER/ {
+    printf("%s\tunlink\t%d:%lu\t%d:%lu\t%d\t%s\t%s\n", strftime("%s.%f", nsecs), pid, curtask->group_leader->start_time, curtask->real_parent->tgid, curtask->real_parent->group_leader->start_time, uid, comm, str(args->pathname));
+}
+tracepoint:syscalls:sys_enter_unlinkat /IN_CONTAINER/ {
+    printf("%s\tunlink\t%d:%lu\t%d:%lu\t%d\t%s\t%s\n", strftime("%s.%f", nsecs), pid, curtask->group_leader->start_time, curtask->real_parent->tgid, curtask->real_parent->group_leader->start_time, uid, comm, str(args->pathname));
+}
+tracepoint:syscalls:sys_enter_openat /IN_CONTAINER && (args->flags & 64)/ {
+    printf("%s\tcreate\t%d:%lu\t%d:%lu\t%d\t%s\t%s\n", strftime("%s.%f", nsecs), pid, curtask->group_leader->start_time, curtask->real_parent->tgid, curtask->real_parent->group_leader->start_time, uid, comm, str(args->filename));
+}
+"#;
+
+impl Collector for Ebpf {
+    fn kind(&self) -> CollectorKind {
+        return CollectorKind::Ebpf;
+    }
+
+    fn start(&self, container: &Container, result_dir_path: &str) -> Option<Child> {
+        // everything in the container runs in its cgroup or one below it, e.g. init.scope
+        let cgroup_ids = container
+            .init_pid()
+            .and_then(container_cgroup)
+            .and_then(|path| cgroup_ids(&path).ok());
+
+        let cgroup_ids = match cgroup_ids {
+            Some(ids) => ids,
+            None => {
+                println!("Failed to find the cgroups of the container, eBPF collector is disabled");
+                return None;
+            }
+        };
+
+        println!(
+            "Tracing {} cgroups of the container with bpftrace...",
+            cgroup_ids.len()
+        );
+
+        // not run through sudo so that it can be interrupted on stop
+        let child = Command::new("bpftrace")
+            .args([
+                "-B",
+                "line",
+                "-e",
+                &EBPF_SCRIPT.replace("IN_CONTAINER", &cgroup_filter(&cgroup_ids)),
+            ])
+            .stdout(Stdio::from(create_log_file(
+                result_dir_path,
+                EBPF_LOG_FILE_NAME,
+            )))
+            .spawn()
+            .expect("Failed to start bpftrace");
+
+        return Some(child);
+    }
+
+    fn stop(&self, stream: Child) {
+        // bpftrace runs until interrupted
+        Command::new("kill")
+            .args(["-INT", &stream.id().to_string()])
+            .status()
+            .expect("Failed to interrupt bpftrace");
+
+        wait_stream(stream);
+    }
+}
+
+// the cgroup v2 of the container's init process, without its init.scope
+fn container_cgroup(init_pid: u32) -> Option<PathBuf> {
+    let cgroups = fs::read_to_string(format!("/proc/{}/cgroup", init_pid)).ok()?;
+    let path = cgroups.lines().find_map(|l| l.strip_prefix("0::"))?;
+    let path = path.strip_suffix("/init.scope").unwrap_or(path);
+
+    return Some(Path::new(CGROUP_ROOT_PATH).join(path.trim_start_matches('/')));
+}
+
+// ids of a cgroup and the ones below it, bpftrace's cgroup is the inode of its directory
+fn cgroup_ids(path: &Path) -> io::Result<Vec<u64>> {
+    let mut ids = vec![];
+    let mut dirs = vec![path.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        ids.push(fs::metadata(&dir)?.ino());
+
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+
+            if entry.file_type()?.is_dir() {
+                dirs.push(entry.path());
+            }
+        }
+    }
+
+    return Ok(ids);
+}
+
+fn cgroup_filter(ids: &[u64]) -> String {
+    let conditions: Vec<String> = ids.iter().map(|id| format!("cgroup == {}", id)).collect();
+
+    return format!("({})", conditions.join(" || "));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn cgroups_of_container() {
+        let dir = env::temp_dir().join(format!("elf-sandbox-cgroup-{}", process::id()));
+        fs::create_dir_all(dir.join("init.scope")).unwrap();
+        fs::create_dir_all(dir.join(".lxc/user.slice")).unwrap();
+        fs::write(dir.join("cgroup.procs"), "1\n").unwrap();
+
+        let mut ids = cgroup_ids(&dir).unwrap();
+        ids.sort();
+        let mut expected: Vec<u64> = ["", "init.scope", ".lxc", ".lxc/user.slice"]
+            .iter()
+            .map(|p| fs::metadata(dir.join(p)).unwrap().ino())
+            .collect();
+        expected.sort();
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(ids, expected);
+        assert_eq!(cgroup_filter(&[7, 8]), "(cgroup == 7 || cgroup == 8)");
+    }
+}
@@ -1,7 +1,18 @@
+use clap::ValueEnum;
 use common::*;
-use std::{fs::OpenOptions, io::Write, process::Command, time::Duration};
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    os::unix::io::AsRawFd,
+    path::Path,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
 use wait_timeout::ChildExt;
 
+use crate::follow;
+
 const PV_LXC_PATH: &str = "/var/lib/lxc";
 const SYSLOG_PATH: &str = "/var/log/syslog";
 
@@ -11,6 +22,14 @@ enum CommandResult {
     TimedOut,
 }
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub enum NetworkMode {
+    /// Connected to the internet through the host's NAT bridge
+    Nat,
+    /// No network, the interface is taken down before the target runs
+    None,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum ContainerState {
     NotExist,
@@ -28,6 +47,33 @@ pub struct Container {
     state: ContainerState,
     timeout: u64,
     pub mount_root_path: String,
+    lock: Option<File>, // held from the creation of the container until it is destroyed
+}
+
+// a lock on the container's name, None if another process holds it, e.g. a sandbox using it
+pub fn try_lock(container_name: &str) -> io::Result<Option<File>> {
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path(container_name))?;
+
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let err = io::Error::last_os_error();
+
+        return match err.kind() {
+            io::ErrorKind::WouldBlock => Ok(None),
+            _ => Err(err),
+        };
+    }
+
+    return Ok(Some(lock));
+}
+
+// next to the container's directory, kept when the container is destroyed so that every process
+// locks the same file
+fn lock_path(container_name: &str) -> String {
+    return format!("{}/{}.lock", PV_LXC_PATH, container_name);
 }
 
 impl Container {
@@ -47,6 +93,7 @@ impl Container {
             state: ContainerState::NotExist,
             timeout,
             mount_root_path,
+            lock: None,
         };
     }
 
@@ -56,6 +103,15 @@ impl Container {
             _ => panic!("Container already exists"),
         }
 
+        self.lock = match try_lock(&self.container_name) {
+            Ok(Some(lock)) => Some(lock),
+            Ok(None) => panic!(
+                "Container {} is used by another sandbox",
+                self.container_name
+            ),
+            Err(err) => panic!("Failed to lock container: {}", err),
+        };
+
         println!(
             "Creating container ({}-{}-{})...",
             self.distribution, self.release, self.arch
@@ -149,7 +205,17 @@ impl Container {
         );
     }
 
-    pub fn execute_target(&mut self) {
+    // everything the target needs is set up before the guest filesystem is snapshotted
+    pub fn prepare_target(&mut self, strace: bool, network: NetworkMode) {
+        if strace {
+            self.attach_shell("command -v strace || apt install strace -y");
+        }
+
+        // after everything is installed, the setup script and collectors may need the network
+        if network == NetworkMode::None {
+            self.attach("ip link set eth0 down");
+        }
+
         self.attach(&format!(
             "chmod +x {}/{}",
             self.mount_root_path, TARGET_FILE_NAME
@@ -159,19 +225,42 @@ impl Container {
             "cp {}/{} /root/{}",
             self.mount_root_path, TARGET_FILE_NAME, TARGET_FILE_NAME
         ));
+    }
 
-        self.exec_command(
-            "sudo",
-            &[
-                "lxc-attach",
-                "-n",
-                &self.container_name,
-                "--",
-                "bash",
-                "-c",
-                &format!("cd /root && ./{}", TARGET_FILE_NAME),
-            ],
-        );
+    // run_id is embedded in the launcher command line so that the analyzer can find the target
+    // with a syscall log, strace writes it through lxc-attach's stderr, not into a file of the guest
+    pub fn execute_target(&mut self, run_id: &str, syscall_log: Option<File>) {
+        // fd 3 is the syscall log, closed before the target is executed
+        let tracer = if syscall_log.is_some() {
+            "strace -f -ttt -s 256 -o /dev/fd/3 sh -c 'exec ./TARGET 3>&-' 3>&2 2>/dev/null"
+                .replace("TARGET", TARGET_FILE_NAME)
+        } else {
+            format!("./{}", TARGET_FILE_NAME)
+        };
+
+        let mut command = Command::new("sudo");
+        command.args([
+            "lxc-attach",
+            "-n",
+            &self.container_name,
+            "--",
+            "bash",
+            "-c",
+            &format!(
+                "cd /root && {}={} {}; : {}={}",
+                RUN_ID_ENV, run_id, tracer, RUN_ID_MARKER, run_id
+            ),
+        ]);
+
+        // without a terminal, lxc-attach hands its stdio to the command instead of a pty
+        if let Some(syscall_log) = syscall_log {
+            command
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::from(syscall_log));
+        }
+
+        self.wait(command.spawn().expect("Failed to execute target"));
     }
 
     pub fn attach(&mut self, command: &str) {
@@ -188,17 +277,138 @@ impl Container {
         let mut args = vec!["lxc-attach", "-n", &self.container_name, "--"];
         args.extend(command.split(" "));
 
-        match self.exec_command("sudo", &args) {
-            CommandResult::TimedOut => {
-                self.stop();
+        if let CommandResult::TimedOut = self.exec_command("sudo", &args) {
+            self.stop();
+            return;
+        }
+
+        println!("Attached!");
+    }
+
+    pub fn attach_shell(&mut self, script: &str) {
+        match self.state {
+            ContainerState::Running => (),
+            _ => {
+                println!("Container is not running");
                 return;
             }
-            _ => (),
+        }
+
+        println!("Attaching with shell \"{}\"...", script);
+
+        let args = [
+            "lxc-attach",
+            "-n",
+            &self.container_name,
+            "--",
+            "sh",
+            "-c",
+            script,
+        ];
+
+        if let CommandResult::TimedOut = self.exec_command("sudo", &args) {
+            self.stop();
+            return;
         }
 
         println!("Attached!");
     }
 
+    // streams the guest log into output until the container is stopped
+    pub fn stream_log(&self, output: File) -> Option<Child> {
+        if self
+            .init_pid()
+            .is_some_and(|pid| follow::open_in_root(pid, Path::new(SYSLOG_PATH)).is_ok())
+        {
+            return self.follow(SYSLOG_PATH, output);
+        }
+
+        // the journal is binary, only the guest's journalctl can follow it
+        return self.stream(
+            "exec journalctl -f -o export --lines=all --no-pager",
+            output,
+        );
+    }
+
+    // copies a guest file into output as it grows until the container is stopped,
+    // from a host process that the guest can not see or kill
+    pub fn follow(&self, path: &str, output: File) -> Option<Child> {
+        let init_pid = match self.init_pid() {
+            Some(pid) => pid,
+            None => {
+                println!("Container is not running");
+                return None;
+            }
+        };
+
+        println!("Following {}...", path);
+
+        let child = Command::new(env::current_exe().expect("Failed to find sandbox binary"))
+            .args(["follow", "--pid", &init_pid.to_string(), path])
+            .stdout(Stdio::from(output))
+            .spawn()
+            .expect("Failed to start following");
+
+        return Some(child);
+    }
+
+    // runs script in the guest with its output written to the host until the container is stopped
+    pub fn stream(&self, script: &str, output: File) -> Option<Child> {
+        match self.state {
+            ContainerState::Running => (),
+            _ => {
+                println!("Container is not running");
+                return None;
+            }
+        }
+
+        println!("Streaming \"{}\"...", script);
+
+        let child = Command::new("sudo")
+            .args([
+                "lxc-attach",
+                "-n",
+                &self.container_name,
+                "--",
+                "sh",
+                "-c",
+                script,
+            ])
+            .stdout(Stdio::from(output))
+            .spawn()
+            .expect("Failed to start streaming");
+
+        return Some(child);
+    }
+
+    // host pid of the container's init process
+    pub fn init_pid(&self) -> Option<u32> {
+        let output = Command::new("sudo")
+            .args(["lxc-info", "-n", &self.container_name, "-p", "-H"])
+            .output()
+            .ok()?;
+
+        return String::from_utf8_lossy(&output.stdout).trim().parse().ok();
+    }
+
+    // stops every process of the container until it is thawed, e.g. while its memory is read
+    pub fn freeze(&self) -> bool {
+        return match self.exec_command("sudo", &["lxc-freeze", "-n", &self.container_name]) {
+            CommandResult::Ok => true,
+            _ => {
+                println!("Failed to freeze container");
+                false
+            }
+        };
+    }
+
+    pub fn unfreeze(&self) {
+        match self.exec_command("sudo", &["lxc-unfreeze", "-n", &self.container_name]) {
+            CommandResult::Ok => (),
+            _ => println!("Failed to unfreeze container"),
+        }
+    }
+
     pub fn stop(&mut self) {
         match self.state {
             ContainerState::Running => (),
@@ -212,10 +422,10 @@ impl Container {
             }
         }
 
-        // copy syslog
+        // copy syslog, or export the journal if the guest has no syslog daemon
         // TODO: if failed to copy, enter to loop of container stopping
-        self.attach(&format!(
-            "cp {} {}/{}",
+        self.attach_shell(&format!(
+            "if [ -f {0} ]; then cp {0} {1}/{2}; else journalctl -o export --no-pager > {1}/{2}; fi",
             SYSLOG_PATH, self.mount_root_path, SYSLOG_FILE_NAME
         ));
 
@@ -251,6 +461,7 @@ impl Container {
             CommandResult::Ok => {
                 println!("Destroyed container!");
                 self.state = ContainerState::NotExist;
+                self.lock = None;
             }
             _ => {
                 println!("Failed to destroy container");
@@ -259,8 +470,10 @@ impl Container {
     }
 
     fn exec_command(&self, program: &str, args: &[&str]) -> CommandResult {
-        let mut child = Command::new(program).args(args).spawn().unwrap();
+        return self.wait(Command::new(program).args(args).spawn().unwrap());
+    }
 
+    fn wait(&self, mut child: Child) -> CommandResult {
         let status_code = match child
             .wait_timeout(Duration::from_secs(self.timeout))
             .unwrap()
@@ -0,0 +1,749 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use common::*;
+use uuid::Uuid;
+
+use crate::{
+    analyze,
+    args::DaemonArguments,
+    cache,
+    collector::CollectorKind,
+    container::NetworkMode,
+    sandbox::{self, ARCH, DISTRIBUTION, RELEASE},
+};
+
+const RUNNING_DIR_NAME: &str = "running";
+const DONE_DIR_NAME: &str = "done";
+const FAILED_DIR_NAME: &str = "failed";
+const LOGS_DIR_NAME: &str = "logs";
+// samples still being written into the inbox, renamed to the sample's name once complete
+const PARTIAL_EXTENSION: &str = "part";
+const MAX_RETRY_DELAY_SECS: u64 = 6 * 60 * 60;
+// exit status of the analyzer for each verdict
+const ANALYZER_VERDICTS: &[(i32, &str)] = &[(0, "benign"), (10, "suspicious"), (20, "malicious")];
+
+// how a sample is detonated, the daemon's arguments unless submitted with others
+#[derive(Debug, Clone)]
+pub struct JobOptions {
+    pub timeout: u64,
+    pub collectors: Vec<CollectorKind>,
+    pub strace: bool,
+    pub network: NetworkMode,
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TaskState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+impl TaskState {
+    pub fn name(&self) -> &str {
+        return match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Done => "done",
+            Self::Failed => "failed",
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Task {
+    pub name: String,
+    pub priority: i64,
+    pub options: JobOptions,
+    pub state: TaskState,
+    pub attempts: u32,
+    pub result_dir: Option<String>,
+    pub verdict: Option<String>,
+    pub error: Option<String>, // of the last attempt
+}
+
+// every job since the daemon started, by id
+pub type Tasks = Arc<Mutex<HashMap<Uuid, Task>>>;
+
+#[derive(Debug)]
+struct Job {
+    id: Uuid,
+    path: PathBuf, // in the running directory while the job is queued or running
+    name: String,
+    priority: i64,
+    options: JobOptions,
+    queued_at: SystemTime,
+    attempts: u32,
+    not_before: Instant,
+}
+
+#[derive(Debug)]
+enum Outcome {
+    Done { result_dir: String, verdict: String },
+    // the sandbox failed, e.g. LXC could not create the container
+    InfrastructureFailure(String),
+    Failed(String),
+}
+
+// submits samples to a running daemon from other threads
+#[derive(Debug, Clone)]
+pub struct DaemonHandle {
+    running_dir: PathBuf,
+    pub tasks: Tasks,
+    pub default_options: JobOptions,
+    sender: Sender<Job>,
+}
+
+impl DaemonHandle {
+    pub fn submit(
+        &self,
+        name: &str,
+        data: &[u8],
+        priority: i64,
+        options: JobOptions,
+    ) -> io::Result<Uuid> {
+        let path = running_path(&self.running_dir, name);
+        fs::write(&path, data)?;
+
+        let job = new_job(&self.tasks, path, name.to_string(), priority, options);
+        let id = job.id;
+
+        println!("Queued {} ({}) with priority {}", name, id, priority);
+        self.sender.send(job).expect("Daemon is not running");
+
+        return Ok(id);
+    }
+}
+
+#[derive(Debug)]
+pub struct Daemon {
+    args: DaemonArguments,
+    default_options: JobOptions,
+    queue: Vec<Job>,
+    running: usize,
+    tasks: Tasks,
+    submissions: (Sender<Job>, Receiver<Job>),
+    // size and modification time of inbox files at the previous scan
+    inbox_files: HashMap<PathBuf, (u64, SystemTime)>,
+}
+
+impl Daemon {
+    pub fn new(args: DaemonArguments) -> Self {
+        let default_options = JobOptions {
+            timeout: args.timeout,
+            collectors: args.collectors.clone(),
+            strace: args.strace,
+            network: args.network,
+            force: args.force,
+        };
+
+        // created before the API can store submissions in it
+        for name in [
+            RUNNING_DIR_NAME,
+            DONE_DIR_NAME,
+            FAILED_DIR_NAME,
+            LOGS_DIR_NAME,
+        ] {
+            fs::create_dir_all(Path::new(&args.inbox).join(name))
+                .expect("Failed to create inbox directory");
+        }
+
+        return Self {
+            args,
+            default_options,
+            queue: vec![],
+            running: 0,
+            tasks: Arc::new(Mutex::new(HashMap::new())),
+            submissions: mpsc::channel(),
+            inbox_files: HashMap::new(),
+        };
+    }
+
+    fn dir(&self, name: &str) -> PathBuf {
+        return Path::new(&self.args.inbox).join(name);
+    }
+
+    pub fn handle(&self) -> DaemonHandle {
+        return DaemonHandle {
+            running_dir: self.dir(RUNNING_DIR_NAME),
+            tasks: Arc::clone(&self.tasks),
+            default_options: self.default_options.clone(),
+            sender: self.submissions.0.clone(),
+        };
+    }
+
+    pub fn run(&mut self) {
+        self.requeue_interrupted();
+
+        println!(
+            "Watching {} with {} sandboxes...",
+            self.args.inbox, self.args.concurrency
+        );
+
+        let (sender, receiver) = mpsc::channel();
+
+        loop {
+            self.scan_inbox();
+            while let Ok(job) = self.submissions.1.try_recv() {
+                self.queue.push(job);
+            }
+            self.start_jobs(&sender);
+            while let Ok((job, outcome)) = receiver.try_recv() {
+                self.running -= 1;
+                self.finish_job(job, outcome);
+            }
+
+            thread::sleep(Duration::from_secs(self.args.poll_interval));
+        }
+    }
+
+    // jobs that were running when the daemon stopped are started again, with the default options
+    fn requeue_interrupted(&mut self) {
+        let entries = fs::read_dir(self.dir(RUNNING_DIR_NAME)).expect("Failed to read inbox");
+
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let name = match name.split_once('-') {
+                Some((_, name)) => name.to_string(),
+                None => name,
+            };
+
+            println!("Requeued interrupted job {}", name);
+            let job = new_job(
+                &self.tasks,
+                entry.path(),
+                name,
+                0,
+                self.default_options.clone(),
+            );
+            self.queue.push(job);
+        }
+    }
+
+    // samples directly in the inbox have priority 0, those in inbox/<n>/ priority n
+    fn scan_inbox(&mut self) {
+        let inbox_files = inbox_files(Path::new(&self.args.inbox));
+        // a sample is complete once it is unchanged since the previous scan
+        let mut samples: Vec<(PathBuf, i64)> = inbox_files
+            .iter()
+            .filter(|(path, (_, state))| self.inbox_files.get(*path) == Some(state))
+            .map(|(path, (priority, _))| (path.clone(), *priority))
+            .collect();
+        samples.sort();
+        self.inbox_files = inbox_files
+            .into_iter()
+            .map(|(path, (_, state))| (path, state))
+            .collect();
+
+        for (path, priority) in samples {
+            self.inbox_files.remove(&path);
+
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            // moved out of the inbox so that it is not picked up again, even by another daemon
+            let running_path = running_path(&self.dir(RUNNING_DIR_NAME), &name);
+
+            if let Err(err) = fs::rename(&path, &running_path) {
+                println!("Failed to queue {}: {}", path.display(), err);
+                continue;
+            }
+
+            let job = new_job(
+                &self.tasks,
+                running_path,
+                name,
+                priority,
+                self.default_options.clone(),
+            );
+            println!(
+                "Queued {} ({}) with priority {}",
+                job.name, job.id, priority
+            );
+            self.queue.push(job);
+        }
+    }
+
+    fn start_jobs(&mut self, sender: &Sender<(Job, Outcome)>) {
+        while self.running < self.args.concurrency as usize {
+            let now = Instant::now();
+            // highest priority first, then in the order they were queued
+            let next = self
+                .queue
+                .iter()
+                .enumerate()
+                .filter(|(_, job)| job.not_before <= now)
+                .max_by(|(_, a), (_, b)| {
+                    a.priority
+                        .cmp(&b.priority)
+                        .then(b.queued_at.cmp(&a.queued_at))
+                })
+                .map(|(i, _)| i);
+
+            let job = match next {
+                Some(i) => self.queue.remove(i),
+                None => return,
+            };
+
+            match self.cached_outcome(&job) {
+                Ok(Some(outcome)) => {
+                    self.finish_job(job, outcome);
+                    continue;
+                }
+                Ok(None) => (),
+                Err(err) => {
+                    let err = format!("failed to look up previous runs: {}", err);
+                    self.finish_job(job, Outcome::Failed(err));
+                    continue;
+                }
+            }
+
+            println!("Starting {} (attempt {})", job.name, job.attempts + 1);
+            self.update_task(&job, TaskState::Running, |_| ());
+
+            let command = self.job_command(&job);
+            let sender = sender.clone();
+            self.running += 1;
+
+            thread::spawn(move || {
+                let outcome = command.run(&job);
+                sender
+                    .send((job, outcome))
+                    .expect("Failed to report job outcome");
+            });
+        }
+    }
+
+    // the sandbox's check for a previous run, done here so that the previous result is known
+    fn cached_outcome(&self, job: &Job) -> io::Result<Option<Outcome>> {
+        if job.options.force {
+            return Ok(None);
+        }
+
+        let target_sha256 = sandbox::sha256_file(&job.path)?;
+        let profile = sandbox::profile(
+            DISTRIBUTION,
+            RELEASE,
+            ARCH,
+            job.options.timeout,
+            &job.options.collectors,
+            job.options.strace,
+            job.options.network,
+            Path::new(&self.args.setup_sh_path),
+        )?;
+        let run = match cache::find_run(
+            &Path::new(RESULTS_DIR_PATH).join(INDEX_DATABASE_FILE_NAME),
+            &target_sha256,
+            &profile,
+        ) {
+            Some(run) => run,
+            None => return Ok(None),
+        };
+
+        println!("Reusing previous run {} for {}", run.run_id, job.name);
+
+        return Ok(Some(Outcome::Done {
+            result_dir: run.path,
+            verdict: run.verdict,
+        }));
+    }
+
+    fn finish_job(&mut self, mut job: Job, outcome: Outcome) {
+        match outcome {
+            Outcome::Done {
+                result_dir,
+                verdict,
+            } => {
+                println!("Finished {}: {} ({})", job.name, result_dir, verdict);
+                self.update_task(&job, TaskState::Done, |task| {
+                    task.result_dir = Some(result_dir);
+                    task.verdict = Some(verdict);
+                });
+                move_job(&job, &self.dir(DONE_DIR_NAME));
+            }
+            Outcome::InfrastructureFailure(err) if job.attempts < self.args.max_retries => {
+                let delay = retry_delay(self.args.retry_delay, job.attempts);
+                println!("{} failed: {}, retrying in {}s", job.name, err, delay);
+
+                job.attempts += 1;
+                job.not_before = Instant::now() + Duration::from_secs(delay);
+                self.update_task(&job, TaskState::Queued, |task| task.error = Some(err));
+                self.queue.push(job);
+            }
+            Outcome::InfrastructureFailure(err) | Outcome::Failed(err) => {
+                println!("{} failed: {}", job.name, err);
+                self.update_task(&job, TaskState::Failed, |task| task.error = Some(err));
+                move_job(&job, &self.dir(FAILED_DIR_NAME));
+            }
+        }
+    }
+
+    fn update_task(&self, job: &Job, state: TaskState, update: impl FnOnce(&mut Task)) {
+        let mut tasks = self.tasks.lock().unwrap();
+
+        if let Some(task) = tasks.get_mut(&job.id) {
+            task.state = state;
+            task.attempts = job.attempts;
+            update(task);
+        }
+    }
+
+    fn job_command(&self, job: &Job) -> JobCommand {
+        let run_id = Uuid::new_v4();
+
+        return JobCommand {
+            sandbox_path: env::current_exe().expect("Failed to locate the sandbox binary"),
+            run_id,
+            setup_sh_path: self.args.setup_sh_path.clone(),
+            // each job needs its own container and mount directory to run concurrently
+            container_name: format!("sandbox-{}", run_id.simple()),
+            mount_dir_path: Path::new(&self.args.mount_dir_path)
+                .join(run_id.to_string())
+                .to_string_lossy()
+                .to_string(),
+            timeout: job.options.timeout,
+            collectors: job
+                .options
+                .collectors
+                .iter()
+                .map(value_name)
+                .collect::<Vec<String>>()
+                .join(","),
+            strace: job.options.strace,
+            network: value_name(&job.options.network),
+            log_path: self.dir(LOGS_DIR_NAME).join(format!("{}.log", run_id)),
+        };
+    }
+}
+
+// everything a worker thread needs to run one attempt of a job
+#[derive(Debug)]
+struct JobCommand {
+    sandbox_path: PathBuf,
+    run_id: Uuid,
+    setup_sh_path: String,
+    container_name: String,
+    mount_dir_path: String,
+    timeout: u64,
+    collectors: String,
+    strace: bool,
+    network: String,
+    log_path: PathBuf,
+}
+
+impl JobCommand {
+    fn run(&self, job: &Job) -> Outcome {
+        let log = match File::create(&self.log_path) {
+            Ok(log) => log,
+            Err(err) => return Outcome::InfrastructureFailure(err.to_string()),
+        };
+        let stdio = || log.try_clone().map(Stdio::from);
+
+        // previous runs were already looked up by the daemon
+        let mut sandbox = Command::new(&self.sandbox_path);
+        sandbox.args([
+            "run",
+            "--setup-sh-path",
+            &self.setup_sh_path,
+            "--target-elf-path",
+            &job.path.to_string_lossy(),
+            "--mount-dir-path",
+            &self.mount_dir_path,
+            "--timeout",
+            &self.timeout.to_string(),
+            "--collectors",
+            &self.collectors,
+            "--network",
+            &self.network,
+            "--container-name",
+            &self.container_name,
+            "--run-id",
+            &self.run_id.to_string(),
+            "--force",
+        ]);
+        if self.strace {
+            sandbox.arg("--strace");
+        }
+
+        let status = match (stdio(), stdio()) {
+            (Ok(stdout), Ok(stderr)) => sandbox.stdout(stdout).stderr(stderr).status(),
+            (Err(err), _) | (_, Err(err)) => {
+                return Outcome::InfrastructureFailure(err.to_string())
+            }
+        };
+
+        match status {
+            Ok(status) if status.success() => (),
+            Ok(status) => {
+                return Outcome::InfrastructureFailure(format!("sandbox exited with {}", status))
+            }
+            Err(err) => return Outcome::InfrastructureFailure(err.to_string()),
+        }
+
+        let result_dir_path = format!("{}/{}", RESULTS_DIR_PATH, self.run_id);
+
+        // analyzed by the sandbox binary too, so that its output goes to the job's log
+        let mut analyzer = Command::new(&self.sandbox_path);
+        analyzer
+            .arg("analyze")
+            .args(analyze::analyze_args(&result_dir_path, None, None, true));
+
+        let status = match (stdio(), stdio()) {
+            (Ok(stdout), Ok(stderr)) => analyzer.stdout(stdout).stderr(stderr).status(),
+            (Err(err), _) | (_, Err(err)) => return Outcome::Failed(err.to_string()),
+        };
+
+        let status = match status {
+            Ok(status) => status,
+            Err(err) => return Outcome::Failed(format!("failed to run the analysis: {}", err)),
+        };
+
+        return match ANALYZER_VERDICTS
+            .iter()
+            .find(|(code, _)| status.code() == Some(*code))
+        {
+            Some((_, verdict)) => Outcome::Done {
+                result_dir: result_dir_path,
+                verdict: verdict.to_string(),
+            },
+            None => Outcome::Failed(format!("analyzer exited with {}", status)),
+        };
+    }
+}
+
+fn new_job(tasks: &Tasks, path: PathBuf, name: String, priority: i64, options: JobOptions) -> Job {
+    let id = Uuid::new_v4();
+
+    tasks.lock().unwrap().insert(
+        id,
+        Task {
+            name: name.clone(),
+            priority,
+            options: options.clone(),
+            state: TaskState::Queued,
+            attempts: 0,
+            result_dir: None,
+            verdict: None,
+            error: None,
+        },
+    );
+
+    return Job {
+        id,
+        path,
+        name,
+        priority,
+        options,
+        queued_at: SystemTime::now(),
+        attempts: 0,
+        not_before: Instant::now(),
+    };
+}
+
+// samples of the inbox with their priority, size and modification time
+fn inbox_files(inbox: &Path) -> HashMap<PathBuf, (i64, (u64, SystemTime))> {
+    let mut files = HashMap::new();
+    let mut add = |path: PathBuf, priority: i64| {
+        if path.extension().is_some_and(|e| e == PARTIAL_EXTENSION) {
+            return;
+        }
+
+        if let Ok(metadata) = fs::metadata(&path) {
+            if metadata.is_file() {
+                let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+                files.insert(path, (priority, (metadata.len(), modified)));
+            }
+        }
+    };
+
+    for entry in fs::read_dir(inbox).into_iter().flatten().flatten() {
+        let path = entry.path();
+
+        if !path.is_dir() {
+            add(path, 0);
+        } else if let Ok(priority) = entry.file_name().to_string_lossy().parse::<i64>() {
+            for sample in fs::read_dir(&path).into_iter().flatten().flatten() {
+                add(sample.path(), priority);
+            }
+        }
+    }
+
+    return files;
+}
+
+// doubled for each attempt, capped so that it neither overflows nor postpones the job for days
+fn retry_delay(first_delay: u64, attempts: u32) -> u64 {
+    let factor = 2u64.checked_pow(attempts).unwrap_or(u64::MAX);
+
+    return first_delay.saturating_mul(factor).min(MAX_RETRY_DELAY_SECS);
+}
+
+// unique even for samples with the same name
+fn running_path(running_dir: &Path, name: &str) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+
+    return running_dir.join(format!("{}-{}", nanos, name));
+}
+
+fn move_job(job: &Job, dir: &Path) {
+    let destination = dir.join(job.path.file_name().unwrap());
+
+    if let Err(err) = fs::rename(&job.path, &destination) {
+        println!("Failed to move {} to {}: {}", job.name, dir.display(), err);
+    }
+}
+
+// the command line spelling of an argument value
+pub fn value_name(value: &impl ValueEnum) -> String {
+    return value.to_possible_value().unwrap().get_name().to_string();
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::process;
+
+    use super::*;
+
+    // a daemon with its inbox in a temporary directory, it only runs when the test runs it
+    pub fn daemon(name: &str) -> Daemon {
+        let dir = env::temp_dir().join(format!("elf-sandbox-daemon-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(SETUP_SH_FILE_NAME), "apt update\n").unwrap();
+
+        let args = DaemonArguments {
+            inbox: dir.join("inbox").to_string_lossy().to_string(),
+            setup_sh_path: dir.join(SETUP_SH_FILE_NAME).to_string_lossy().to_string(),
+            mount_dir_path: dir.join("mnt").to_string_lossy().to_string(),
+            timeout: 60,
+            collectors: vec![CollectorKind::Sysmon],
+            strace: false,
+            network: NetworkMode::None,
+            force: false,
+            concurrency: 1,
+            max_retries: 3,
+            retry_delay: 60,
+            poll_interval: 5,
+        };
+
+        return Daemon::new(args);
+    }
+
+    fn queued(daemon: &Daemon) -> Vec<(String, i64)> {
+        let mut queued: Vec<(String, i64)> = daemon
+            .queue
+            .iter()
+            .map(|job| (job.name.clone(), job.priority))
+            .collect();
+        queued.sort();
+
+        return queued;
+    }
+
+    #[test]
+    fn queue_samples_once_they_are_complete() {
+        let mut daemon = daemon("inbox");
+        let inbox = PathBuf::from(&daemon.args.inbox);
+        fs::create_dir_all(inbox.join("10")).unwrap();
+        fs::write(inbox.join("a.bin"), "\x7fELF").unwrap();
+        fs::write(inbox.join("10").join("b.bin"), "\x7fELF").unwrap();
+        fs::write(inbox.join("c.bin.part"), "\x7fE").unwrap();
+
+        // only seen once, the copy may still be going on
+        daemon.scan_inbox();
+        assert!(daemon.queue.is_empty());
+
+        daemon.scan_inbox();
+        assert_eq!(
+            queued(&daemon),
+            vec![("a.bin".to_string(), 0), ("b.bin".to_string(), 10)]
+        );
+        assert!(!inbox.join("a.bin").exists());
+        assert!(inbox.join("c.bin.part").exists());
+
+        // renamed when the copy is done
+        fs::rename(inbox.join("c.bin.part"), inbox.join("c.bin")).unwrap();
+        daemon.scan_inbox();
+        assert_eq!(daemon.queue.len(), 2);
+        daemon.scan_inbox();
+        assert_eq!(daemon.queue.len(), 3);
+
+        fs::remove_dir_all(inbox.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn wait_for_growing_samples() {
+        let mut daemon = daemon("growing");
+        let inbox = PathBuf::from(&daemon.args.inbox);
+        fs::write(inbox.join("a.bin"), "\x7fELF").unwrap();
+
+        daemon.scan_inbox();
+        fs::write(inbox.join("a.bin"), "\x7fELF\x02\x01\x01").unwrap();
+        daemon.scan_inbox();
+        assert!(daemon.queue.is_empty());
+
+        daemon.scan_inbox();
+        assert_eq!(queued(&daemon), vec![("a.bin".to_string(), 0)]);
+
+        fs::remove_dir_all(inbox.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn look_up_previous_runs() {
+        let mut daemon = daemon("cached");
+        let inbox = PathBuf::from(&daemon.args.inbox);
+        let path = inbox.join(RUNNING_DIR_NAME).join("1-a.bin");
+        fs::write(&path, "\x7fELF").unwrap();
+
+        let job = new_job(
+            &daemon.tasks,
+            path.clone(),
+            "a.bin".to_string(),
+            0,
+            daemon.default_options.clone(),
+        );
+        assert!(daemon.cached_outcome(&job).unwrap().is_none());
+
+        // the sample is gone, the job fails instead of the daemon
+        fs::remove_file(&path).unwrap();
+        assert!(daemon.cached_outcome(&job).is_err());
+
+        daemon.default_options.force = true;
+        let job = new_job(
+            &daemon.tasks,
+            path,
+            "a.bin".to_string(),
+            0,
+            daemon.default_options.clone(),
+        );
+        assert!(daemon.cached_outcome(&job).unwrap().is_none());
+
+        fs::remove_dir_all(inbox.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn retry_delays() {
+        assert_eq!(retry_delay(60, 0), 60);
+        assert_eq!(retry_delay(60, 3), 480);
+        assert_eq!(retry_delay(60, 10), MAX_RETRY_DELAY_SECS);
+        assert_eq!(retry_delay(60, 64), MAX_RETRY_DELAY_SECS);
+        assert_eq!(retry_delay(u64::MAX, 1), MAX_RETRY_DELAY_SECS);
+        assert_eq!(retry_delay(0, u32::MAX), 0);
+    }
+}
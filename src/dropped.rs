@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use crate::follow;
+
+// written by the guest's logging and the collectors, not by the target
+const SKIPPED_PATHS: &[&str] = &["/proc", "/sys", "/var/log"];
+// larger files and the files beyond the limit are left out so that the target can not fill the host
+const MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+const MAX_FILES: usize = 10000;
+
+// what tells a changed file apart, the ctime can not be set back by the guest
+#[derive(Debug, Eq, PartialEq)]
+struct FileState {
+    ino: u64,
+    size: u64,
+    ctime: i64,
+    ctime_nsec: i64,
+}
+
+// regular files of the guest filesystem, read through the root of the container's init process
+#[derive(Debug)]
+pub struct Snapshot {
+    init_pid: u32,
+    root_dir: PathBuf,
+    skipped: Vec<PathBuf>,
+    files: HashMap<PathBuf, FileState>,
+}
+
+impl Snapshot {
+    // only root_dir is walked, skipped are paths below it that are left out with SKIPPED_PATHS
+    pub fn take(init_pid: u32, root_dir: &str, skipped: &[&str]) -> Self {
+        let root_dir = PathBuf::from(root_dir);
+        let mut snapshot = Self {
+            init_pid,
+            skipped: SKIPPED_PATHS
+                .iter()
+                .chain(skipped)
+                .map(|p| root_dir.join(p.trim_start_matches('/')))
+                .collect(),
+            root_dir,
+            files: HashMap::new(),
+        };
+        snapshot.files = snapshot.walk();
+
+        return snapshot;
+    }
+
+    // symlinks are not followed, only the directories that were listed are descended into
+    fn walk(&self) -> HashMap<PathBuf, FileState> {
+        let proc_root = PathBuf::from(format!("/proc/{}/root", self.init_pid));
+        let mut files = HashMap::new();
+        let mut dirs = vec![self.root_dir.clone()];
+
+        while let Some(dir) = dirs.pop() {
+            let entries = match fs::read_dir(proc_root.join(dir.strip_prefix("/").unwrap_or(&dir)))
+            {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            for entry in entries.flatten() {
+                let path = dir.join(entry.file_name());
+
+                if self.skipped.contains(&path) {
+                    continue;
+                }
+
+                let metadata = match entry.metadata() {
+                    Ok(metadata) => metadata,
+                    Err(_) => continue,
+                };
+
+                if metadata.is_dir() {
+                    dirs.push(path);
+                } else if metadata.is_file() {
+                    files.insert(
+                        path,
+                        FileState {
+                            ino: metadata.ino(),
+                            size: metadata.size(),
+                            ctime: metadata.ctime(),
+                            ctime_nsec: metadata.ctime_nsec(),
+                        },
+                    );
+                }
+            }
+        }
+
+        return files;
+    }
+
+    // copies the files created or modified since the snapshot into output_dir at their guest
+    // path, keeping their modification time, returns the number of copied files
+    pub fn copy_changed(&self, output_dir: &Path) -> io::Result<usize> {
+        let mut changed: Vec<_> = self
+            .walk()
+            .into_iter()
+            .filter(|(path, state)| self.files.get(path) != Some(state))
+            .map(|(path, _)| path)
+            .collect();
+        changed.sort();
+
+        if changed.len() > MAX_FILES {
+            println!(
+                "{} files were dropped, copying the first {}",
+                changed.len(),
+                MAX_FILES
+            );
+            changed.truncate(MAX_FILES);
+        }
+
+        let mut copied = 0;
+
+        for path in changed {
+            // the file may be removed or replaced with a symlink since it was listed
+            let mut file = match follow::open_in_root(self.init_pid, &path) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            let metadata = file.metadata()?;
+
+            if metadata.len() > MAX_FILE_SIZE {
+                println!(
+                    "Dropped file {} is larger than {} bytes, skipping",
+                    path.display(),
+                    MAX_FILE_SIZE
+                );
+                continue;
+            }
+
+            let output = output_dir.join(path.strip_prefix("/").unwrap_or(&path));
+            fs::create_dir_all(output.parent().unwrap_or(output_dir))?;
+            copy_file(&mut file, &output)?;
+            File::options()
+                .write(true)
+                .open(&output)?
+                .set_modified(metadata.modified()?)?;
+            copied += 1;
+        }
+
+        return Ok(copied);
+    }
+}
+
+// at most MAX_FILE_SIZE bytes, the guest may still be writing to the file
+fn copy_file(file: &mut File, output: &Path) -> io::Result<()> {
+    let mut data = vec![];
+    file.take(MAX_FILE_SIZE).read_to_end(&mut data)?;
+
+    return File::create(output)?.write_all(&data);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, os::unix::fs::symlink, process};
+
+    use super::*;
+
+    // a directory of the host, seen through the root of this process
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("elf-sandbox-dropped-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("guest/etc/cron.d")).unwrap();
+        fs::create_dir_all(dir.join("guest/var/log")).unwrap();
+        fs::write(
+            dir.join("guest/etc/passwd"),
+            "root:x:0:0:root:/root:/bin/bash\n",
+        )
+        .unwrap();
+        fs::write(dir.join("guest/etc/hostname"), "sandbox\n").unwrap();
+
+        return dir;
+    }
+
+    fn copied_files(dir: &Path) -> Vec<String> {
+        let mut files = vec![];
+        let mut dirs = vec![dir.to_path_buf()];
+
+        while let Some(d) = dirs.pop() {
+            for entry in fs::read_dir(d).unwrap().flatten() {
+                if entry.file_type().unwrap().is_dir() {
+                    dirs.push(entry.path());
+                } else {
+                    let path = entry.path();
+                    files.push(
+                        path.strip_prefix(dir)
+                            .unwrap()
+                            .to_string_lossy()
+                            .to_string(),
+                    );
+                }
+            }
+        }
+        files.sort();
+
+        return files;
+    }
+
+    #[test]
+    fn copy_created_and_modified_files() {
+        let dir = test_dir("changed");
+        let guest = dir.join("guest");
+        let guest_dir = guest.to_str().unwrap();
+        let snapshot = Snapshot::take(process::id(), guest_dir, &[]);
+
+        fs::write(
+            guest.join("etc/cron.d/update"),
+            "* * * * * root /tmp/.x/kworker\n",
+        )
+        .unwrap();
+        fs::write(guest.join("etc/hostname"), "infected\n").unwrap();
+        fs::write(guest.join("var/log/syslog"), "Aug 10 12:00:00 sandbox\n").unwrap();
+
+        let output = dir.join("dropped");
+        let copied = snapshot.copy_changed(&output).unwrap();
+        let guest_output = output.join(guest.strip_prefix("/").unwrap());
+
+        assert_eq!(copied, 2);
+        assert_eq!(
+            copied_files(&guest_output),
+            vec!["etc/cron.d/update", "etc/hostname"]
+        );
+        assert_eq!(
+            fs::read_to_string(guest_output.join("etc/hostname")).unwrap(),
+            "infected\n"
+        );
+        assert_eq!(
+            fs::metadata(guest_output.join("etc/cron.d/update"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            fs::metadata(guest.join("etc/cron.d/update"))
+                .unwrap()
+                .modified()
+                .unwrap()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skip_symlinks_and_skipped_paths() {
+        let dir = test_dir("symlinks");
+        let guest = dir.join("guest");
+        let guest_dir = guest.to_str().unwrap();
+        let mount = guest.join("mnt/sandtmp");
+        let snapshot = Snapshot::take(process::id(), guest_dir, &["/mnt/sandtmp"]);
+
+        symlink("/etc/shadow", guest.join("etc/cron.d/shadow")).unwrap();
+        fs::create_dir_all(&mount).unwrap();
+        fs::write(mount.join("syslog"), "Aug 10 12:00:00 sandbox\n").unwrap();
+
+        let output = dir.join("dropped");
+
+        assert_eq!(snapshot.copy_changed(&output).unwrap(), 0);
+        assert!(!output.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    ffi::CString,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    mem,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{MetadataExt, OpenOptionsExt},
+        io::{AsRawFd, FromRawFd},
+    },
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// opens a file of the container through the root of its init process, the guest may have replaced
+// the path with a symlink, it is resolved inside the container and can not point to a host file
+pub fn open_in_root(pid: u32, path: &Path) -> io::Result<File> {
+    let root = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECTORY)
+        .open(format!("/proc/{}/root", pid))?;
+    let path = CString::new(
+        path.strip_prefix("/")
+            .unwrap_or(path)
+            .as_os_str()
+            .as_bytes(),
+    )
+    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    let mut how: libc::open_how = unsafe { mem::zeroed() };
+    // a fifo would block the open
+    how.flags = (libc::O_RDONLY | libc::O_CLOEXEC | libc::O_NONBLOCK) as u64;
+    how.resolve = libc::RESOLVE_IN_ROOT | libc::RESOLVE_NO_MAGICLINKS;
+
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            root.as_raw_fd(),
+            path.as_ptr(),
+            &how,
+            mem::size_of::<libc::open_how>(),
+        )
+    };
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let file = unsafe { File::from_raw_fd(fd as i32) };
+
+    if !file.metadata()?.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Not a regular file",
+        ));
+    }
+
+    return Ok(file);
+}
+
+// copies a file of the container to stdout as it grows, like tail -F -n +1,
+// until the container's init process exits
+pub fn follow(pid: u32, path: &str) {
+    let proc_path = format!("/proc/{}", pid);
+    let mut stdout = io::stdout().lock();
+    let mut file: Option<File> = None;
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        // checked before reading so that data written before the exit is not lost
+        let running = Path::new(&proc_path).exists();
+
+        if let Some(file) = &mut file {
+            // truncated by the guest
+            let position = file.stream_position().expect("Failed to read guest file");
+
+            if file.metadata().is_ok_and(|m| m.len() < position) {
+                file.seek(SeekFrom::Start(0))
+                    .expect("Failed to read guest file");
+            }
+
+            loop {
+                let size = file.read(&mut buf).expect("Failed to read guest file");
+
+                if size == 0 {
+                    break;
+                }
+
+                stdout
+                    .write_all(&buf[..size])
+                    .expect("Failed to write guest file");
+            }
+            stdout.flush().expect("Failed to write guest file");
+        }
+
+        if !running {
+            return;
+        }
+
+        // the guest may rotate or recreate the file, the old one is read to its end first
+        if let Ok(reopened) = open_in_root(pid, Path::new(path)) {
+            let replaced = match &file {
+                Some(file) => !is_same_file(file, &reopened),
+                None => true,
+            };
+
+            if replaced {
+                file = Some(reopened);
+                continue;
+            }
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn is_same_file(a: &File, b: &File) -> bool {
+    return match (a.metadata(), b.metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    #[test]
+    fn open_regular_files_only() {
+        let dir = env::temp_dir().join(format!("elf-sandbox-follow-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("syslog"), "Aug 10 12:00:00 sandbox\n").unwrap();
+        let fifo = CString::new(dir.join("fifo").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+
+        let mut file = open_in_root(process::id(), &dir.join("syslog")).unwrap();
+        let mut text = String::new();
+        file.read_to_string(&mut text).unwrap();
+
+        assert_eq!(text, "Aug 10 12:00:00 sandbox\n");
+        assert_eq!(
+            open_in_root(process::id(), &dir).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        // opened without a writer, so a blocking open would hang the test
+        assert_eq!(
+            open_in_root(process::id(), &dir.join("fifo"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            open_in_root(process::id(), &dir.join("missing"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,36 +1,131 @@
+use std::thread;
+
+use analyzer::cli;
+use api::Api;
 use clap::Parser;
-use sandbox::Sandbox;
+use container::Container;
+use daemon::Daemon;
+use sandbox::{Sandbox, ARCH, DISTRIBUTION, RELEASE};
 use sudo::RunningAs;
 
-use crate::args::Arguments;
+use crate::args::{Arguments, Command, ReportArguments, RunArguments};
 
+mod analyze;
+mod api;
 mod args;
+mod cache;
+mod cleanup;
+mod collector;
 mod container;
+mod daemon;
+mod dropped;
+mod follow;
+mod memory;
 mod sandbox;
 
 fn main() {
+    let args = Arguments::parse();
+
+    match args.command {
+        Command::Run(run_args) => {
+            require_root();
+            run(run_args);
+        }
+        Command::Analyze(analyze_args) => analyze::exit_with(cli::analyze(&analyze_args)),
+        Command::Report(report_args) => report(report_args),
+        Command::Diff(diff_args) => analyze::exit_with(cli::diff(&diff_args)),
+        Command::Search(search_args) => analyze::exit_with(cli::search(&search_args)),
+        Command::Cleanup(cleanup_args) => {
+            require_root();
+            cleanup::cleanup(cleanup_args);
+        }
+        Command::PrepareBase { timeout } => {
+            require_root();
+            prepare_base(timeout);
+        }
+        Command::Daemon(daemon_args) => {
+            require_root();
+            Daemon::new(daemon_args).run();
+        }
+        Command::Serve(serve_args) => {
+            require_root();
+            let mut daemon = Daemon::new(serve_args.daemon);
+            let api = Api::new(
+                &serve_args.listen,
+                serve_args.unix_socket.as_deref(),
+                daemon.handle(),
+            );
+
+            thread::spawn(move || api.run());
+            daemon.run();
+        }
+        Command::Follow { pid, path } => follow::follow(pid, &path),
+    }
+}
+
+// containers are created with sudo lxc-*
+fn require_root() {
     match sudo::check() {
         RunningAs::Root => (),
         _ => panic!("You must be run as sudo"),
     }
+}
 
-    let args = Arguments::parse();
-
-    let container_name = "sandbox";
-    let distribution = "ubuntu";
-    let release = "jammy";
-    let arch = "amd64";
-
-    let mut analyzer = Sandbox::new(
-        container_name.to_string(),
-        distribution.to_string(),
-        release.to_string(),
-        arch.to_string(),
+fn run(args: RunArguments) {
+    let mut sandbox = Sandbox::new(
+        args.run_id,
+        args.container_name,
+        DISTRIBUTION.to_string(),
+        RELEASE.to_string(),
+        ARCH.to_string(),
         args.timeout,
         args.setup_sh_path,
         args.target_elf_path,
         args.mount_dir_path,
+        args.collectors,
+        args.strace,
+        args.network,
+    );
+
+    if !args.force {
+        if let Some(run) = sandbox.cached_run() {
+            println!(
+                "Found a previous run {} of this target with the same profile: {} ({}, score {})",
+                run.run_id, run.path, run.verdict, run.score
+            );
+            println!("Run with --force to detonate it again");
+
+            if args.analyze {
+                analyze::analyze_run(&run.path, None, None, false);
+            }
+            return;
+        }
+    }
+
+    sandbox.run_container();
+
+    if args.analyze {
+        analyze::analyze_run(&sandbox.result_dir_path(), None, None, true);
+    }
+}
+
+fn report(args: ReportArguments) {
+    let result_dir_path = analyze::resolve_run(&args.run);
+
+    analyze::analyze_run(&result_dir_path, args.html, args.json, false);
+}
+
+// lxc-create downloads the image once and keeps it in its cache for later containers
+fn prepare_base(timeout: u64) {
+    let mut container = Container::new(
+        "sandbox-base".to_string(),
+        DISTRIBUTION.to_string(),
+        RELEASE.to_string(),
+        ARCH.to_string(),
+        timeout,
+        String::new(),
     );
 
-    analyzer.run_container();
+    container.create();
+    container.destroy();
 }
@@ -0,0 +1,278 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use common::*;
+use sha2::{Digest, Sha256};
+
+// the rest of a process's regions is left out once this much has been dumped
+const MAX_DUMP_BYTES_PER_PROCESS: u64 = 64 * 1024 * 1024;
+const CHUNK_SIZE: usize = 1024 * 1024;
+// mappings provided by the kernel that are either unreadable or the same for every process
+const KERNEL_MAPPINGS: &[&str] = &["[vvar]", "[vsyscall]", "[vdso]"];
+
+#[derive(Debug)]
+struct Region {
+    start: u64,
+    end: u64,
+    permissions: String,
+}
+
+// writable or executable regions of /proc/<pid>/maps, e.g.
+// 7f1c2a000000-7f1c2a021000 rw-p 00000000 00:00 0
+fn dumpable_regions(pid: u32) -> io::Result<Vec<Region>> {
+    let maps = fs::read_to_string(format!("/proc/{}/maps", pid))?;
+
+    return Ok(maps
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (start, end) = fields.next()?.split_once('-')?;
+            let permissions = fields.next()?;
+            let path = fields.nth(3).unwrap_or_default();
+
+            if !permissions.starts_with('r')
+                || !(permissions.contains('w') || permissions.contains('x'))
+                || KERNEL_MAPPINGS.contains(&path)
+            {
+                return None;
+            }
+
+            Some(Region {
+                start: u64::from_str_radix(start, 16).ok()?,
+                end: u64::from_str_radix(end, 16).ok()?,
+                permissions: permissions.to_string(),
+            })
+        })
+        .collect());
+}
+
+// parent pid from /proc/<pid>/stat, the command name before it may contain spaces and parentheses
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    return stat
+        .rsplit_once(')')?
+        .1
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok();
+}
+
+// TracerPid of /proc/<pid>/status, 0 if the process is not traced
+fn tracer_pid(pid: u32) -> Option<u32> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    return status
+        .lines()
+        .find_map(|line| line.strip_prefix("TracerPid:"))?
+        .trim()
+        .parse()
+        .ok();
+}
+
+fn has_null_separated(path: String, value: &str) -> bool {
+    return fs::read(path).is_ok_and(|data| data.split(|b| *b == 0).any(|v| v == value.as_bytes()));
+}
+
+// host pids of the processes started by the target, found by the run id in their environment or
+// by descending from the launcher
+fn target_processes(init_pid: u32, run_id: &str) -> Vec<u32> {
+    let parents: HashMap<u32, u32> = fs::read_dir("/proc")
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|e| e.file_name().to_str()?.parse().ok())
+                .filter_map(|pid| Some((pid, parent_pid(pid)?)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let env = format!("{}={}", RUN_ID_ENV, run_id);
+    let launcher_marker = format!("{}={}", RUN_ID_MARKER, run_id);
+    let marked: HashSet<u32> = parents
+        .keys()
+        .copied()
+        .filter(|pid| has_null_separated(format!("/proc/{}/environ", pid), &env))
+        .collect();
+    let launchers: HashSet<u32> = parents
+        .keys()
+        .copied()
+        .filter(|pid| {
+            fs::read(format!("/proc/{}/cmdline", pid))
+                .is_ok_and(|c| String::from_utf8_lossy(&c).contains(&launcher_marker))
+        })
+        .collect();
+
+    let mut pids: Vec<u32> = parents
+        .keys()
+        .copied()
+        .filter(|pid| !launchers.contains(pid))
+        .filter(|pid| {
+            let mut ancestor = *pid;
+            let mut is_target = marked.contains(pid);
+
+            // only processes of the container, whose ancestors end at its init process
+            while let Some(parent) = parents.get(&ancestor) {
+                if *parent == init_pid {
+                    return is_target;
+                }
+
+                is_target |= marked.contains(parent) || launchers.contains(parent);
+                ancestor = *parent;
+            }
+
+            return false;
+        })
+        .collect();
+
+    // strace is started with the run id, it traces the target but is not part of it
+    let tracers: HashSet<u32> = pids
+        .iter()
+        .filter_map(|pid| tracer_pid(*pid))
+        .filter(|tracer| {
+            fs::read_link(format!("/proc/{}/exe", tracer))
+                .is_ok_and(|exe| exe.file_name().is_some_and(|name| name == "strace"))
+        })
+        .collect();
+    pids.retain(|pid| !tracers.contains(pid));
+    pids.sort();
+
+    return pids;
+}
+
+// copies up to limit bytes of the region, returning the number of bytes and their hash
+fn dump_region(
+    mem: &mut File,
+    region: &Region,
+    limit: u64,
+    output: &Path,
+) -> io::Result<(u64, String)> {
+    let mut file = File::create(output)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut dumped = 0;
+
+    mem.seek(SeekFrom::Start(region.start))?;
+
+    while dumped < limit {
+        let size = (limit - dumped).min(CHUNK_SIZE as u64) as usize;
+
+        // guard pages and regions unmapped since maps was read can not be read
+        match mem.read(&mut buffer[..size]) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => {
+                file.write_all(&buffer[..read])?;
+                hasher.update(&buffer[..read]);
+                dumped += read as u64;
+            }
+        }
+    }
+
+    return Ok((dumped, format!("{:x}", hasher.finalize())));
+}
+
+// dumps the memory of the target's processes that are still running into output_dir, with an
+// index of "pid image region permissions size sha256 file" lines, returns the number of regions
+pub fn dump_processes(init_pid: u32, run_id: &str, output_dir: &Path) -> io::Result<usize> {
+    fs::create_dir_all(output_dir)?;
+
+    let mut index = File::create(output_dir.join(MEMORY_INDEX_FILE_NAME))?;
+    writeln!(
+        index,
+        "# pid\timage\tregion\tpermissions\tsize\tsha256\tfile"
+    )?;
+
+    let mut dumped_regions = 0;
+
+    for pid in target_processes(init_pid, run_id) {
+        // the process may exit while it is being dumped
+        let regions = match dumpable_regions(pid) {
+            Ok(regions) => regions,
+            Err(_) => continue,
+        };
+        let mut mem = match File::open(format!("/proc/{}/mem", pid)) {
+            Ok(mem) => mem,
+            Err(_) => continue,
+        };
+        let image = fs::read_link(format!("/proc/{}/exe", pid))
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or("-".to_string());
+
+        let process_dir = output_dir.join(pid.to_string());
+        fs::create_dir_all(&process_dir)?;
+
+        let mut remaining = MAX_DUMP_BYTES_PER_PROCESS;
+
+        for region in regions {
+            if remaining == 0 {
+                println!("Memory dump of {} reached the size limit", pid);
+                break;
+            }
+
+            let name = format!("{:x}-{:x}.dump", region.start, region.end);
+            let limit = (region.end - region.start).min(remaining);
+            let (size, sha256) = dump_region(&mut mem, &region, limit, &process_dir.join(&name))?;
+
+            if size == 0 {
+                fs::remove_file(process_dir.join(&name))?;
+                continue;
+            }
+
+            remaining -= size;
+            dumped_regions += 1;
+
+            writeln!(
+                index,
+                "{}\t{}\t{:x}-{:x}\t{}\t{}\t{}\t{}/{}",
+                pid, image, region.start, region.end, region.permissions, size, sha256, pid, name
+            )?;
+        }
+    }
+
+    return Ok(dumped_regions);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn read_own_process() {
+        let pid = process::id();
+
+        assert_eq!(parent_pid(pid), Some(std::os::unix::process::parent_id()));
+        // cargo test does not run under a debugger
+        assert_eq!(tracer_pid(pid), Some(0));
+        assert!(dumpable_regions(pid)
+            .unwrap()
+            .iter()
+            .all(|r| r.permissions.starts_with('r') && r.start < r.end));
+    }
+
+    #[test]
+    fn dump_own_region() {
+        let pid = process::id();
+        let data = vec![0x41u8; 3 * CHUNK_SIZE / 2];
+        let start = data.as_ptr() as u64;
+        let region = Region {
+            start,
+            end: start + data.len() as u64,
+            permissions: "rw-p".to_string(),
+        };
+        let output = std::env::temp_dir().join(format!("elf-sandbox-memory-{}.dump", pid));
+        let mut mem = File::open(format!("/proc/{}/mem", pid)).unwrap();
+
+        let (size, sha256) = dump_region(&mut mem, &region, CHUNK_SIZE as u64, &output).unwrap();
+
+        assert_eq!(size, CHUNK_SIZE as u64);
+        assert_eq!(fs::read(&output).unwrap(), data[..CHUNK_SIZE]);
+        assert_eq!(sha256, format!("{:x}", Sha256::digest(&data[..CHUNK_SIZE])));
+
+        fs::remove_file(&output).unwrap();
+    }
+}
@@ -1,11 +1,27 @@
-use std::fs;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use analyzer::database::RunSummary;
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
-use crate::container::Container;
-use common::*;
+use crate::{
+    cache,
+    collector::{self, Collector, CollectorKind},
+    container::{Container, NetworkMode},
+    dropped::Snapshot,
+    memory,
+};
+use common::{manifest::Manifest, *};
 
-const SANDBOX_RESULT_DIR_PATH: &str = "./sandbox_results";
+// the guest every target is detonated in
+pub const DISTRIBUTION: &str = "ubuntu";
+pub const RELEASE: &str = "jammy";
+pub const ARCH: &str = "amd64";
 
 #[derive(Debug)]
 pub struct Sandbox {
@@ -14,10 +30,17 @@ pub struct Sandbox {
     setup_sh_path: String,
     target_elf_path: String,
     mount_dir_path: String,
+    collectors: Vec<Box<dyn Collector>>,
+    strace: bool,
+    network: NetworkMode,
+    target_sha256: String,
+    profile: String,
 }
 
 impl Sandbox {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        run_id: Option<Uuid>,
         container_name: String,
         distribution: String,
         release: String,
@@ -26,9 +49,26 @@ impl Sandbox {
         setup_sh_path: String,
         target_elf_path: String,
         mount_dir_path: String,
+        collectors: Vec<CollectorKind>,
+        strace: bool,
+        network: NetworkMode,
     ) -> Self {
+        let target_sha256 =
+            sha256_file(Path::new(&target_elf_path)).expect("Failed to read target elf file");
+        let profile = profile(
+            &distribution,
+            &release,
+            &arch,
+            timeout,
+            &collectors,
+            strace,
+            network,
+            Path::new(&setup_sh_path),
+        )
+        .expect("Failed to read setup sh file");
+
         return Self {
-            uuid: Uuid::new_v4(),
+            uuid: run_id.unwrap_or_else(Uuid::new_v4),
             container: Container::new(
                 container_name,
                 distribution,
@@ -40,9 +80,22 @@ impl Sandbox {
             setup_sh_path,
             target_elf_path,
             mount_dir_path,
+            collectors: collectors.into_iter().map(collector::new).collect(),
+            strace,
+            network,
+            target_sha256,
+            profile,
         };
     }
 
+    pub fn cached_run(&self) -> Option<RunSummary> {
+        return cache::find_run(
+            &Path::new(RESULTS_DIR_PATH).join(INDEX_DATABASE_FILE_NAME),
+            &self.target_sha256,
+            &self.profile,
+        );
+    }
+
     pub fn run_container(&mut self) {
         let mut mount_root_path = self.container.mount_root_path.clone();
 
@@ -51,6 +104,7 @@ impl Sandbox {
         }
 
         self.generate_mount_entries();
+        self.generate_result_dir();
 
         self.container.create();
         self.container.set_config(&format!(
@@ -58,11 +112,73 @@ impl Sandbox {
             self.mount_dir_path, mount_root_path
         ));
         self.container.start();
-        self.container.execute_target();
+
+        for collector in &self.collectors {
+            println!("Installing {:?} collector...", collector.kind());
+            collector.install(&mut self.container);
+        }
+
+        // logs are streamed to the host so that they survive the target wiping them in the guest
+        let result_dir_path = self.result_dir_path();
+        let streams: Vec<_> = self
+            .collectors
+            .iter()
+            .filter_map(|c| Some((c, c.start(&self.container, &result_dir_path)?)))
+            .collect();
+
+        self.container.prepare_target(self.strace, self.network);
+        // files of the guest before the target runs, the ones it creates or modifies are collected
+        let snapshot = self.snapshot_guest();
+
+        let syscall_log = self.strace.then(|| {
+            File::create(format!("{}/{}", result_dir_path, SYSCALL_LOG_FILE_NAME))
+                .expect("Failed to create syscall log file")
+        });
+
+        // written right before the launch, so that the setup and the snapshot are not in scope
+        let started_at = SystemTime::now();
+        let target_name = Path::new(&self.target_elf_path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        self.write_detonation_marker(&format!(
+            "{}={}\n{}={}\n{}={}\n{}={}\n{}={}\n",
+            RUN_ID_MARKER,
+            self.uuid,
+            STARTED_AT_MARKER,
+            unix_time(started_at),
+            TARGET_NAME_MARKER,
+            target_name,
+            TARGET_SHA256_MARKER,
+            self.target_sha256,
+            PROFILE_MARKER,
+            self.profile
+        ));
+        self.container
+            .execute_target(&self.uuid.to_string(), syscall_log);
+        let ended_at = SystemTime::now();
+
+        // packed targets only reveal their code in memory, which is gone once the container stops,
+        // frozen so that the target can not change it or its files while they are collected
+        let frozen = self.container.freeze();
+        self.dump_memory();
+        // files on a tmpfs of the guest are gone as well
+        if let Some(snapshot) = snapshot {
+            self.collect_dropped_files(&snapshot);
+        }
+        if frozen {
+            self.container.unfreeze();
+        }
+
         self.container.stop();
         self.container.destroy();
 
-        self.generate_sandbox_result();
+        for (collector, stream) in streams {
+            collector.stop(stream);
+        }
+
+        self.generate_sandbox_result(ended_at);
         self.remove_mount_entries();
     }
 
@@ -84,26 +200,252 @@ impl Sandbox {
         fs::remove_dir_all(&self.mount_dir_path).expect("Failed to remove mount direcotry");
     }
 
-    fn generate_sandbox_result(&self) {
-        let result_dir_path = &format!("{}/{}", SANDBOX_RESULT_DIR_PATH, self.uuid.to_string());
+    pub fn result_dir_path(&self) -> String {
+        return format!("{}/{}", RESULTS_DIR_PATH, self.uuid);
+    }
+
+    fn generate_result_dir(&self) {
+        let result_dir_path = &self.result_dir_path();
+
+        let samples_dir_path = Path::new(RESULTS_DIR_PATH).join(SAMPLES_DIR_NAME);
+        let sample_path = samples_dir_path.join(&self.target_sha256);
+        let target_path = format!(
+            "{}/{}/{}",
+            result_dir_path, TARGETS_DIR_NAME, TARGET_FILE_NAME
+        );
 
-        fs::create_dir_all(&format!("{}/targets", result_dir_path))
+        fs::create_dir_all(format!("{}/{}", result_dir_path, TARGETS_DIR_NAME))
             .expect("Failed to create result directory");
-        fs::copy(
-            &format!("{}/{}", self.mount_dir_path, SYSLOG_FILE_NAME),
-            &format!("{}/{}", result_dir_path, SYSLOG_FILE_NAME),
+        fs::create_dir_all(&samples_dir_path).expect("Failed to create samples directory");
+
+        // renamed into place, so that a job storing the same sample never links a partial copy
+        if !sample_path.exists() {
+            let temp_path = samples_dir_path.join(format!(".{}.{}", self.target_sha256, self.uuid));
+
+            fs::copy(&self.target_elf_path, &temp_path).expect("Failed to copy target elf file");
+            fs::rename(&temp_path, &sample_path).expect("Failed to store target elf file");
+        }
+
+        // every run of the same target shares one stored copy
+        if fs::hard_link(&sample_path, &target_path).is_err() {
+            fs::copy(&sample_path, &target_path).expect("Failed to copy target elf file");
+        }
+    }
+
+    fn dump_memory(&self) {
+        let init_pid = match self.container.init_pid() {
+            Some(pid) => pid,
+            None => {
+                println!("Container is not running, skipping memory dump");
+                return;
+            }
+        };
+
+        println!("Dumping memory of running target processes...");
+
+        // the container is still stopped and destroyed if the dump fails
+        match memory::dump_processes(
+            init_pid,
+            &self.uuid.to_string(),
+            &Path::new(&self.result_dir_path()).join(MEMORY_DIR_NAME),
+        ) {
+            Ok(regions) => println!("Dumped {} memory regions!", regions),
+            Err(err) => println!("Failed to dump process memory: {}", err),
+        }
+    }
+
+    fn snapshot_guest(&self) -> Option<Snapshot> {
+        let init_pid = match self.container.init_pid() {
+            Some(pid) => pid,
+            None => {
+                println!("Container is not running, skipping dropped files");
+                return None;
+            }
+        };
+
+        println!("Taking snapshot of guest filesystem...");
+
+        // the mount holds the copied target and syslog, not files of the target
+        return Some(Snapshot::take(
+            init_pid,
+            "/",
+            &[&self.container.mount_root_path],
+        ));
+    }
+
+    fn collect_dropped_files(&self, snapshot: &Snapshot) {
+        println!("Collecting dropped files...");
+
+        match snapshot.copy_changed(&Path::new(&self.result_dir_path()).join(DROPPED_DIR_NAME)) {
+            Ok(files) => println!("Collected {} dropped files!", files),
+            Err(err) => println!("Failed to collect dropped files: {}", err),
+        }
+    }
+
+    fn write_detonation_marker(&self, lines: &str) {
+        let mut marker = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!(
+                "{}/{}",
+                self.result_dir_path(),
+                DETONATION_FILE_NAME
+            ))
+            .expect("Failed to open detonation marker file");
+
+        marker
+            .write_all(lines.as_bytes())
+            .expect("Failed to write detonation marker file");
+    }
+
+    fn generate_sandbox_result(&self, ended_at: SystemTime) {
+        let result_dir_path = &self.result_dir_path();
+        let syslog_path = format!("{}/{}", result_dir_path, SYSLOG_FILE_NAME);
+
+        // fall back to the log copied at container stop if streaming did not work
+        let streamed = fs::metadata(&syslog_path).is_ok_and(|m| m.len() > 0);
+
+        if !streamed {
+            println!("Log streaming produced no output, using the copied syslog");
+            fs::copy(
+                format!("{}/{}", self.mount_dir_path, SYSLOG_FILE_NAME),
+                &syslog_path,
+            )
+            .expect("Failed to copy syslog file");
+        }
+
+        self.write_detonation_marker(&format!("{}={}\n", ENDED_AT_MARKER, unix_time(ended_at)));
+
+        // hashed on the host after everything is written, the analyzer verifies it before analysis
+        let manifest = Manifest::create(Path::new(result_dir_path), &self.uuid.to_string())
+            .expect("Failed to create manifest");
+        fs::write(
+            format!("{}/{}", result_dir_path, MANIFEST_FILE_NAME),
+            manifest.to_string(),
         )
-        .expect("Failed to copy syslog file");
-        fs::copy(
-            &format!("{}/{}", self.mount_dir_path, TARGET_FILE_NAME),
-            &format!("{}/targets/{}", result_dir_path, TARGET_FILE_NAME),
+        .expect("Failed to write manifest file");
+
+        println!("Generated sandbox result: {}", result_dir_path);
+    }
+}
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    return Ok(format!("{:x}", Sha256::digest(fs::read(path)?)));
+}
+
+// hash of everything that changes what a detonation can observe
+#[allow(clippy::too_many_arguments)]
+pub fn profile(
+    distribution: &str,
+    release: &str,
+    arch: &str,
+    timeout: u64,
+    collectors: &[CollectorKind],
+    strace: bool,
+    network: NetworkMode,
+    setup_sh_path: &Path,
+) -> io::Result<String> {
+    let setup_sh_sha256 = sha256_file(setup_sh_path)?;
+
+    return Ok(format!(
+        "{:x}",
+        Sha256::digest(format!(
+            "{}\n{}\n{}\n{}\n{:?}\n{}\n{:?}\n{}",
+            distribution, release, arch, timeout, collectors, strace, network, setup_sh_sha256
+        ))
+    ));
+}
+
+// seconds since the unix epoch with nanosecond precision
+fn unix_time(time: SystemTime) -> String {
+    let duration = time
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before the unix epoch");
+
+    return format!("{}.{:09}", duration.as_secs(), duration.subsec_nanos());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn profile_of_detonation_options() {
+        let setup_sh_path = env::temp_dir().join(format!("elf-sandbox-setup-{}.sh", process::id()));
+        fs::write(
+            &setup_sh_path,
+            "#!/bin/bash\napt-get install -y sysmonforlinux\n",
         )
-        .expect("Failed to copy target elf file");
+        .unwrap();
+        let profile = |collectors: &[CollectorKind], strace: bool, network: NetworkMode| {
+            return super::profile(
+                DISTRIBUTION,
+                RELEASE,
+                ARCH,
+                60,
+                collectors,
+                strace,
+                network,
+                &setup_sh_path,
+            )
+            .unwrap();
+        };
 
-        println!(
-            "Generated sandbox result: {}/{}",
-            SANDBOX_RESULT_DIR_PATH,
-            self.uuid.to_string()
+        let sysmon = profile(&[CollectorKind::Sysmon], false, NetworkMode::Nat);
+
+        assert_eq!(sysmon.len(), 64);
+        assert_eq!(
+            sysmon,
+            profile(&[CollectorKind::Sysmon], false, NetworkMode::Nat)
+        );
+        assert_ne!(
+            sysmon,
+            profile(&[CollectorKind::Auditd], false, NetworkMode::Nat)
+        );
+        assert_ne!(
+            sysmon,
+            profile(&[CollectorKind::Sysmon], true, NetworkMode::Nat)
         );
+        assert_ne!(
+            sysmon,
+            profile(&[CollectorKind::Sysmon], false, NetworkMode::None)
+        );
+
+        // a changed guest setup invalidates the previous runs
+        fs::write(&setup_sh_path, "#!/bin/bash\napt-get install -y auditd\n").unwrap();
+
+        assert_ne!(
+            sysmon,
+            profile(&[CollectorKind::Sysmon], false, NetworkMode::Nat)
+        );
+
+        fs::remove_file(&setup_sh_path).unwrap();
+
+        assert!(super::profile(
+            DISTRIBUTION,
+            RELEASE,
+            ARCH,
+            60,
+            &[CollectorKind::Sysmon],
+            false,
+            NetworkMode::Nat,
+            &setup_sh_path
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn hash_sample() {
+        let path = env::temp_dir().join(format!("elf-sandbox-sample-{}", process::id()));
+        fs::write(&path, "\x7fELF").unwrap();
+
+        assert_eq!(
+            sha256_file(&path).unwrap(),
+            "3bdbb4fe8397cd2b842430b39ccff01a8663c751945ef5e9a09e267fb8b1d359"
+        );
+
+        fs::remove_file(&path).unwrap();
     }
 }